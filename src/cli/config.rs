@@ -0,0 +1,97 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const CONFIG_FILE: &str = "config.toml";
+
+/// Defaults read from `config.toml` in the minau config directory
+/// (`~/.config/minau` on Linux). Command-line arguments take precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub volume: Option<u16>,
+    pub gui: Option<bool>,
+    pub output: Option<String>,
+    /// Output device name or index and cpal host
+    pub device: Option<String>,
+    pub host: Option<String>,
+    /// Resample mode: auto, always or never
+    pub resample: Option<String>,
+    /// Resampler quality: fast, linear, medium, high or best
+    pub resampler_quality: Option<String>,
+    /// Crossfade length in seconds, 0 to disable
+    pub crossfade: Option<f32>,
+    pub crossfade_curve: Option<String>,
+    /// ReplayGain mode: off, track, album or auto
+    pub replaygain: Option<String>,
+    /// ReplayGain preamp in dB
+    pub replaygain_preamp: Option<f32>,
+    /// Measure untagged files and normalize them
+    pub normalize: Option<bool>,
+    /// Loudness targeted by normalization, in LUFS
+    pub target_lufs: Option<f32>,
+    /// Parametric EQ bands as `FREQ:GAIN[:Q]`
+    pub eq: Option<Vec<String>>,
+    pub eq_preset: Option<String>,
+    /// Bass and treble shelf gains in dB
+    pub bass: Option<f32>,
+    pub treble: Option<f32>,
+    /// Stereo balance from -1.0 (left) to 1.0 (right)
+    pub balance: Option<f32>,
+    pub mono: Option<bool>,
+    /// Gain in dB applied before the equalizer
+    pub preamp: Option<f32>,
+    pub limiter: Option<bool>,
+    /// Reconnect attempts after a stream drops
+    pub retries: Option<u32>,
+    /// Seconds before the first reconnect attempt
+    pub retry_delay: Option<f32>,
+    /// HTTP headers as `NAME: VALUE`, sent with every request
+    pub headers: Vec<String>,
+    /// HTTP Basic credentials as `USER[:PASSWORD]`, or a Bearer token
+    pub user: Option<String>,
+    pub bearer_token: Option<String>,
+    /// Proxy URL, `""` to ignore `HTTP_PROXY`
+    pub proxy: Option<String>,
+    /// PEM file with additional trusted CA certificates
+    pub ca_file: Option<PathBuf>,
+    pub insecure: Option<bool>,
+    /// Connect and read timeouts in seconds
+    pub connect_timeout: Option<f32>,
+    pub read_timeout: Option<f32>,
+    /// Playlist file the queue is saved to from the player
+    pub save_playlist: Option<PathBuf>,
+    /// How saved playlists refer to local files: absolute or relative
+    pub save_paths: Option<String>,
+    /// Headers and credentials for single hosts, under `[hosts."name"]`
+    pub hosts: BTreeMap<String, HostConfig>,
+}
+
+/// Settings of a `[hosts."name"]` table.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HostConfig {
+    pub headers: Vec<String>,
+    pub user: Option<String>,
+    pub bearer_token: Option<String>,
+}
+
+/// Location of the config file, if the platform has a config directory.
+pub fn path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join(CONFIG_FILE))
+}
+
+/// Reads the config file. A missing file yields the defaults.
+pub fn load() -> Result<Config, String> {
+    let Some(path) = path() else {
+        return Ok(Config::default());
+    };
+
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+}
@@ -0,0 +1,40 @@
+use clap::Args;
+use crossterm::style::Stylize;
+use minau::Result;
+use minau::sink::{self, ConfigRange};
+
+#[derive(Args)]
+pub struct DevicesArgs {
+    /// Only list the devices of this host
+    #[arg(long)]
+    host: Option<String>,
+}
+
+/// Prints every output device with the configurations it supports.
+pub fn run(args: DevicesArgs) -> Result<()> {
+    for host in sink::list_devices(args.host.as_deref())? {
+        let default = if host.is_default { " (default)" } else { "" };
+        println!("{}{}", host.name.bold(), default);
+        if host.devices.is_empty() {
+            println!("  no output devices");
+        }
+
+        for device in host.devices {
+            let default = if device.is_default { " (default)" } else { "" };
+            println!("  {}: {}{}", device.index, device.name.cyan(), default);
+            for config in device.configs {
+                println!("      {}", describe(&config));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn describe(config: &ConfigRange) -> String {
+    let rate = if config.min_rate == config.max_rate {
+        format!("{} Hz", config.min_rate)
+    } else {
+        format!("{}-{} Hz", config.min_rate, config.max_rate)
+    };
+    format!("{} ch, {}, {}", config.channels, rate, config.format)
+}
@@ -1,19 +1,23 @@
-use crate::{display_info::string_info, err, player::metadata::MetaData};
+use crate::cli::display_info::string_info;
 use image::GenericImageView;
+use minau::{MetaData, err};
 use minifb::{Window, WindowOptions};
 use parking_lot::Mutex;
-use std::{process::exit, sync::Arc, thread, time::Duration};
+use std::{sync::Arc, thread, time::Duration};
 
 pub fn display(data: Vec<u8>, filename: &str, metadata: MetaData, close: Arc<Mutex<bool>>) {
-    let img = image::load_from_memory(&data).unwrap_or_else(|e| {
-        err!("Unsupported image type\n{}", e);
-        exit(1);
-    });
+    let img = match image::load_from_memory(&data) {
+        Ok(img) => img,
+        Err(e) => {
+            err!("Unsupported image type\n{}", e);
+            return;
+        }
+    };
 
     let (width, height) = img.dimensions();
     let (mut last_width, mut last_height) = (width as usize, height as usize);
 
-    let mut window = Window::new(
+    let mut window = match Window::new(
         &string_info(filename, &metadata),
         last_width,
         last_height,
@@ -21,8 +25,13 @@ pub fn display(data: Vec<u8>, filename: &str, metadata: MetaData, close: Arc<Mut
             resize: true,
             ..WindowOptions::default()
         },
-    )
-    .unwrap();
+    ) {
+        Ok(window) => window,
+        Err(e) => {
+            err!("Failed to open window: {}", e);
+            return;
+        }
+    };
 
     let mut buffer: Vec<u32> = img
         .to_rgb8()
@@ -1,4 +1,4 @@
-use crate::player::metadata::MetaData;
+use minau::MetaData;
 
 pub fn display_info(filename: &str, metadata: &MetaData) {
     println!("{}", string_info(filename, metadata));
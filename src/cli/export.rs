@@ -0,0 +1,106 @@
+use crate::cli::play_music;
+use clap::{Args, Subcommand};
+use crossterm::style::Stylize;
+use minau::playlist::{self, PathStyle, PlaylistEntry, PlaylistFormat};
+use minau::{MinauError, Player, err};
+use std::path::PathBuf;
+use url::Url;
+
+#[derive(Args)]
+pub struct PlaylistArgs {
+    #[command(subcommand)]
+    command: PlaylistCommand,
+}
+
+#[derive(Subcommand)]
+enum PlaylistCommand {
+    /// Write files and playlists into one playlist, with titles and lengths from their tags
+    Export(ExportArgs),
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// Files, playlists and URLs to write, in order
+    #[arg(required = true)]
+    files: Vec<String>,
+    /// Playlist file to write
+    #[arg(short, long, value_name = "PATH")]
+    output: PathBuf,
+    /// Playlist format: m3u, pls or xspf [default: from the extension of --output]
+    #[arg(short, long)]
+    format: Option<PlaylistFormat>,
+    /// Write local files as absolute or relative paths [default: absolute]
+    #[arg(long, value_name = "STYLE")]
+    paths: Option<PathStyle>,
+}
+
+/// Runs a `minau playlist` command. Returns the entries that failed.
+pub fn run(args: PlaylistArgs) -> Vec<(String, MinauError)> {
+    match args.command {
+        PlaylistCommand::Export(args) => export(args),
+    }
+}
+
+/// Expands the files into one list and writes it. Local files that cannot
+/// be read are left out; URLs are written without being fetched.
+fn export(args: ExportArgs) -> Vec<(String, MinauError)> {
+    let mut failed = Vec::new();
+    let mut entries = Vec::new();
+
+    for path in args.files {
+        let result = if is_url(&path) {
+            Ok(vec![PlaylistEntry::new(&path)])
+        } else if playlist::is_playlist_file(path.as_ref()) {
+            playlist::load(&path).map(|list| {
+                list.into_iter()
+                    .filter_map(|entry| match describe(entry) {
+                        Ok(entry) => Some(entry),
+                        Err((location, e)) => {
+                            err!("Skipping {}: {}", location, e);
+                            failed.push((location, e));
+                            None
+                        }
+                    })
+                    .collect()
+            })
+        } else {
+            describe(PlaylistEntry::new(&path))
+                .map(|entry| vec![entry])
+                .map_err(|(_, e)| e)
+        };
+
+        match result {
+            Ok(list) => entries.extend(list),
+            Err(e) => {
+                err!("Skipping {}: {}", path, e);
+                failed.push((path, e));
+            }
+        }
+    }
+
+    let paths = args.paths.unwrap_or_default();
+    match playlist::save(&args.output, &entries, args.format, paths) {
+        Ok(()) => println!(
+            "Wrote {} entries to {}",
+            entries.len(),
+            args.output.display().to_string().cyan()
+        ),
+        Err(e) => failed.push((args.output.display().to_string(), e)),
+    }
+    failed
+}
+
+/// Fills in the tags and length of a local file.
+fn describe(entry: PlaylistEntry) -> std::result::Result<PlaylistEntry, (String, MinauError)> {
+    if is_url(&entry.location) {
+        return Ok(entry);
+    }
+    match Player::new(&entry.location).and_then(|player| player.metadata()) {
+        Ok(metadata) => Ok(play_music::describe(entry, &metadata)),
+        Err(e) => Err((entry.location, e)),
+    }
+}
+
+fn is_url(path: &str) -> bool {
+    Url::parse(path).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
@@ -1,5 +1,3 @@
-use crate::display_info;
-use crate::player::metadata::MetaData;
 use crossterm::terminal;
 use crossterm::{
     cursor::{self, MoveToPreviousLine},
@@ -29,12 +27,9 @@ pub fn info<P: AsRef<str>>(msg: P) {
     println!("{}", msg.as_ref());
 }
 
-pub fn info_with_restore<P: AsRef<str>>(
-    msg: P,
-    filename: String,
-    path: String,
-    metadata: MetaData,
-) {
+/// Shows `msg` for a moment, then restores the status line to `restore`.
+pub fn info_with_restore<P: AsRef<str>>(msg: P, restore: &str) {
+    let restore = String::from(restore);
     info(msg);
 
     thread::spawn(move || {
@@ -51,43 +46,7 @@ pub fn info_with_restore<P: AsRef<str>>(
                 return;
             }
 
-            let text_width =
-                UnicodeWidthStr::width(display_info::string_info(&path, &metadata).as_str());
-            let (cols, _rows) = terminal::size().unwrap_or((80, 24));
-            let lines_needed = (text_width as u16).div_ceil(cols).max(1);
-
-            for _ in 0..lines_needed {
-                execute!(
-                    std::io::stdout(),
-                    MoveToPreviousLine(1),
-                    Clear(ClearType::CurrentLine),
-                )
-                .unwrap();
-            }
-            crate::display_info::display_info(&filename, &metadata);
-        });
-    });
-}
-
-pub fn info_with_restore_url<P: AsRef<str>>(msg: P, url: &str) {
-    let url = String::from(url);
-    info(msg);
-
-    thread::spawn(move || {
-        smol::block_on(async move {
-            let call_time = Instant::now();
-            {
-                *LAST_CALL.lock().await = Some(call_time);
-            }
-
-            Timer::after(Duration::from_millis(2400)).await;
-
-            let last = LAST_CALL.lock().await;
-            if last.is_some_and(|t| t != call_time) {
-                return;
-            }
-
-            let text_width = UnicodeWidthStr::width(url.as_str());
+            let text_width = UnicodeWidthStr::width(restore.as_str());
             let (cols, _rows) = terminal::size().unwrap_or((80, 24));
             let lines_needed = (text_width as u16).div_ceil(cols).max(1);
 
@@ -100,7 +59,7 @@ pub fn info_with_restore_url<P: AsRef<str>>(msg: P, url: &str) {
                 .unwrap();
             }
 
-            println!("{}", url);
+            println!("{}", restore);
         });
     });
 }
@@ -0,0 +1,257 @@
+use crate::cli::info::{info, info_with_restore};
+use crate::cli::queue::Tracklist;
+use crossterm::{
+    cursor::{Hide, Show},
+    event::{Event, KeyCode, KeyEventKind, poll, read},
+    execute,
+    style::Stylize,
+    terminal::{disable_raw_mode, enable_raw_mode},
+};
+use minau::dsp::DspSettings;
+use minau::{MinauError, PlaybackEngine, Result, err};
+use parking_lot::Mutex;
+use smol::Task;
+use std::{
+    io::{IsTerminal, stdout},
+    process::exit,
+    sync::Arc,
+    time::Duration,
+};
+
+pub fn init_terminal() -> Result<()> {
+    enable_raw_mode().map_err(MinauError::Terminal)?;
+    execute!(stdout(), Hide).map_err(MinauError::Terminal)?;
+    Ok(())
+}
+
+pub fn deinit() {
+    disable_raw_mode().unwrap_or_else(|e| {
+        err!("Failed to disable raw mode: {}", e);
+        err!("Please execute 'reset' command");
+        exit(1);
+    });
+
+    execute!(stdout(), Show).unwrap_or_else(|e| {
+        err!("Failed to initialize terminal: {}", e);
+        exit(1);
+    });
+}
+
+const VOLUME_STEP: f32 = 0.05;
+const POLL_INTERVAL_MS: u64 = 100;
+const SEEK_STEP_SECS: u64 = 5;
+const BALANCE_STEP: f32 = 0.1;
+
+/// Starts keyboard handling, unless stdout is not a terminal (for example when
+/// minau runs headless in CI).
+pub fn spawn_input(
+    engine: &Arc<Mutex<PlaybackEngine>>,
+    quit: &Arc<Mutex<bool>>,
+    tracklist: &Tracklist,
+    status: String,
+) -> Result<Option<Task<()>>> {
+    if !stdout().is_terminal() {
+        return Ok(None);
+    }
+
+    init_terminal()?;
+    Ok(Some(smol::spawn(get_input(
+        Arc::clone(engine),
+        Arc::clone(quit),
+        tracklist.clone(),
+        status,
+    ))))
+}
+
+/// Handles key presses for the playing track until it ends or is skipped.
+///
+/// `status` is the line shown under the progress output, restored after
+/// transient messages such as volume changes.
+async fn get_input(
+    engine: Arc<Mutex<PlaybackEngine>>,
+    quit: Arc<Mutex<bool>>,
+    tracklist: Tracklist,
+    status: String,
+) {
+    let status = status.as_str();
+    loop {
+        if *quit.lock() {
+            return;
+        }
+
+        if !poll(Duration::from_millis(POLL_INTERVAL_MS)).unwrap() {
+            continue;
+        }
+
+        let event = read().unwrap_or_else(|e| {
+            err!("Failed to read key: {}", e);
+            exit(1);
+        });
+
+        if let Event::Key(key) = event {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q') => {
+                    info("Exitting...");
+                    deinit();
+                    println!();
+                    exit(0);
+                }
+                KeyCode::Char('>') | KeyCode::Right => {
+                    info("Next track");
+                    engine.lock().skip();
+                    return;
+                }
+                KeyCode::Char(' ') => {
+                    let play = engine.lock();
+                    let msg = if play.is_paused() {
+                        play.resume();
+                        "|> Resumed"
+                    } else {
+                        play.pause();
+                        "|| Paused"
+                    };
+                    info_with_restore(msg, status);
+                }
+                KeyCode::Char('+') | KeyCode::Char('=') | KeyCode::Char('k') => {
+                    adjust_volume(&engine, VOLUME_STEP, status);
+                }
+                KeyCode::Char('-') | KeyCode::Char('_') | KeyCode::Char('j') => {
+                    adjust_volume(&engine, -VOLUME_STEP, status);
+                }
+                KeyCode::Char('l') => {
+                    seek_by(&engine, SEEK_STEP_SECS as i64, status);
+                }
+                KeyCode::Char('h') => {
+                    seek_by(&engine, -(SEEK_STEP_SECS as i64), status);
+                }
+                KeyCode::Char('e') => {
+                    toggle_dsp(&engine, "Equalizer", |dsp| &mut dsp.eq_enabled, status);
+                }
+                KeyCode::Char('m') => {
+                    toggle_dsp(&engine, "Mono", |dsp| &mut dsp.mono, status);
+                }
+                KeyCode::Char('x') => {
+                    toggle_dsp(&engine, "Limiter", |dsp| &mut dsp.limiter, status);
+                }
+                KeyCode::Char('d') => {
+                    toggle_dsp(&engine, "DSP bypass", |dsp| &mut dsp.bypass, status);
+                }
+                KeyCode::Char('[') => {
+                    adjust_balance(&engine, -BALANCE_STEP, status);
+                }
+                KeyCode::Char(']') => {
+                    adjust_balance(&engine, BALANCE_STEP, status);
+                }
+                KeyCode::Char('s') => {
+                    let msg = match tracklist.save() {
+                        Ok(path) => format!("Saved queue to {}", path.display().to_string().cyan()),
+                        Err(e) => e.to_string().red().to_string(),
+                    };
+                    info_with_restore(msg, status);
+                }
+                KeyCode::Char(c) => {
+                    info_with_restore(format!("Unknown key: {}", c.red()), status);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn seek_by(engine: &Arc<Mutex<PlaybackEngine>>, secs: i64, status: &str) {
+    let play = engine.lock();
+    let cur_pos = play.get_pos();
+    let step = Duration::from_secs(secs.unsigned_abs());
+    let (new_pos, direction) = if secs >= 0 {
+        (cur_pos + step, "forward")
+    } else {
+        (cur_pos.saturating_sub(step), "backward")
+    };
+
+    match play.seek(new_pos) {
+        Ok(_) => {
+            info_with_restore(
+                format!(
+                    "Seeked {} ({} -> {})",
+                    direction,
+                    humantime::format_duration(to_millis(cur_pos)),
+                    humantime::format_duration(to_millis(new_pos))
+                ),
+                status,
+            );
+        }
+        Err(e) => {
+            info_with_restore(e.to_string().red().to_string(), status);
+        }
+    }
+}
+
+/// Drops the sub-millisecond part so positions print as e.g. `1m 2s 345ms`.
+fn to_millis(pos: Duration) -> Duration {
+    Duration::from_millis(pos.as_millis() as u64)
+}
+
+fn toggle_dsp(
+    engine: &Arc<Mutex<PlaybackEngine>>,
+    name: &str,
+    field: impl Fn(&mut DspSettings) -> &mut bool,
+    status: &str,
+) {
+    let play = engine.lock();
+    let mut dsp = play.dsp();
+    let flag = field(&mut dsp);
+    *flag = !*flag;
+    let state = if *flag { "on".green() } else { "off".red() };
+    play.set_dsp(dsp);
+    info_with_restore(format!("{} {}", name, state), status);
+}
+
+fn adjust_balance(engine: &Arc<Mutex<PlaybackEngine>>, delta: f32, status: &str) {
+    let play = engine.lock();
+    let mut dsp = play.dsp();
+    // 0.1 刻みに丸めて誤差の蓄積を防ぐ
+    dsp.balance = ((dsp.balance + delta).clamp(-1.0, 1.0) * 10.0).round() / 10.0;
+    let balance = dsp.balance;
+    play.set_dsp(dsp);
+
+    let msg = if balance == 0.0 {
+        "Balance centered".to_string()
+    } else if balance < 0.0 {
+        format!(
+            "Balance {} left",
+            format!("{:.0}%", -balance * 100.0).cyan()
+        )
+    } else {
+        format!(
+            "Balance {} right",
+            format!("{:.0}%", balance * 100.0).cyan()
+        )
+    };
+    info_with_restore(msg, status);
+}
+
+fn adjust_volume(engine: &Arc<Mutex<PlaybackEngine>>, delta: f32, status: &str) {
+    let play = engine.lock();
+    let vol = play.get_volume();
+    let new_vol = (vol + delta).clamp(0.0, 1.0);
+
+    if new_vol == vol {
+        let msg = if delta > 0.0 {
+            "Already at maximum volume!".red().to_string()
+        } else {
+            "Already at minimum volume!".red().to_string()
+        };
+        info_with_restore(msg, status);
+    } else {
+        play.set_volume_mut(new_vol);
+        let percent = (new_vol * 100.0).round() as u16;
+        info_with_restore(
+            format!("Volume set to {}", percent.to_string().cyan()),
+            status,
+        );
+    }
+}
@@ -0,0 +1,30 @@
+pub mod config;
+pub mod devices;
+pub mod display_image;
+pub mod display_info;
+pub mod export;
+pub mod info;
+pub mod input;
+pub mod play_music;
+pub mod play_stream;
+pub mod queue;
+pub mod scan;
+
+use minau::EngineOptions;
+use minau::play_url::StreamOptions;
+use minau::playlist::PathStyle;
+use std::path::PathBuf;
+
+/// Playback settings collected from the command line.
+#[derive(Clone)]
+pub struct Settings {
+    pub volume: f32,
+    pub gui: bool,
+    /// Measure the loudness of files without ReplayGain tags
+    pub normalize: bool,
+    pub engine: EngineOptions,
+    pub stream: StreamOptions,
+    /// Playlist file the queue is saved to from the player
+    pub save_playlist: PathBuf,
+    pub save_paths: PathStyle,
+}
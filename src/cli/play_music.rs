@@ -0,0 +1,311 @@
+use crate::cli::display_info::string_info;
+use crate::cli::input::{deinit, spawn_input};
+use crate::cli::queue::Tracklist;
+use crate::cli::{Settings, display_image, display_info};
+use crossterm::cursor::MoveToPreviousLine;
+use crossterm::terminal::ClearType;
+use crossterm::terminal::{Clear, SetTitle};
+use crossterm::{execute, terminal};
+use humantime::format_duration;
+use indicatif::{ProgressBar, ProgressStyle};
+use minau::engine::TrackId;
+use minau::playlist::PlaylistEntry;
+use minau::replaygain::ReplayGain;
+use minau::{MetaData, MinauError, PlaybackEngine, Player, Result, Source, err, loudness};
+use parking_lot::Mutex;
+use std::env;
+use std::io::{Write, stdout};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::thread::sleep;
+use std::time::Duration;
+use unicode_width::UnicodeWidthStr;
+
+const TICK_INTERVAL_MS: u64 = 200;
+const UPDATE_INTERVAL_SECS: u64 = 1; // 1秒ごとに更新
+
+/// A local file or standard input that has been queued on the engine.
+pub struct FileTrack {
+    metadata: MetaData,
+    filename: String,
+    /// Length of the track, or of the part of the file it plays
+    duration: Duration,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl FileTrack {
+    /// `entry`, the playlist entry this track was opened from, completed
+    /// with its tags.
+    pub fn describe(&self, entry: PlaylistEntry) -> PlaylistEntry {
+        describe(entry, &self.metadata)
+    }
+}
+
+/// Completes `entry` with the tags and length of its file. What the
+/// playlist already says takes precedence, as it does when playing.
+pub fn describe(entry: PlaylistEntry, metadata: &MetaData) -> PlaylistEntry {
+    let length = clip_length(&entry, metadata.duration());
+    PlaylistEntry {
+        title: entry.title.or_else(|| metadata.title()),
+        artist: entry.artist.or_else(|| metadata.artist()),
+        album: entry.album.or_else(|| metadata.album()),
+        duration: entry
+            .duration
+            .or_else(|| Some(length).filter(|d| !d.is_zero())),
+        ..entry
+    }
+}
+
+/// Length of what `entry` plays of a file that is `whole` long.
+fn clip_length(entry: &PlaylistEntry, whole: Duration) -> Duration {
+    entry
+        .end
+        .unwrap_or(whole)
+        .saturating_sub(entry.start.unwrap_or_default())
+}
+
+/// Readies `player`, opened from the file at `path`, to play `entry`: only
+/// its part of the file if it is a track of a CUE sheet, and with the
+/// title and artist the playlist gives.
+pub fn prepare_file<P: AsRef<Path>>(
+    mut player: Player,
+    path: P,
+    entry: &PlaylistEntry,
+    settings: &Settings,
+) -> Result<(Player, FileTrack)> {
+    let metadata = player.metadata()?;
+    player.replay_gain = metadata.replay_gain;
+    if entry.start.is_some() || entry.end.is_some() {
+        player.set_clip(entry.start.unwrap_or_default(), entry.end);
+    }
+
+    // タグの無いファイルは別スレッドでラウドネスを測定する
+    if settings.normalize && player.replay_gain.is_empty() {
+        let mut analyzed = Player::new(&path)?;
+        analyzed.clip = player.clip;
+        let measured = Arc::new(OnceLock::new());
+        player.measured_gain = Some(Arc::clone(&measured));
+        let tagged = player.replay_gain;
+        let name = path.as_ref().display().to_string();
+        std::thread::spawn(move || match loudness::analyze(&analyzed) {
+            Ok(loudness) => {
+                let _ = measured.set(ReplayGain {
+                    track_gain: loudness.replay_gain().map(|gain| gain as f32),
+                    track_peak: Some(loudness.peak),
+                    ..tagged
+                });
+            }
+            Err(e) => err!("Failed to measure the loudness of {}: {}", name, e),
+        });
+    }
+
+    let filename = path
+        .as_ref()
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.as_ref().display().to_string());
+
+    let track = file_track(&player, metadata, filename, entry);
+    Ok((player, track))
+}
+
+/// Opens audio piped through standard input.
+pub fn open_stdin(entry: &PlaylistEntry) -> Result<(Player, FileTrack)> {
+    let player = Player::from_source(Source::Stdin)?;
+    let track = file_track(&player, MetaData::default(), "stdin".to_string(), entry);
+    Ok((player, track))
+}
+
+fn file_track(
+    player: &Player,
+    mut metadata: MetaData,
+    filename: String,
+    entry: &PlaylistEntry,
+) -> FileTrack {
+    if entry.title.is_some() {
+        metadata.set_title(entry.title.clone());
+    }
+    if entry.artist.is_some() {
+        metadata.set_artist(entry.artist.clone());
+    }
+    FileTrack {
+        duration: clip_length(entry, metadata.duration()),
+        metadata,
+        filename,
+        sample_rate: player.sample_rate(),
+        channels: player.channels(),
+    }
+}
+
+/// Shows the progress of track `id` until it has finished playing.
+pub fn show(
+    track: FileTrack,
+    engine: &Arc<Mutex<PlaybackEngine>>,
+    id: TrackId,
+    settings: &Settings,
+    tracklist: &Tracklist,
+) -> Result<()> {
+    let close_gui = Arc::new(Mutex::new(false));
+
+    set_terminal_title(&track.filename, &track.metadata);
+
+    let metadata = track.metadata.clone();
+    let filename = track.filename.clone();
+
+    let bind_clg = Arc::clone(&close_gui);
+    let bind_engine = Arc::clone(engine);
+    let tracklist = tracklist.clone();
+    let play_thread = std::thread::spawn(move || {
+        smol::block_on(async {
+            let result = really_play(track, &bind_engine, id, &tracklist).await;
+            let mut clg = bind_clg.lock();
+            *clg = true;
+            result
+        })
+    });
+
+    if settings.gui
+        && let Some(pic) = metadata.picture()
+    {
+        if env::var("WAYLAND_DISPLAY").is_ok() {
+            unsafe { env::remove_var("WAYLAND_DISPLAY") };
+        }
+        display_image::display(pic, &filename, metadata, close_gui);
+    }
+
+    let result = play_thread
+        .join()
+        .unwrap_or_else(|_| Err(MinauError::Decode("Playback thread panicked".into())));
+
+    reset_terminal_title();
+    result
+}
+
+fn set_terminal_title(filename: &str, metadata: &MetaData) {
+    execute!(stdout(), SetTitle(string_info(filename, metadata))).unwrap();
+}
+
+fn reset_terminal_title() {
+    let cwd = env::current_dir().unwrap().display().to_string();
+    execute!(stdout(), SetTitle(cwd)).unwrap();
+    print!("\x1b]2;\x07");
+    stdout().flush().unwrap();
+}
+
+async fn really_play(
+    track: FileTrack,
+    music_play: &Arc<Mutex<PlaybackEngine>>,
+    id: TrackId,
+    tracklist: &Tracklist,
+) -> Result<()> {
+    let FileTrack {
+        metadata,
+        filename,
+        duration,
+        sample_rate,
+        channels,
+    } = track;
+    let sample_rate_khz = sample_rate as f32 / 1000.0;
+
+    println!(
+        "{}kHz/{}ch | {}",
+        sample_rate_khz,
+        channels,
+        format_duration(Duration::from_secs(duration.as_secs()))
+    );
+    display_info::display_info(&filename, &metadata);
+
+    let key_state = Arc::new(Mutex::new(false));
+    let key_thread = spawn_input(
+        music_play,
+        &key_state,
+        tracklist,
+        string_info(&filename, &metadata),
+    )?;
+
+    let duration_secs = duration.as_secs();
+    let pb = create_progress_bar(duration_secs);
+
+    let mut last_update = std::time::Instant::now();
+    let mut last_pos = 0u64;
+
+    loop {
+        if key_thread.as_ref().is_some_and(|t| t.is_finished()) {
+            cleanup_and_exit(&pb, metadata, &filename);
+            return Ok(());
+        }
+
+        if music_play.lock().is_finished(id) {
+            *key_state.lock() = true;
+            cleanup_and_exit(&pb, metadata, &filename);
+            return Ok(());
+        }
+
+        sleep(Duration::from_millis(TICK_INTERVAL_MS));
+
+        // 一定間隔でのみプログレスバーを更新
+        if last_update.elapsed() >= Duration::from_secs(UPDATE_INTERVAL_SECS) {
+            let current_secs = music_play.lock().get_pos().as_secs();
+
+            // 位置が実際に変わった場合のみ更新
+            if current_secs != last_pos {
+                update_progress(&pb, current_secs, duration_secs);
+                last_pos = current_secs;
+            }
+
+            last_update = std::time::Instant::now();
+        }
+    }
+}
+
+fn create_progress_bar(duration: u64) -> ProgressBar {
+    let pb = ProgressBar::new(duration);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:40.yellow} {msg}")
+            .unwrap()
+            .progress_chars("# "),
+    );
+    pb.set_position(0);
+    pb.set_message(format!(
+        "{} / {}",
+        format_duration(Duration::from_secs(0)),
+        format_duration(Duration::from_secs(duration))
+    ));
+    pb
+}
+
+fn update_progress(pb: &ProgressBar, current: u64, total: u64) {
+    pb.set_position(current);
+    pb.set_message(format!(
+        "{} / {}",
+        format_duration(Duration::from_secs(current)),
+        format_duration(Duration::from_secs(total))
+    ));
+}
+
+fn cleanup_and_exit(pb: &ProgressBar, metadata: MetaData, path: &str) {
+    let text_width = UnicodeWidthStr::width(display_info::string_info(path, &metadata).as_str());
+    let (cols, _rows) = terminal::size().unwrap_or((80, 24));
+    let lines_needed = (text_width as u16).div_ceil(cols).max(1) - 1;
+
+    execute!(
+        std::io::stdout(),
+        MoveToPreviousLine(2),
+        Clear(crossterm::terminal::ClearType::FromCursorDown),
+    )
+    .unwrap();
+
+    for _ in 0..lines_needed {
+        execute!(
+            std::io::stdout(),
+            MoveToPreviousLine(1),
+            Clear(ClearType::FromCursorDown),
+        )
+        .unwrap();
+    }
+
+    pb.finish_and_clear();
+    deinit();
+}
@@ -0,0 +1,268 @@
+use crate::cli::input::{self, deinit};
+use crate::cli::queue::Tracklist;
+use crossterm::cursor::MoveToPreviousLine;
+use crossterm::terminal::{self, Clear, ClearType, SetTitle};
+use crossterm::{cursor, execute};
+use humantime::format_duration;
+use minau::engine::TrackId;
+use minau::http::IcyMetadata;
+use minau::http::StreamState;
+use minau::play_url::{self, DownloadProgress, StreamOptions, UrlTarget};
+use minau::playlist::PlaylistEntry;
+use minau::{PlaybackEngine, Player, Result};
+use parking_lot::Mutex;
+use std::env;
+use std::io::{self, Write, stdout};
+use std::sync::Arc;
+use std::time::Duration;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// A remote stream that has been queued on the engine.
+pub struct StreamTrack {
+    /// Title line, with the artist and album given by a playlist
+    title: String,
+    download: DownloadProgress,
+    sample_rate: u32,
+    channels: u16,
+    /// Known for seekable streams whose container reports a length, and
+    /// otherwise taken from the playlist
+    duration: Option<Duration>,
+    /// Genre given by a playlist, shown unless the station sends its own
+    genre: Option<String>,
+    /// Station details of internet radio streams
+    icy: Option<IcyMetadata>,
+}
+
+impl StreamTrack {
+    /// `entry`, the playlist entry this stream was opened from, completed
+    /// with what the stream told about itself.
+    pub fn describe(&self, entry: PlaylistEntry) -> PlaylistEntry {
+        PlaylistEntry {
+            title: entry
+                .title
+                .or_else(|| self.icy.as_ref().and_then(|icy| icy.get_name())),
+            duration: entry.duration.or(self.duration),
+            genre: entry
+                .genre
+                .or_else(|| self.icy.as_ref().and_then(|icy| icy.get_genre())),
+            ..entry
+        }
+    }
+}
+
+/// What a URL given to the player turned out to be.
+#[allow(clippy::large_enum_variant)]
+pub enum Remote {
+    Stream(Player, StreamTrack),
+    Playlist(Vec<PlaylistEntry>),
+}
+
+/// Opens the URL of `entry`. Details from the playlist it came from are
+/// shown in place of those the stream does not have.
+pub async fn open_url(entry: &PlaylistEntry, options: &StreamOptions) -> Result<Remote> {
+    let url = entry.location.as_str();
+    let p = match play_url::open_url(url, options).await? {
+        UrlTarget::Stream(p) => p,
+        UrlTarget::Playlist(entries) => return Ok(Remote::Playlist(entries)),
+    };
+
+    let icy = p.icy();
+    let name = entry
+        .title
+        .clone()
+        .or_else(|| icy.as_ref().and_then(|icy| icy.get_name()))
+        .unwrap_or_else(|| url.to_string());
+    let track = StreamTrack {
+        title: format!(
+            "{}{}{}",
+            entry
+                .album
+                .as_ref()
+                .map_or(String::new(), |album| format!("[{}] ", album)),
+            entry
+                .artist
+                .as_ref()
+                .map_or(String::new(), |artist| format!("{} - ", artist)),
+            name
+        ),
+        download: p.progress(),
+        sample_rate: p.sample_rate(),
+        channels: p.channels(),
+        // 長さの分からないストリームはプレイリストの長さで進み具合を出す
+        duration: p.duration().filter(|_| p.is_seekable()).or(entry.duration),
+        genre: entry.genre.clone(),
+        icy,
+    };
+    Ok(Remote::Stream(p.into_player(), track))
+}
+
+/// Shows the download progress of stream `id` until it has finished playing.
+pub async fn show(
+    track: StreamTrack,
+    engine: &Arc<Mutex<PlaybackEngine>>,
+    id: TrackId,
+    tracklist: &Tracklist,
+) -> Result<()> {
+    let StreamTrack {
+        title,
+        download,
+        sample_rate,
+        channels,
+        duration,
+        genre,
+        icy,
+    } = track;
+    let mut header = format!(
+        "{}kHz/{}ch | {}",
+        sample_rate as f32 / 1000.0,
+        channels,
+        duration.map_or_else(
+            || "Unknown".to_string(),
+            |d| format_duration(Duration::from_secs(d.as_secs())).to_string()
+        )
+    );
+    if let Some(bitrate) = icy.as_ref().and_then(|icy| icy.get_bitrate()) {
+        header.push_str(&format!(" | {}kbps", bitrate));
+    }
+    if let Some(genre) = icy.as_ref().and_then(|icy| icy.get_genre()).or(genre) {
+        header.push_str(&format!(" | {}", genre));
+    }
+    println!("{}", header);
+    let key_state = Arc::new(Mutex::new(false));
+
+    println!("{}", title);
+    let thread = input::spawn_input(engine, &key_state, tracklist, title.clone())?;
+
+    set_terminal_title(&title);
+
+    let mut first = false;
+    let mut stream_title = None;
+
+    loop {
+        smol::Timer::after(Duration::from_millis(200)).await;
+
+        if !first {
+            execute!(
+                stdout(),
+                cursor::MoveToColumn(0),
+                Clear(ClearType::CurrentLine)
+            )
+            .unwrap();
+        } else {
+            first = !first;
+        }
+
+        // 曲が変わったらターミナルのタイトルも更新する
+        let current = icy.as_ref().and_then(|icy| icy.get_stream_title());
+        if current != stream_title {
+            match &current {
+                Some(song) => set_terminal_title(&format!("{} - {}", song, title)),
+                None => set_terminal_title(&title),
+            }
+            stream_title = current;
+        }
+
+        let mut status = String::new();
+        match download.get_state() {
+            StreamState::Streaming => {}
+            StreamState::Buffering => status.push_str("buffering… | "),
+            StreamState::Reconnecting { attempt } => {
+                status.push_str(&format!("reconnecting… (attempt {}) | ", attempt))
+            }
+            StreamState::Failed => status.push_str("connection lost | "),
+        }
+        if let Some(song) = &stream_title {
+            status.push_str(&format!("♪ {} | ", song));
+        }
+        if let Some(duration) = duration {
+            let pos = engine.lock().get_pos();
+            status.push_str(&format!(
+                "{} / {} | ",
+                format_duration(Duration::from_secs(pos.as_secs())),
+                format_duration(Duration::from_secs(duration.as_secs()))
+            ));
+        }
+        if let Some(progress) = download.get_download_progress() {
+            status.push_str(&format!(
+                "{:.1}% ({:.2} / {:.2} MB)",
+                progress,
+                download.get_downloaded_mb(),
+                download.get_total_mb().unwrap(),
+            ));
+        } else {
+            status.push_str(&format!("({:.2} MB)", download.get_downloaded_mb()));
+        }
+        print!("{}", fit_to_terminal(&status));
+        io::stdout().flush().unwrap();
+
+        if thread.as_ref().is_some_and(|t| t.is_finished()) {
+            cleanup_and_exit(&title);
+            break;
+        }
+        if engine.lock().is_finished(id) {
+            *key_state.lock() = true;
+            cleanup_and_exit(&title);
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Cuts `line` so it does not wrap, since the status line is redrawn in place.
+fn fit_to_terminal(line: &str) -> String {
+    let (cols, _rows) = terminal::size().unwrap_or((80, 24));
+    let max = (cols as usize).saturating_sub(1);
+    if UnicodeWidthStr::width(line) <= max {
+        return line.to_string();
+    }
+
+    let mut fitted = String::new();
+    let mut width = 0;
+    for ch in line.chars() {
+        let ch_width = UnicodeWidthChar::width(ch).unwrap_or(0);
+        if width + ch_width + 1 > max {
+            break;
+        }
+        width += ch_width;
+        fitted.push(ch);
+    }
+    fitted.push('…');
+    fitted
+}
+
+fn set_terminal_title(title: &str) {
+    execute!(stdout(), SetTitle(title.to_string())).unwrap();
+}
+
+fn reset_terminal_title() {
+    let cwd = env::current_dir().unwrap().display().to_string();
+    execute!(stdout(), SetTitle(cwd)).unwrap();
+    print!("\x1b]2;\x07");
+    stdout().flush().unwrap();
+}
+
+fn cleanup_and_exit(title: &str) {
+    let text_width = UnicodeWidthStr::width(title);
+    let (cols, _rows) = terminal::size().unwrap_or((80, 24));
+    let lines_needed = (text_width as u16).div_ceil(cols).max(1) - 1;
+
+    execute!(
+        std::io::stdout(),
+        MoveToPreviousLine(2),
+        Clear(crossterm::terminal::ClearType::FromCursorDown),
+    )
+    .unwrap();
+
+    for _ in 0..lines_needed {
+        execute!(
+            std::io::stdout(),
+            MoveToPreviousLine(1),
+            Clear(ClearType::FromCursorDown),
+        )
+        .unwrap();
+    }
+
+    reset_terminal_title();
+    deinit();
+}
@@ -0,0 +1,416 @@
+use crate::cli::Settings;
+use crate::cli::play_music::{self, FileTrack};
+use crate::cli::play_stream::{self, Remote, StreamTrack};
+use async_compat::CompatExt;
+use minau::engine::TrackId;
+use minau::playlist::PlaylistEntry;
+use minau::playlist::{self, PathStyle};
+use minau::sink::ResampleMode;
+use minau::{MinauError, PlaybackEngine, Player, Result, err};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use url::Url;
+
+/// Remote playlists pointing at further playlists are followed this deep
+const MAX_PLAYLIST_DEPTH: usize = 4;
+/// Remote entries are opened this long before the track ahead of them
+/// ends, rather than holding a connection open for the whole track
+const REMOTE_PRELOAD: Duration = Duration::from_secs(10);
+
+/// A track opened for playback, with what the UI needs to show it.
+enum Opened {
+    File(FileTrack),
+    Stream(StreamTrack),
+}
+
+/// What opening an entry gave.
+#[allow(clippy::large_enum_variant)]
+enum Target {
+    Track(Player, Opened),
+    /// Entries of a remote playlist or of a CUE sheet embedded in a file,
+    /// to be played in its place
+    Playlist(Vec<PlaylistEntry>),
+}
+
+/// Where an opened track waits for its turn.
+#[allow(clippy::large_enum_variant)]
+enum Slot {
+    /// Queued on the engine after the track playing
+    Queued(TrackId),
+    /// To be played through a new output at its own rate once the engine
+    /// has played out, as `--resample never` does not resample it
+    Reopen(Player),
+}
+
+/// An entry after opening it and queueing its track on the engine.
+#[allow(clippy::large_enum_variant)]
+enum Queued {
+    Track {
+        slot: Slot,
+        opened: Opened,
+        duration: Option<Duration>,
+    },
+    Playlist(Vec<PlaylistEntry>),
+}
+
+/// The track being played, as handed from [`Queue::queue_next`] to the
+/// player.
+struct Current {
+    path: String,
+    opened: Opened,
+    slot: Slot,
+    duration: Option<Duration>,
+}
+
+/// Plays every entry through one shared engine.
+///
+/// The next entry is opened and queued while the current one is still
+/// playing, so the engine can continue into it without a gap. Remote
+/// entries are only opened in the last seconds of the current track. With
+/// `--resample never` a track at another rate starts a new engine instead.
+pub struct Queue {
+    settings: Settings,
+    engine: Option<Arc<Mutex<PlaybackEngine>>>,
+    failed: Vec<(String, MinauError)>,
+    tracklist: Tracklist,
+    /// Index in the tracklist of the next entry to open
+    cursor: usize,
+}
+
+impl Queue {
+    pub fn new(settings: Settings) -> Self {
+        let tracklist = Tracklist {
+            entries: Arc::default(),
+            path: settings.save_playlist.clone(),
+            paths: settings.save_paths,
+        };
+        Self {
+            settings,
+            engine: None,
+            failed: Vec::new(),
+            tracklist,
+            cursor: 0,
+        }
+    }
+
+    /// Plays `entries`, from the command line or expanded playlists, in
+    /// order and returns the ones that could not be played.
+    pub fn play_all(mut self, entries: Vec<PlaylistEntry>) -> Vec<(String, MinauError)> {
+        *self.tracklist.entries.lock() = entries.clone();
+        // プレイリストの入れ子の深さと一緒に持つ
+        let mut entries: VecDeque<(PlaylistEntry, usize)> =
+            entries.into_iter().map(|e| (e, 0)).collect();
+        let mut next = self.queue_next(&mut entries, None);
+
+        while let Some(current) = next.take() {
+            let id = match current.slot {
+                Slot::Queued(id) => id,
+                Slot::Reopen(player) => match self.reopen(player) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        self.fail(current.path, e);
+                        next = self.queue_next(&mut entries, None);
+                        continue;
+                    }
+                },
+            };
+            let Some(engine) = self.engine.clone() else {
+                break;
+            };
+
+            // 再生中に次の曲を開いてキューに入れておく。リモートの曲は
+            // 終わりが近づいてから別スレッドで開く
+            let preload = match entries.front() {
+                Some((entry, _)) if is_remote(&entry.location) => {
+                    Some(self.preload(entry.clone(), Arc::clone(&engine), id, current.duration))
+                }
+                _ => {
+                    next = self.queue_next(&mut entries, None);
+                    None
+                }
+            };
+
+            let result = match current.opened {
+                Opened::File(track) => {
+                    play_music::show(track, &engine, id, &self.settings, &self.tracklist)
+                }
+                Opened::Stream(track) => smol::block_on(async {
+                    play_stream::show(track, &engine, id, &self.tracklist).await
+                }),
+            };
+            if let Err(e) = result {
+                self.fail(current.path, e);
+            }
+
+            if let Some(preload) = preload {
+                let queued = preload
+                    .join()
+                    .unwrap_or_else(|_| Err(MinauError::Decode("Preloading panicked".into())));
+                next = self.queue_next(&mut entries, Some(queued));
+            }
+        }
+
+        self.failed
+    }
+
+    /// Opens the remote `entry` on another thread once track `id` is within
+    /// [`REMOTE_PRELOAD`] of its end or has finished, and queues its track.
+    fn preload(
+        &self,
+        entry: PlaylistEntry,
+        engine: Arc<Mutex<PlaybackEngine>>,
+        id: TrackId,
+        duration: Option<Duration>,
+    ) -> JoinHandle<Result<Queued>> {
+        let settings = self.settings.clone();
+        std::thread::spawn(move || {
+            loop {
+                let engine = engine.lock();
+                let near_end =
+                    duration.is_some_and(|d| d.saturating_sub(engine.get_pos()) <= REMOTE_PRELOAD);
+                if near_end || engine.is_finished(id) {
+                    break;
+                }
+                drop(engine);
+                std::thread::sleep(Duration::from_millis(200));
+            }
+            open(&entry, &settings).map(|target| match target {
+                Target::Track(player, opened) => {
+                    let duration = player.duration();
+                    let slot = queue_on(&engine.lock(), player, &settings);
+                    Queued::Track {
+                        slot,
+                        opened,
+                        duration,
+                    }
+                }
+                Target::Playlist(list) => Queued::Playlist(list),
+            })
+        })
+    }
+
+    /// Opens entries until one can be queued. Remote playlists are replaced
+    /// by their entries on the way. `preloaded` is what opening the first
+    /// entry gave, if that was done already.
+    ///
+    /// The tracklist is updated to match: `entries` are always the ones from
+    /// the cursor on.
+    fn queue_next(
+        &mut self,
+        entries: &mut VecDeque<(PlaylistEntry, usize)>,
+        mut preloaded: Option<Result<Queued>>,
+    ) -> Option<Current> {
+        while let Some((entry, depth)) = entries.pop_front() {
+            let queued = match preloaded.take() {
+                Some(queued) => queued,
+                None => self.open_and_enqueue(&entry),
+            };
+            let list = match queued {
+                Ok(Queued::Track {
+                    slot,
+                    opened,
+                    duration,
+                }) => {
+                    let path = entry.location.clone();
+                    let described = match &opened {
+                        Opened::File(track) => track.describe(entry),
+                        Opened::Stream(track) => track.describe(entry),
+                    };
+                    self.tracklist.replace(self.cursor, vec![described]);
+                    self.cursor += 1;
+                    return Some(Current {
+                        path,
+                        opened,
+                        slot,
+                        duration,
+                    });
+                }
+                Ok(Queued::Playlist(list)) => list,
+                Err(e) => {
+                    self.drop_entry(entry.location, e);
+                    continue;
+                }
+            };
+
+            if list.is_empty() {
+                let e = MinauError::Playlist("the playlist has no playable entries".into());
+                self.drop_entry(entry.location, e);
+            } else if depth >= MAX_PLAYLIST_DEPTH {
+                let e = MinauError::Playlist("playlists are nested too deeply".into());
+                self.drop_entry(entry.location, e);
+            } else {
+                self.tracklist.replace(self.cursor, list.clone());
+                for item in list.into_iter().rev() {
+                    entries.push_front((item, depth + 1));
+                }
+            }
+        }
+        None
+    }
+
+    fn open_and_enqueue(&mut self, entry: &PlaylistEntry) -> Result<Queued> {
+        match open(entry, &self.settings)? {
+            Target::Track(player, opened) => {
+                let duration = player.duration();
+                let slot = self.enqueue(player)?;
+                Ok(Queued::Track {
+                    slot,
+                    opened,
+                    duration,
+                })
+            }
+            Target::Playlist(list) => Ok(Queued::Playlist(list)),
+        }
+    }
+
+    /// Skips the entry at the cursor, which could not be opened.
+    fn drop_entry(&mut self, path: String, e: MinauError) {
+        self.tracklist.replace(self.cursor, Vec::new());
+        self.fail(path, e);
+    }
+
+    /// Queues `player`, starting the engine with its format on first use.
+    fn enqueue(&mut self, player: Player) -> Result<Slot> {
+        match &self.engine {
+            Some(engine) => Ok(queue_on(&engine.lock(), player, &self.settings)),
+            None => self.start(player).map(Slot::Queued),
+        }
+    }
+
+    /// Waits for the engine to play out what it has queued, then closes it
+    /// and starts a new one at the rate of `player`. The volume and effects
+    /// carry over.
+    fn reopen(&mut self, player: Player) -> Result<TrackId> {
+        if let Some(engine) = self.engine.take() {
+            // デバイスのバッファが鳴り終わってから閉じる
+            while engine.lock().current_track().is_some() {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            let engine = engine.lock();
+            self.settings.volume = engine.get_volume();
+            self.settings.engine.dsp = engine.dsp();
+        }
+        self.start(player)
+    }
+
+    /// Starts the engine with the format of `player` and queues it.
+    fn start(&mut self, player: Player) -> Result<TrackId> {
+        let engine = PlaybackEngine::new(
+            &self.settings.engine,
+            (player.sample_rate(), player.channels()),
+        )?
+        .set_volume(self.settings.volume);
+        let id = engine.enqueue(player);
+        self.engine = Some(Arc::new(Mutex::new(engine)));
+        Ok(id)
+    }
+
+    fn fail(&mut self, path: String, e: MinauError) {
+        err!("Skipping {}: {}", path, e);
+        self.failed.push((path, e));
+    }
+}
+
+/// The entries of a queue, with the details of those opened so far, for
+/// saving the queue as a playlist while it plays.
+#[derive(Clone)]
+pub struct Tracklist {
+    entries: Arc<Mutex<Vec<PlaylistEntry>>>,
+    path: PathBuf,
+    paths: PathStyle,
+}
+
+impl Tracklist {
+    /// Replaces the entry at `index` with `entries`.
+    fn replace(&self, index: usize, entries: Vec<PlaylistEntry>) {
+        let mut list = self.entries.lock();
+        if index < list.len() {
+            list.splice(index..=index, entries);
+        }
+    }
+
+    /// Writes the queue to the playlist file set in the settings and
+    /// returns its path.
+    pub fn save(&self) -> Result<&PathBuf> {
+        // 標準入力はプレイリストに書けない
+        let entries: Vec<PlaylistEntry> = self
+            .entries
+            .lock()
+            .iter()
+            .filter(|entry| entry.location != "-")
+            .cloned()
+            .collect();
+        playlist::save(&self.path, &entries, None, self.paths)?;
+        Ok(&self.path)
+    }
+}
+
+/// Queues `player` on `engine`, unless `--resample never` needs an output
+/// at its own rate for it.
+fn queue_on(engine: &PlaybackEngine, player: Player, settings: &Settings) -> Slot {
+    if settings.engine.resample == ResampleMode::Never
+        && settings.engine.output.can_reopen()
+        && player.sample_rate() != engine.sample_rate()
+    {
+        return Slot::Reopen(player);
+    }
+    Slot::Queued(engine.enqueue(player))
+}
+
+fn is_remote(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Opens a local file or URL, or standard input when the location is `-`.
+fn open(entry: &PlaylistEntry, settings: &Settings) -> Result<Target> {
+    let path = entry.location.as_str();
+    if path == "-" {
+        let (player, track) = play_music::open_stdin(entry)?;
+        return Ok(Target::Track(player, Opened::File(track)));
+    }
+
+    if (path.starts_with("file://") || is_remote(path))
+        && let Ok(url) = Url::parse(path)
+    {
+        // to_file_path はスキームを見ないので http://localhost/ もパスになってしまう
+        if url.scheme() == "file"
+            && let Ok(file_url) = url.to_file_path()
+        {
+            return open_local(&file_url, entry, settings);
+        }
+        let remote = smol::block_on(async {
+            play_stream::open_url(entry, &settings.stream)
+                .compat()
+                .await
+        })?;
+        return Ok(match remote {
+            Remote::Stream(player, track) => Target::Track(player, Opened::Stream(track)),
+            Remote::Playlist(entries) => Target::Playlist(entries),
+        });
+    }
+
+    open_local(Path::new(path), entry, settings)
+}
+
+/// Opens a local file, or the tracks of the CUE sheet embedded in it.
+fn open_local(path: &Path, entry: &PlaylistEntry, settings: &Settings) -> Result<Target> {
+    let player = Player::new(path)?;
+
+    // CUE シートの曲として開いたものはもう分けない
+    if entry.start.is_none()
+        && entry.end.is_none()
+        && let Some(cuesheet) = &player.cuesheet
+    {
+        let tracks = playlist::from_cuesheet(cuesheet, &entry.location);
+        if !tracks.is_empty() {
+            return Ok(Target::Playlist(tracks));
+        }
+    }
+
+    let (player, track) = play_music::prepare_file(player, path, entry, settings)?;
+    Ok(Target::Track(player, Opened::File(track)))
+}
@@ -0,0 +1,86 @@
+use clap::Args;
+use minau::loudness::{self, Loudness};
+use minau::replaygain::{self, ReplayGain};
+use minau::{MinauError, Player, Result, err};
+
+#[derive(Args)]
+pub struct ScanArgs {
+    /// Files to analyse
+    #[arg(required = true)]
+    files: Vec<String>,
+    /// Write the computed ReplayGain tags back to the files
+    #[arg(short, long)]
+    write: bool,
+    /// Treat the files as one album and also compute album gain
+    #[arg(short, long)]
+    album: bool,
+}
+
+/// Measures the loudness of every file and optionally tags them. Returns
+/// the files that failed.
+pub fn run(args: ScanArgs) -> Vec<(String, MinauError)> {
+    let mut failed = Vec::new();
+    let mut scanned: Vec<(String, Loudness)> = Vec::new();
+
+    for path in args.files {
+        match analyze(&path) {
+            Ok(loudness) => {
+                print_loudness(&path, loudness.integrated(), loudness.peak);
+                scanned.push((path, loudness));
+            }
+            Err(e) => {
+                err!("Skipping {}: {}", path, e);
+                failed.push((path, e));
+            }
+        }
+    }
+
+    let album = if args.album && !scanned.is_empty() {
+        let tracks: Vec<Loudness> = scanned.iter().map(|(_, l)| l.clone()).collect();
+        let integrated = loudness::album_loudness(&tracks);
+        let peak = tracks.iter().map(|l| l.peak).fold(0.0, f32::max);
+        print_loudness("Album", integrated, peak);
+        integrated.map(|lufs| (gain_from(lufs), peak))
+    } else {
+        None
+    };
+
+    if args.write {
+        for (path, loudness) in &scanned {
+            let gain = ReplayGain {
+                track_gain: loudness.integrated().map(gain_from),
+                track_peak: Some(loudness.peak),
+                album_gain: album.map(|(gain, _)| gain),
+                album_peak: album.map(|(_, peak)| peak),
+            };
+            if let Err(e) = replaygain::write_tags(path, &gain) {
+                err!("Failed to tag {}: {}", path, e);
+                failed.push((path.clone(), e));
+            }
+        }
+    }
+
+    failed
+}
+
+fn analyze(path: &str) -> Result<Loudness> {
+    let player = Player::new(path)?;
+    loudness::analyze(&player)
+}
+
+fn gain_from(lufs: f64) -> f32 {
+    (loudness::REPLAYGAIN_REFERENCE - lufs) as f32
+}
+
+fn print_loudness(name: &str, integrated: Option<f64>, peak: f32) {
+    match integrated {
+        Some(lufs) => println!(
+            "{}: {:.1} LUFS, gain {:+.2} dB, peak {:.6}",
+            name,
+            lufs,
+            gain_from(lufs),
+            peak
+        ),
+        None => println!("{}: silent, peak {:.6}", name, peak),
+    }
+}
@@ -0,0 +1,107 @@
+use std::f64::consts::PI;
+
+/// Second order IIR filter in direct form I.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// Builds a filter from coefficients already normalized by `a0`.
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            ..Default::default()
+        }
+    }
+
+    fn normalized(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self::new(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
+
+    /// Peaking EQ from the RBJ audio EQ cookbook.
+    pub fn peaking(rate: u32, freq: f32, gain_db: f32, q: f32) -> Self {
+        let (w0, a) = Self::params(rate, freq, gain_db);
+        let alpha = w0.sin() / (2.0 * q.max(0.01) as f64);
+        let cos = w0.cos();
+        Self::normalized(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    /// Low shelf with a slope of 1.
+    pub fn low_shelf(rate: u32, freq: f32, gain_db: f32) -> Self {
+        let (w0, a) = Self::params(rate, freq, gain_db);
+        let cos = w0.cos();
+        // 2 * sqrt(A) * alpha (S = 1)
+        let beta = a.sqrt() * w0.sin() * 2f64.sqrt();
+        Self::normalized(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + beta),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - beta),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + beta,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - beta,
+            ],
+        )
+    }
+
+    /// High shelf with a slope of 1.
+    pub fn high_shelf(rate: u32, freq: f32, gain_db: f32) -> Self {
+        let (w0, a) = Self::params(rate, freq, gain_db);
+        let cos = w0.cos();
+        // 2 * sqrt(A) * alpha (S = 1)
+        let beta = a.sqrt() * w0.sin() * 2f64.sqrt();
+        Self::normalized(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + beta),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - beta),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + beta,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - beta,
+            ],
+        )
+    }
+
+    /// Angular frequency and amplitude; the frequency is kept below Nyquist.
+    fn params(rate: u32, freq: f32, gain_db: f32) -> (f64, f64) {
+        let rate = rate.max(1) as f64;
+        let freq = (freq as f64).clamp(1.0, rate * 0.45);
+        (2.0 * PI * freq / rate, 10f64.powf(gain_db as f64 / 40.0))
+    }
+
+    /// Whether `other` filters the same way as this filter.
+    pub fn same_coefficients(&self, other: &Biquad) -> bool {
+        self.b == other.b && self.a == other.a
+    }
+
+    /// Continues from the input and output history of `other`, so
+    /// replacing a filter does not restart it from silence.
+    pub fn take_state(&mut self, other: &Biquad) {
+        self.x = other.x;
+        self.y = other.y;
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
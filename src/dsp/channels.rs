@@ -0,0 +1,293 @@
+//! Conversion between channel layouts.
+
+use symphonia::core::audio::Channels;
+
+/// -3 dB, used when one channel is spread over two or folded into another
+const FOLD: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Channel order used for devices that only report a channel count. This is
+/// the WAVE / ALSA order, which matches the bit order of [`Channels`].
+const DEVICE_ORDER: [Channels; 8] = [
+    Channels::FRONT_LEFT,
+    Channels::FRONT_RIGHT,
+    Channels::FRONT_CENTRE,
+    Channels::LFE1,
+    Channels::REAR_LEFT,
+    Channels::REAR_RIGHT,
+    Channels::SIDE_LEFT,
+    Channels::SIDE_RIGHT,
+];
+
+/// Returns the usual layout for `count` channels: mono, stereo, 3.0, quad,
+/// 5.0, 5.1, 6.1 or 7.1.
+pub fn default_layout(count: usize) -> Channels {
+    let positions: &[Channels] = match count {
+        0 => &[],
+        1 => &[Channels::FRONT_CENTRE],
+        2 => &DEVICE_ORDER[..2],
+        3 => &DEVICE_ORDER[..3],
+        4 => &[
+            Channels::FRONT_LEFT,
+            Channels::FRONT_RIGHT,
+            Channels::REAR_LEFT,
+            Channels::REAR_RIGHT,
+        ],
+        5 => &[
+            Channels::FRONT_LEFT,
+            Channels::FRONT_RIGHT,
+            Channels::FRONT_CENTRE,
+            Channels::REAR_LEFT,
+            Channels::REAR_RIGHT,
+        ],
+        6 => &DEVICE_ORDER[..6],
+        7 => &[
+            Channels::FRONT_LEFT,
+            Channels::FRONT_RIGHT,
+            Channels::FRONT_CENTRE,
+            Channels::LFE1,
+            Channels::REAR_CENTRE,
+            Channels::SIDE_LEFT,
+            Channels::SIDE_RIGHT,
+        ],
+        _ => &DEVICE_ORDER,
+    };
+    let mut layout = positions
+        .iter()
+        .fold(Channels::empty(), |layout, &ch| layout | ch);
+
+    // 8 チャンネルを超える分は残りのビットを順に割り当てる
+    let mut bit = 0;
+    while layout.count() < count && bit < 32 {
+        if let Some(ch) = Channels::from_bits(1 << bit) {
+            layout |= ch;
+        }
+        bit += 1;
+    }
+    layout
+}
+
+/// Mixing matrix from a source layout to an output layout.
+#[derive(Clone, Debug)]
+pub struct ChannelMap {
+    from: usize,
+    to: usize,
+    /// `to` rows of `from` gains
+    matrix: Vec<f32>,
+    identity: bool,
+}
+
+impl ChannelMap {
+    pub fn new(from: Channels, to: Channels) -> Self {
+        let sources: Vec<Channels> = from.iter().collect();
+        let targets: Vec<Channels> = to.iter().collect();
+        let mut matrix = vec![0.0f32; sources.len() * targets.len()];
+
+        for (col, &source) in sources.iter().enumerate() {
+            if sources.len() == 1 {
+                // モノラルはフロントの全チャンネルに複製する
+                for (row, &target) in targets.iter().enumerate() {
+                    if is_front(target, &targets) {
+                        matrix[row * sources.len() + col] = 1.0;
+                    }
+                }
+                continue;
+            }
+            for (target, gain) in route(source, to) {
+                if let Some(row) = targets.iter().position(|&t| t == target) {
+                    matrix[row * sources.len() + col] += gain;
+                }
+            }
+        }
+
+        // ダウンミックスでクリップしないように各出力の合計を 1 に抑える
+        for row in matrix.chunks_mut(sources.len().max(1)) {
+            let sum: f32 = row.iter().sum();
+            if sum > 1.0 {
+                row.iter_mut().for_each(|gain| *gain /= sum);
+            }
+        }
+
+        let identity = sources == targets;
+        Self {
+            from: sources.len(),
+            to: targets.len(),
+            matrix,
+            identity,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.identity
+    }
+
+    /// Converts interleaved samples.
+    pub fn apply(&self, samples: Vec<f32>) -> Vec<f32> {
+        if self.identity || self.from == 0 || self.to == 0 {
+            return samples;
+        }
+
+        let frames = samples.len() / self.from;
+        let mut out = Vec::with_capacity(frames * self.to);
+        for frame in samples.chunks_exact(self.from) {
+            for row in self.matrix.chunks_exact(self.from) {
+                out.push(row.iter().zip(frame).map(|(gain, s)| gain * s).sum());
+            }
+        }
+        out
+    }
+}
+
+/// Whether `target` is one of the front channels a mono source is copied to.
+fn is_front(target: Channels, targets: &[Channels]) -> bool {
+    let has_pair = targets.contains(&Channels::FRONT_LEFT);
+    if has_pair {
+        target == Channels::FRONT_LEFT || target == Channels::FRONT_RIGHT
+    } else {
+        target == Channels::FRONT_CENTRE || targets.len() == 1
+    }
+}
+
+/// Where a source channel goes in the output layout `to`, with its gains.
+fn route(source: Channels, to: Channels) -> Vec<(Channels, f32)> {
+    if to.contains(source) {
+        return vec![(source, 1.0)];
+    }
+
+    let left = Channels::FRONT_LEFT;
+    let right = Channels::FRONT_RIGHT;
+    let centre = Channels::FRONT_CENTRE;
+
+    let (side, gain) = match source {
+        // 重低音は ITU のダウンミックスに倣って捨てる
+        Channels::LFE1 | Channels::LFE2 => return Vec::new(),
+        Channels::FRONT_CENTRE | Channels::REAR_CENTRE | Channels::TOP_CENTRE => {
+            if to.contains(left | right) {
+                return vec![(left, FOLD), (right, FOLD)];
+            }
+            return vec![(centre, 1.0)];
+        }
+        Channels::REAR_LEFT => {
+            if to.contains(Channels::SIDE_LEFT) {
+                return vec![(Channels::SIDE_LEFT, 1.0)];
+            }
+            (Side::Left, FOLD)
+        }
+        Channels::REAR_RIGHT => {
+            if to.contains(Channels::SIDE_RIGHT) {
+                return vec![(Channels::SIDE_RIGHT, 1.0)];
+            }
+            (Side::Right, FOLD)
+        }
+        Channels::SIDE_LEFT => {
+            if to.contains(Channels::REAR_LEFT) {
+                return vec![(Channels::REAR_LEFT, 1.0)];
+            }
+            (Side::Left, FOLD)
+        }
+        Channels::SIDE_RIGHT => {
+            if to.contains(Channels::REAR_RIGHT) {
+                return vec![(Channels::REAR_RIGHT, 1.0)];
+            }
+            (Side::Right, FOLD)
+        }
+        Channels::FRONT_LEFT | Channels::FRONT_LEFT_CENTRE | Channels::FRONT_LEFT_WIDE => {
+            (Side::Left, 1.0)
+        }
+        Channels::FRONT_RIGHT | Channels::FRONT_RIGHT_CENTRE | Channels::FRONT_RIGHT_WIDE => {
+            (Side::Right, 1.0)
+        }
+        Channels::TOP_FRONT_LEFT | Channels::TOP_REAR_LEFT => (Side::Left, FOLD),
+        Channels::TOP_FRONT_RIGHT | Channels::TOP_REAR_RIGHT => (Side::Right, FOLD),
+        _ => (Side::Centre, FOLD),
+    };
+
+    let front = match side {
+        Side::Left => left,
+        Side::Right => right,
+        Side::Centre => centre,
+    };
+    if to.contains(front) {
+        vec![(front, gain)]
+    } else if to.contains(left | right) {
+        vec![(left, gain * FOLD), (right, gain * FOLD)]
+    } else {
+        // モノラル出力
+        vec![(centre, gain)]
+    }
+}
+
+enum Side {
+    Left,
+    Right,
+    Centre,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEREO: Channels = Channels::FRONT_LEFT.union(Channels::FRONT_RIGHT);
+
+    fn assert_gains(map: &ChannelMap, expected: &[f32]) {
+        assert_eq!(map.matrix.len(), expected.len());
+        for (i, (gain, want)) in map.matrix.iter().zip(expected).enumerate() {
+            assert!(
+                (gain - want).abs() < 1e-6,
+                "gain {}: {} != {}",
+                i,
+                gain,
+                want
+            );
+        }
+    }
+
+    #[test]
+    fn downmixes_5_1_to_stereo() {
+        let map = ChannelMap::new(default_layout(6), STEREO);
+        // FL + 0.707 C + 0.707 SL を合計 1 に正規化し、LFE は捨てる
+        let sum = 1.0 + 2.0 * FOLD;
+        let (front, fold) = (1.0 / sum, FOLD / sum);
+        #[rustfmt::skip]
+        assert_gains(&map, &[
+            // FL     FR     FC    LFE  RL    RR
+            front, 0.0,   fold, 0.0, fold, 0.0,
+            0.0,   front, fold, 0.0, 0.0,  fold,
+        ]);
+        assert!(!map.is_identity());
+
+        let out = map.apply(vec![
+            1.0, 0.0, 0.0, 1.0, 0.0, 0.0, // FL と LFE だけ
+            1.0, 1.0, 1.0, 1.0, 1.0, 1.0,
+        ]);
+        assert_eq!(out.len(), 4);
+        assert!((out[0] - front).abs() < 1e-6 && out[1].abs() < 1e-6);
+        // 全チャンネルが 1 でもクリップしない
+        assert!((out[2] - 1.0).abs() < 1e-6 && (out[3] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn copies_mono_to_both_front_channels() {
+        let map = ChannelMap::new(default_layout(1), STEREO);
+        assert_gains(&map, &[1.0, 1.0]);
+        assert_eq!(map.apply(vec![0.5, -0.25]), [0.5, 0.5, -0.25, -0.25]);
+
+        // 5.1 ではセンターではなく左右に出す
+        let map = ChannelMap::new(default_layout(1), default_layout(6));
+        assert_gains(&map, &[1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn averages_stereo_to_mono() {
+        let map = ChannelMap::new(STEREO, default_layout(1));
+        assert_gains(&map, &[0.5, 0.5]);
+        assert_eq!(map.apply(vec![1.0, 0.0, 0.5, 0.5]), [0.5, 0.5]);
+    }
+
+    #[test]
+    fn leaves_same_layout_alone() {
+        let map = ChannelMap::new(default_layout(6), default_layout(6));
+        assert!(map.is_identity());
+        let samples: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        assert_eq!(map.apply(samples.clone()), samples);
+    }
+}
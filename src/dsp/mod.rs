@@ -0,0 +1,436 @@
+//! Effects applied to the mixed output before it reaches the sink.
+
+pub mod biquad;
+pub mod channels;
+pub mod resample;
+
+use biquad::Biquad;
+use std::fmt;
+use std::str::FromStr;
+
+const BASS_FREQ: f32 = 100.0;
+const TREBLE_FREQ: f32 = 10_000.0;
+const DEFAULT_Q: f32 = 1.0;
+const LIMITER_THRESHOLD: f32 = 0.98;
+const LIMITER_RELEASE_SECS: f32 = 0.05;
+
+/// One band of the parametric equalizer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EqBand {
+    /// Centre frequency in Hz
+    pub freq: f32,
+    /// Gain in dB
+    pub gain: f32,
+    pub q: f32,
+}
+
+/// Built-in equalizer curves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EqPreset {
+    #[default]
+    Flat,
+    Rock,
+    Pop,
+    Jazz,
+    Classical,
+    Vocal,
+    BassBoost,
+    TrebleBoost,
+}
+
+/// User facing settings of the DSP chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DspSettings {
+    /// Skip every effect
+    pub bypass: bool,
+    /// Gain in dB applied before the other effects
+    pub preamp: f32,
+    /// Whether the equalizer bands and tone shelves are applied
+    pub eq_enabled: bool,
+    pub eq: Vec<EqBand>,
+    /// Low shelf gain in dB
+    pub bass: f32,
+    /// High shelf gain in dB
+    pub treble: f32,
+    /// -1.0 is fully left, 1.0 fully right
+    pub balance: f32,
+    /// Mix every channel down to mono
+    pub mono: bool,
+    /// Keep peaks below full scale
+    pub limiter: bool,
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        Self {
+            bypass: false,
+            preamp: 0.0,
+            eq_enabled: true,
+            eq: Vec::new(),
+            bass: 0.0,
+            treble: 0.0,
+            balance: 0.0,
+            mono: false,
+            limiter: false,
+        }
+    }
+}
+
+impl DspSettings {
+    /// Returns true if processing would leave the signal unchanged.
+    pub fn is_neutral(&self) -> bool {
+        self.bypass
+            || (self.preamp == 0.0
+                && (!self.eq_enabled
+                    || (self.eq.iter().all(|band| band.gain == 0.0)
+                        && self.bass == 0.0
+                        && self.treble == 0.0))
+                && self.balance == 0.0
+                && !self.mono
+                && !self.limiter)
+    }
+}
+
+/// The DSP chain with its filter state, running at the sink rate.
+pub struct DspChain {
+    settings: DspSettings,
+    rate: u32,
+    channels: usize,
+    preamp: f32,
+    /// Filters per channel, applied in order
+    filters: Vec<Vec<Biquad>>,
+    limiter_gain: f32,
+    limiter_release: f32,
+}
+
+impl DspChain {
+    pub fn new(settings: DspSettings, rate: u32, channels: u16) -> Self {
+        let mut chain = Self {
+            settings: DspSettings::default(),
+            rate,
+            channels: channels.max(1) as usize,
+            preamp: 1.0,
+            filters: Vec::new(),
+            limiter_gain: 1.0,
+            limiter_release: 1.0 - (-1.0 / (LIMITER_RELEASE_SECS * rate.max(1) as f32)).exp(),
+        };
+        chain.configure(settings);
+        chain
+    }
+
+    pub fn settings(&self) -> &DspSettings {
+        &self.settings
+    }
+
+    /// Applies new settings, rebuilding the filters. Filters that are kept
+    /// continue where they were instead of starting over.
+    pub fn configure(&mut self, settings: DspSettings) {
+        let mut filters = Vec::new();
+        if settings.eq_enabled {
+            filters.extend(
+                settings
+                    .eq
+                    .iter()
+                    .filter(|band| band.gain != 0.0)
+                    .map(|band| Biquad::peaking(self.rate, band.freq, band.gain, band.q)),
+            );
+            if settings.bass != 0.0 {
+                filters.push(Biquad::low_shelf(self.rate, BASS_FREQ, settings.bass));
+            }
+            if settings.treble != 0.0 {
+                filters.push(Biquad::high_shelf(self.rate, TREBLE_FREQ, settings.treble));
+            }
+        }
+
+        let previous = std::mem::replace(&mut self.filters, vec![filters; self.channels]);
+        keep_state(&mut self.filters, &previous);
+        self.preamp = 10f32.powf(settings.preamp / 20.0);
+        self.settings = settings;
+    }
+
+    /// Takes over the filter and limiter state of `previous`, the chain
+    /// this one replaces.
+    pub fn continue_from(&mut self, previous: &DspChain) {
+        keep_state(&mut self.filters, &previous.filters);
+        self.limiter_gain = previous.limiter_gain;
+    }
+
+    /// Processes interleaved samples in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if self.settings.is_neutral() {
+            return;
+        }
+
+        let channels = self.channels;
+        let (left, right) = balance_gains(self.settings.balance);
+
+        for frame in samples.chunks_exact_mut(channels) {
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let mut x = (*sample * self.preamp) as f64;
+                for filter in self.filters[ch].iter_mut() {
+                    x = filter.process(x);
+                }
+                *sample = x as f32;
+            }
+
+            if self.settings.mono && channels > 1 {
+                let mixed = frame.iter().sum::<f32>() / channels as f32;
+                frame.fill(mixed);
+            }
+
+            if channels >= 2 {
+                frame[0] *= left;
+                frame[1] *= right;
+            }
+
+            if self.settings.limiter {
+                // アタックは即時、リリースはなめらかに戻す
+                let peak = frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+                if peak * self.limiter_gain > LIMITER_THRESHOLD {
+                    self.limiter_gain = LIMITER_THRESHOLD / peak;
+                } else {
+                    self.limiter_gain += (1.0 - self.limiter_gain) * self.limiter_release;
+                }
+                for sample in frame.iter_mut() {
+                    *sample *= self.limiter_gain;
+                }
+            }
+        }
+    }
+}
+
+/// Carries filter state over from `previous`. With the same filters in
+/// each channel every filter keeps its history, which only the changed
+/// ones have to settle from; otherwise filters with unchanged coefficients
+/// keep theirs and the others start from silence.
+fn keep_state(filters: &mut [Vec<Biquad>], previous: &[Vec<Biquad>]) {
+    for (channel, old) in filters.iter_mut().zip(previous) {
+        if channel.len() == old.len() {
+            for (filter, old) in channel.iter_mut().zip(old) {
+                filter.take_state(old);
+            }
+            continue;
+        }
+
+        // 帯域の追加や削除では係数の同じフィルタを順に対応させる
+        let mut from = 0;
+        for filter in channel.iter_mut() {
+            if let Some(i) = old[from..].iter().position(|o| o.same_coefficients(filter)) {
+                filter.take_state(&old[from + i]);
+                from += i + 1;
+            }
+        }
+    }
+}
+
+fn balance_gains(balance: f32) -> (f32, f32) {
+    let balance = balance.clamp(-1.0, 1.0);
+    ((1.0 - balance).min(1.0), (1.0 + balance).min(1.0))
+}
+
+impl EqPreset {
+    pub const ALL: [EqPreset; 8] = [
+        EqPreset::Flat,
+        EqPreset::Rock,
+        EqPreset::Pop,
+        EqPreset::Jazz,
+        EqPreset::Classical,
+        EqPreset::Vocal,
+        EqPreset::BassBoost,
+        EqPreset::TrebleBoost,
+    ];
+
+    pub fn bands(self) -> Vec<EqBand> {
+        let gains: &[(f32, f32)] = match self {
+            EqPreset::Flat => &[],
+            EqPreset::Rock => &[
+                (60.0, 4.0),
+                (250.0, -2.0),
+                (1000.0, -1.0),
+                (4000.0, 2.0),
+                (12000.0, 4.0),
+            ],
+            EqPreset::Pop => &[
+                (60.0, -1.0),
+                (250.0, 2.0),
+                (1000.0, 3.0),
+                (4000.0, 1.0),
+                (12000.0, -1.0),
+            ],
+            EqPreset::Jazz => &[
+                (60.0, 3.0),
+                (250.0, 1.0),
+                (1000.0, -1.0),
+                (4000.0, 1.0),
+                (12000.0, 3.0),
+            ],
+            EqPreset::Classical => &[(60.0, 3.0), (4000.0, 1.0), (12000.0, 3.0)],
+            EqPreset::Vocal => &[(250.0, -2.0), (1000.0, 3.0), (3000.0, 3.0), (8000.0, 1.0)],
+            EqPreset::BassBoost => &[(60.0, 6.0), (150.0, 3.0)],
+            EqPreset::TrebleBoost => &[(8000.0, 3.0), (14000.0, 6.0)],
+        };
+        gains
+            .iter()
+            .map(|&(freq, gain)| EqBand {
+                freq,
+                gain,
+                q: DEFAULT_Q,
+            })
+            .collect()
+    }
+}
+
+impl FromStr for EqPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EqPreset::ALL
+            .into_iter()
+            .find(|preset| preset.to_string() == s)
+            .ok_or_else(|| {
+                let names: Vec<String> = EqPreset::ALL.iter().map(|p| p.to_string()).collect();
+                format!(
+                    "unknown EQ preset '{}' (expected one of {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for EqPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EqPreset::Flat => "flat",
+            EqPreset::Rock => "rock",
+            EqPreset::Pop => "pop",
+            EqPreset::Jazz => "jazz",
+            EqPreset::Classical => "classical",
+            EqPreset::Vocal => "vocal",
+            EqPreset::BassBoost => "bass-boost",
+            EqPreset::TrebleBoost => "treble-boost",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for EqBand {
+    type Err = String;
+
+    /// Parses `FREQ:GAIN` or `FREQ:GAIN:Q`, e.g. `1000:-3:1.4`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid EQ band '{}' (expected FREQ:GAIN[:Q])", s);
+        let fields: Vec<f32> = s
+            .split(':')
+            .map(|field| field.trim().parse::<f32>().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+
+        let (freq, gain, q) = match *fields.as_slice() {
+            [freq, gain] => (freq, gain, DEFAULT_Q),
+            [freq, gain, q] => (freq, gain, q),
+            _ => return Err(invalid()),
+        };
+        if !(freq > 0.0 && gain.is_finite() && q > 0.0 && q.is_finite()) {
+            return Err(invalid());
+        }
+        Ok(EqBand { freq, gain, q })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+
+    /// A stereo low tone, long enough for the filters to build up state.
+    fn tone(start: usize, frames: usize) -> Vec<f32> {
+        (start..start + frames)
+            .flat_map(|i| {
+                let s = (i as f32 * 2.0 * std::f32::consts::PI * 80.0 / RATE as f32).sin() * 0.3;
+                [s, s]
+            })
+            .collect()
+    }
+
+    fn settings(bass: f32, treble: f32) -> DspSettings {
+        DspSettings {
+            bass,
+            treble,
+            eq: EqPreset::Rock.bands(),
+            ..Default::default()
+        }
+    }
+
+    fn max_difference(a: &[f32], b: &[f32]) -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn continues_with_same_settings_seamlessly() {
+        let mut chain = DspChain::new(settings(6.0, 0.0), RATE, 2);
+        chain.process(&mut tone(0, 4800));
+
+        let mut replacement = DspChain::new(settings(6.0, 0.0), RATE, 2);
+        replacement.continue_from(&chain);
+
+        let mut expected = tone(4800, 480);
+        let mut actual = expected.clone();
+        chain.process(&mut expected);
+        replacement.process(&mut actual);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn keeps_state_of_unchanged_filters_when_adding_one() {
+        // 低音を上げたまま高音のシェルフを足す
+        let mut chain = DspChain::new(settings(6.0, 0.0), RATE, 2);
+        let mut reference = DspChain::new(settings(6.0, 0.0), RATE, 2);
+        chain.process(&mut tone(0, 4800));
+        reference.process(&mut tone(0, 4800));
+
+        chain.configure(settings(6.0, 3.0));
+        let mut actual = tone(4800, 480);
+        chain.process(&mut actual);
+
+        // 高音のシェルフだけが無音から始まる
+        let mut expected = tone(4800, 480);
+        reference.process(&mut expected);
+        let mut treble = DspChain::new(
+            DspSettings {
+                treble: 3.0,
+                ..Default::default()
+            },
+            RATE,
+            2,
+        );
+        treble.process(&mut expected);
+        assert!(max_difference(&expected, &actual) < 1e-5);
+    }
+
+    #[test]
+    fn changing_a_band_does_not_click() {
+        let mut chain = DspChain::new(settings(6.0, 0.0), RATE, 2);
+        let mut before = tone(0, 4800);
+        chain.process(&mut before);
+        chain.configure(settings(7.0, 0.0));
+        let mut after = tone(4800, 480);
+        chain.process(&mut after);
+
+        // 左チャンネルの隣り合うサンプルの差が 80 Hz の波形なりに収まること
+        let left: Vec<f32> = before[before.len() - 2..]
+            .iter()
+            .chain(&after)
+            .step_by(2)
+            .copied()
+            .collect();
+        let largest_step = left
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).abs())
+            .fold(0.0, f32::max);
+        assert!(largest_step < 0.02, "{}", largest_step);
+    }
+}
@@ -0,0 +1,333 @@
+//! Sample rate conversion of interleaved audio.
+
+use rubato::{
+    FastFixedIn, FftFixedIn, PolynomialDegree, SincFixedIn, SincInterpolationParameters,
+    SincInterpolationType, VecResampler, WindowFunction,
+};
+use std::fmt;
+use std::str::FromStr;
+
+const CHUNK_FRAMES: usize = 1024;
+/// Upper bound on flush rounds, in case the resampler never catches up
+const MAX_FLUSH_CHUNKS: usize = 16;
+
+/// Trade-off between CPU use and conversion quality.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResamplerQuality {
+    /// FFT based, cheap for fixed ratios
+    Fast,
+    /// Linear interpolation without anti-aliasing
+    Linear,
+    /// Short sinc filter
+    Medium,
+    /// 256 tap sinc filter
+    #[default]
+    High,
+    /// Long sinc filter with cubic interpolation
+    Best,
+}
+
+/// Converts interleaved samples from one rate to another.
+///
+/// The output has no leading filter delay, and after [`Resampler::flush`]
+/// exactly `round(input_frames * to / from)` frames have been produced.
+pub struct Resampler {
+    inner: Box<dyn VecResampler<f32>>,
+    from: u32,
+    to: u32,
+    channels: usize,
+    quality: ResamplerQuality,
+    /// Interleaved input waiting for a full chunk
+    pending: Vec<f32>,
+    /// Output frames still to be dropped to cancel the filter delay
+    delay_left: usize,
+    input_frames: u64,
+    output_frames: u64,
+    flushed: bool,
+}
+
+impl Resampler {
+    pub fn new(
+        from: u32,
+        to: u32,
+        channels: usize,
+        quality: ResamplerQuality,
+    ) -> Result<Self, String> {
+        let channels = channels.max(1);
+        let inner = build(from, to, channels, quality)?;
+        Ok(Self {
+            delay_left: inner.output_delay(),
+            inner,
+            from,
+            to,
+            channels,
+            quality,
+            pending: Vec::new(),
+            input_frames: 0,
+            output_frames: 0,
+            flushed: false,
+        })
+    }
+
+    /// Resamples `samples`, returning whatever output is ready. Input that
+    /// does not fill a chunk is kept for the next call.
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        self.flushed = false;
+        self.input_frames += (samples.len() / self.channels) as u64;
+        self.pending.extend_from_slice(samples);
+
+        let mut out = Vec::new();
+        loop {
+            let needed = self.inner.input_frames_next() * self.channels;
+            if self.pending.len() < needed {
+                break;
+            }
+            let chunk = deinterleave(&self.pending[..needed], self.channels);
+            self.pending.drain(..needed);
+            match self.inner.process(&chunk, None) {
+                Ok(resampled) => self.emit(&resampled, &mut out),
+                Err(e) => {
+                    crate::err!("Resampling error: {}", e);
+                    break;
+                }
+            }
+        }
+        out
+    }
+
+    /// Pushes the last partial chunk and the filter tail through. Returns
+    /// nothing when there is nothing left to flush.
+    pub fn flush(&mut self) -> Vec<f32> {
+        if self.flushed {
+            return Vec::new();
+        }
+        self.flushed = true;
+
+        let expected =
+            (self.input_frames as f64 * self.to as f64 / self.from as f64).round() as u64;
+        let mut out = Vec::new();
+        let mut partial = Some(deinterleave(&self.pending, self.channels));
+        self.pending.clear();
+
+        for _ in 0..MAX_FLUSH_CHUNKS {
+            if self.output_frames >= expected {
+                break;
+            }
+            // 最初は残りの入力を、その後は無音を流してフィルタの遅延分を出し切る
+            let result = match partial.take() {
+                Some(chunk) if !chunk[0].is_empty() => {
+                    self.inner.process_partial(Some(&chunk), None)
+                }
+                _ => self.inner.process_partial(None, None),
+            };
+            match result {
+                Ok(resampled) => self.emit(&resampled, &mut out),
+                Err(e) => {
+                    crate::err!("Resampling error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        // 期待フレーム数を超えた分は切り捨てる
+        let extra = self.output_frames.saturating_sub(expected) as usize;
+        out.truncate(out.len().saturating_sub(extra * self.channels));
+        self.output_frames -= extra as u64;
+        out
+    }
+
+    /// Forgets all buffered audio, e.g. after a seek.
+    pub fn reset(&mut self) {
+        // VecResampler には reset がないので作り直す
+        if let Ok(inner) = build(self.from, self.to, self.channels, self.quality) {
+            self.inner = inner;
+        }
+        self.delay_left = self.inner.output_delay();
+        self.pending.clear();
+        self.input_frames = 0;
+        self.output_frames = 0;
+        self.flushed = false;
+    }
+
+    /// Interleaves `resampled` into `out`, dropping the filter delay.
+    fn emit(&mut self, resampled: &[Vec<f32>], out: &mut Vec<f32>) {
+        let frames = resampled.first().map_or(0, |ch| ch.len());
+        let skip = self.delay_left.min(frames);
+        self.delay_left -= skip;
+
+        out.reserve((frames - skip) * self.channels);
+        for frame in skip..frames {
+            for channel in resampled {
+                out.push(channel[frame]);
+            }
+        }
+        self.output_frames += (frames - skip) as u64;
+    }
+}
+
+fn build(
+    from: u32,
+    to: u32,
+    channels: usize,
+    quality: ResamplerQuality,
+) -> Result<Box<dyn VecResampler<f32>>, String> {
+    let ratio = to as f64 / from as f64;
+    let sinc = |sinc_len, interpolation, oversampling_factor| {
+        let params = SincInterpolationParameters {
+            sinc_len,
+            f_cutoff: 0.95,
+            interpolation,
+            oversampling_factor,
+            window: WindowFunction::BlackmanHarris2,
+        };
+        SincFixedIn::<f32>::new(ratio, 2.0, params, CHUNK_FRAMES, channels)
+            .map(|r| Box::new(r) as Box<dyn VecResampler<f32>>)
+    };
+
+    match quality {
+        ResamplerQuality::Fast => {
+            FftFixedIn::<f32>::new(from as usize, to as usize, CHUNK_FRAMES, 2, channels)
+                .map(|r| Box::new(r) as Box<dyn VecResampler<f32>>)
+        }
+        ResamplerQuality::Linear => {
+            FastFixedIn::<f32>::new(ratio, 2.0, PolynomialDegree::Linear, CHUNK_FRAMES, channels)
+                .map(|r| Box::new(r) as Box<dyn VecResampler<f32>>)
+        }
+        ResamplerQuality::Medium => sinc(64, SincInterpolationType::Linear, 128),
+        ResamplerQuality::High => sinc(256, SincInterpolationType::Linear, 256),
+        ResamplerQuality::Best => sinc(512, SincInterpolationType::Cubic, 256),
+    }
+    .map_err(|e| e.to_string())
+}
+
+fn deinterleave(samples: &[f32], channels: usize) -> Vec<Vec<f32>> {
+    let frames = samples.len() / channels;
+    let mut out = vec![Vec::with_capacity(frames); channels];
+    for frame in samples.chunks_exact(channels) {
+        for (ch, &sample) in frame.iter().enumerate() {
+            out[ch].push(sample);
+        }
+    }
+    out
+}
+
+impl FromStr for ResamplerQuality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(ResamplerQuality::Fast),
+            "linear" => Ok(ResamplerQuality::Linear),
+            "medium" => Ok(ResamplerQuality::Medium),
+            "high" => Ok(ResamplerQuality::High),
+            "best" => Ok(ResamplerQuality::Best),
+            _ => Err(format!(
+                "unknown resampler quality '{}' (expected fast, linear, medium, high or best)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ResamplerQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ResamplerQuality::Fast => "fast",
+            ResamplerQuality::Linear => "linear",
+            ResamplerQuality::Medium => "medium",
+            ResamplerQuality::High => "high",
+            ResamplerQuality::Best => "best",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALITIES: [ResamplerQuality; 5] = [
+        ResamplerQuality::Fast,
+        ResamplerQuality::Linear,
+        ResamplerQuality::Medium,
+        ResamplerQuality::High,
+        ResamplerQuality::Best,
+    ];
+    /// Chunk sizes in frames, none of them a multiple of the internal chunk
+    const CHUNKS: [usize; 6] = [1, 7, 333, 1023, 1025, 4097];
+
+    /// Feeds `frames` of a stereo signal in odd-sized chunks and returns the
+    /// number of frames that came out.
+    fn resample(from: u32, to: u32, quality: ResamplerQuality, frames: usize) -> usize {
+        let mut resampler = Resampler::new(from, to, 2, quality).unwrap();
+        let mut out = 0;
+        let mut fed = 0;
+        for &chunk in CHUNKS.iter().cycle() {
+            if fed == frames {
+                break;
+            }
+            let len = chunk.min(frames - fed);
+            let samples: Vec<f32> = (fed..fed + len)
+                .flat_map(|i| {
+                    let s = (i as f32 * 0.01).sin() * 0.5;
+                    [s, -s]
+                })
+                .collect();
+            out += resampler.process(&samples).len();
+            fed += len;
+        }
+        out += resampler.flush().len();
+        assert_eq!(out % 2, 0);
+        out / 2
+    }
+
+    fn expected(from: u32, to: u32, frames: usize) -> usize {
+        (frames as f64 * to as f64 / from as f64).round() as usize
+    }
+
+    #[test]
+    fn produces_exact_frame_count() {
+        for quality in QUALITIES {
+            for (from, to) in [
+                (44100, 48000),
+                (48000, 44100),
+                (22050, 96000),
+                (96000, 8000),
+            ] {
+                for frames in [10, 1000, 12345] {
+                    assert_eq!(
+                        resample(from, to, quality, frames),
+                        expected(from, to, frames),
+                        "{} {} -> {} with {} frames",
+                        quality,
+                        from,
+                        to,
+                        frames
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flushes_only_once() {
+        for quality in QUALITIES {
+            let mut resampler = Resampler::new(44100, 48000, 1, quality).unwrap();
+            resampler.process(&[0.25; 3000]);
+            assert!(!resampler.flush().is_empty());
+            assert!(resampler.flush().is_empty());
+        }
+    }
+
+    #[test]
+    fn starts_over_after_reset() {
+        for quality in QUALITIES {
+            let mut resampler = Resampler::new(48000, 44100, 2, quality).unwrap();
+            resampler.process(&[0.5; 5000]);
+            resampler.reset();
+            let mut out = resampler.process(&[0.5; 2 * 777]);
+            out.extend(resampler.flush());
+            assert_eq!(out.len() / 2, expected(48000, 44100, 777), "{}", quality);
+        }
+    }
+}
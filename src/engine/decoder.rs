@@ -0,0 +1,687 @@
+#![allow(clippy::needless_range_loop)]
+use super::fade::FadeOut;
+use super::{Segment, Shared, TrackId};
+use crate::dsp::DspChain;
+use crate::dsp::channels::{self, ChannelMap};
+use crate::dsp::resample::Resampler;
+use crate::err;
+use crate::player::player_structs::{AlbumInfo, Clip, GaplessInfo, Player};
+use crate::replaygain::ReplayGain;
+use ringbuf::HeapProducer;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use symphonia::core::audio::{AudioBufferRef, Channels, SampleBuffer};
+use symphonia::core::codecs::Decoder;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
+use symphonia::core::units::{Time, TimeBase};
+
+const MAX_DECODE_PER_CYCLE: usize = 4;
+/// Consecutive packets that may fail to decode before the track is ended
+const MAX_DECODE_ERRORS: usize = 32;
+
+/// Messages from the engine handle to the decoder thread.
+pub(super) enum Command {
+    /// Continue track `id` from `to`; only the newest pending seek is applied
+    Seek {
+        id: TrackId,
+        to: Duration,
+        generation: u64,
+    },
+    /// Drop track `id` and discard whatever of it is buffered
+    Skip(TrackId),
+    Stop,
+}
+
+/// Where a seek landed, as source timestamps.
+#[derive(Clone, Copy, Debug)]
+struct SeekedTo {
+    /// Start of the packet the format reader stopped at
+    actual_ts: u64,
+    /// Timestamp that was asked for; frames before it are dropped
+    required_ts: u64,
+}
+
+/// A queued track, as handed over from a [`Player`].
+pub(super) struct Track {
+    pub id: TrackId,
+    pub format: Arc<Mutex<Box<dyn FormatReader>>>,
+    pub decoder: Arc<Mutex<Box<dyn Decoder>>>,
+    pub track_id: u32,
+    pub sample_rate: u32,
+    pub time_base: Option<TimeBase>,
+    pub channels: usize,
+    pub layout: Channels,
+    pub seekable: bool,
+    pub gapless: Option<GaplessInfo>,
+    pub album: AlbumInfo,
+    pub clip: Option<Clip>,
+    pub replay_gain: ReplayGain,
+    pub measured_gain: Option<Arc<OnceLock<ReplayGain>>>,
+    pub interrupt: Option<Arc<AtomicBool>>,
+}
+
+impl Track {
+    pub fn new(id: TrackId, player: Player) -> Self {
+        Self {
+            id,
+            format: player.format,
+            decoder: player.decoder,
+            track_id: player.track_id,
+            sample_rate: player.sample_rate,
+            time_base: player.time_base,
+            channels: player.channels as usize,
+            layout: player.layout,
+            seekable: player.seekable,
+            gapless: player.gapless,
+            album: player.album,
+            clip: player.clip,
+            replay_gain: player.replay_gain,
+            measured_gain: player.measured_gain,
+            interrupt: player.interrupt,
+        }
+    }
+}
+
+impl Track {
+    /// Makes a stream source that is waiting for data give up, so the
+    /// decoder thread gets to its commands.
+    pub fn interrupt(&self) {
+        if let Some(interrupt) = &self.interrupt {
+            interrupt.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn is_interrupted(&self) -> bool {
+        self.interrupt
+            .as_ref()
+            .is_some_and(|interrupt| interrupt.load(Ordering::Relaxed))
+    }
+
+    /// Lets a seekable source wait for data again after an interruption.
+    fn clear_interrupt(&self) {
+        if let Some(interrupt) = &self.interrupt {
+            interrupt.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Converts a source timestamp to a frame count at the track's rate.
+    fn ts_to_frames(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(ts);
+                time.seconds * self.sample_rate as u64
+                    + (time.frac * self.sample_rate as f64).round() as u64
+            }
+            None => ts,
+        }
+    }
+
+    /// Converts a time into the file to a source timestamp. Clip bounds all
+    /// go through here, so a clip ends exactly where the next one starts.
+    fn time_to_ts(&self, time: Duration) -> u64 {
+        match self.time_base {
+            Some(time_base) => time_base.calc_timestamp(Time::from(time)),
+            None => (time.as_secs_f64() * self.sample_rate as f64).round() as u64,
+        }
+    }
+
+    /// Where the played part of the file starts.
+    fn clip_start(&self) -> Duration {
+        self.clip.map_or(Duration::ZERO, |clip| clip.start)
+    }
+
+    /// Encoder delay frames at the start of the source.
+    fn delay(&self) -> u64 {
+        self.gapless.map_or(0, |info| info.delay)
+    }
+
+    /// Source timestamp of `time` into the audio, past the encoder delay.
+    fn source_ts(&self, time: Duration) -> u64 {
+        let delay = self.delay();
+        let delay_ts = match self.time_base {
+            Some(time_base) if delay > 0 => {
+                let rate = self.sample_rate.max(1) as u64;
+                time_base
+                    .calc_timestamp(Time::new(delay / rate, (delay % rate) as f64 / rate as f64))
+            }
+            _ => delay,
+        };
+        self.time_to_ts(time) + delay_ts
+    }
+}
+
+/// Per-track state owned by the decoder thread.
+struct Decoding {
+    track: Arc<Track>,
+    resampler: Option<Resampler>,
+    current_samples: Vec<f32>,
+    current_index: usize,
+    output_channels: usize,
+    /// Converts the track's channel layout to the sink's
+    channel_map: ChannelMap,
+    /// ReplayGain factor applied to every sample of the track
+    gain: f32,
+    /// Samples held back for a crossfade with the next track
+    hold: usize,
+    tail: VecDeque<f32>,
+    /// End of the previous track being mixed into this one
+    fade: Option<FadeOut>,
+    /// Set once the decoder reached the end and only `current_samples` is left
+    draining: bool,
+    /// Encoder delay frames still to be dropped
+    trim_start: u64,
+    /// Frames left before the encoder padding or the end of the clip
+    frames_left: Option<u64>,
+    /// Packets in a row that failed to decode
+    decode_errors: usize,
+}
+
+impl Decoding {
+    fn new(track: Arc<Track>, shared: &Shared, gain: f32) -> Self {
+        let output_sample_rate = shared.output_rate;
+        let output_channels = shared.output_channels;
+        let input_sample_rate = track.sample_rate;
+        let channels = track.channels;
+
+        let resampler = if input_sample_rate != output_sample_rate {
+            match Resampler::new(
+                input_sample_rate,
+                output_sample_rate,
+                channels,
+                shared.resampler_quality,
+            ) {
+                Ok(r) => Some(r),
+                Err(e) => {
+                    err!("Failed to create resampler: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let (trim_start, frames_left) = match track.gapless {
+            Some(info) => (info.delay, info.frames),
+            None => (0, None),
+        };
+
+        let channel_map = ChannelMap::new(
+            track.layout,
+            channels::default_layout(output_channels as usize),
+        );
+
+        let mut decoding = Self {
+            track,
+            resampler,
+            current_samples: Vec::new(),
+            current_index: 0,
+            output_channels: output_channels as usize,
+            channel_map,
+            gain,
+            hold: shared.crossfade.map_or(0, |c| {
+                (c.duration.as_secs_f64() * output_sample_rate as f64) as usize
+                    * output_channels as usize
+            }),
+            tail: VecDeque::new(),
+            fade: None,
+            draining: false,
+            trim_start,
+            frames_left,
+            decode_errors: 0,
+        };
+
+        // クリップの頭へシークし、できなければ頭から数えて捨てる
+        if let Some(clip) = decoding.track.clip {
+            let to = SeekTo::TimeStamp {
+                ts: decoding.track.source_ts(clip.start),
+                track_id: decoding.track.track_id,
+            };
+            if clip.start.is_zero() || decoding.seek_source(to).is_none() {
+                let start = decoding
+                    .track
+                    .ts_to_frames(decoding.track.time_to_ts(clip.start));
+                decoding.trim_start += start;
+                decoding.limit_to_clip(start);
+            }
+        }
+        decoding
+    }
+
+    /// Drops buffered audio after a seek.
+    fn reset(&mut self, seek: SeekedTo) {
+        self.current_samples.clear();
+        self.current_index = 0;
+        self.tail.clear();
+        self.fade = None;
+        self.draining = false;
+        if let Some(ref mut r) = self.resampler {
+            r.reset();
+        }
+
+        // シーク先のパケットの頭から目的の位置までを捨てる。エンコーダーの
+        // 遅延より前に着地したときは遅延の分も捨てる
+        let delay = self.track.delay();
+        let first = self.track.ts_to_frames(seek.required_ts).max(delay);
+        self.trim_start = first.saturating_sub(self.track.ts_to_frames(seek.actual_ts));
+        let position = first - delay;
+        self.frames_left = self
+            .track
+            .gapless
+            .and_then(|info| info.frames)
+            .map(|frames| frames.saturating_sub(position));
+        self.limit_to_clip(position);
+    }
+
+    /// Stops the track at the end of its clip, counting from `position`,
+    /// the frame of the audio past the encoder delay.
+    fn limit_to_clip(&mut self, position: u64) {
+        let Some(end) = self.track.clip.and_then(|clip| clip.end) else {
+            return;
+        };
+        let end = self.track.ts_to_frames(self.track.time_to_ts(end));
+        let left = end.saturating_sub(position);
+        self.frames_left = Some(self.frames_left.map_or(left, |frames| frames.min(left)));
+    }
+
+    /// Seeks the source and drops the decoded audio.
+    fn seek_source(&mut self, to: SeekTo) -> Option<()> {
+        let result = {
+            let mut format = self.track.format.lock().unwrap();
+            let mut decoder = self.track.decoder.lock().unwrap();
+            let result = format.seek(SeekMode::Accurate, to);
+            decoder.reset();
+            result
+        };
+        let seeked = result.ok()?;
+
+        self.reset(SeekedTo {
+            actual_ts: seeked.actual_ts,
+            required_ts: seeked.required_ts,
+        });
+        Some(())
+    }
+
+    /// Seeks the track to `to` and drops everything buffered for it. Returns
+    /// the sample count the new position starts at.
+    fn seek(&mut self, shared: &Shared, to: Duration) -> Option<u64> {
+        self.track.clear_interrupt();
+        self.seek_source(SeekTo::TimeStamp {
+            ts: self.track.source_ts(self.track.clip_start() + to),
+            track_id: self.track.track_id,
+        })?;
+
+        // リングバッファに残っているシーク前の音を捨てる
+        let start = shared.pushed.load(Ordering::Relaxed);
+        shared.skip_until.fetch_max(start, Ordering::Relaxed);
+        shared.segments.lock().unwrap().push_back(Segment {
+            id: self.track.id,
+            start,
+            base: to,
+        });
+        Some(start)
+    }
+
+    /// Decodes until `current_samples` holds something to push, or the
+    /// source was interrupted and the commands should be looked at first.
+    /// Returns false at the end of the track.
+    fn decode_next(&mut self) -> bool {
+        loop {
+            if !self.decode_packet() {
+                return false;
+            }
+            if !self.current_samples.is_empty() || self.track.is_interrupted() {
+                return true;
+            }
+        }
+    }
+
+    /// Decodes one packet into `current_samples`. Returns false at the end of
+    /// the track.
+    fn decode_packet(&mut self) -> bool {
+        self.current_samples.clear();
+        self.current_index = 0;
+        if self.draining {
+            return false;
+        }
+
+        let samples = match self.read_packet() {
+            Some(Some(samples)) => samples,
+            Some(None) => return true,
+            // 終端ではリサンプラーに残っている分を出し切る
+            None => match self.resampler.as_mut().map(Resampler::flush) {
+                Some(samples) if !samples.is_empty() => {
+                    self.finish_packet(samples);
+                    return true;
+                }
+                _ => return false,
+            },
+        };
+
+        let samples = match self.resampler.as_mut() {
+            Some(resampler) => resampler.process(&samples),
+            None => samples,
+        };
+        self.finish_packet(samples);
+        true
+    }
+
+    /// Reads and trims the next packet of the track. Returns `None` at the
+    /// end of the track and `Some(None)` for packets without audio.
+    fn read_packet(&mut self) -> Option<Option<Vec<f32>>> {
+        if self.frames_left == Some(0) {
+            return None;
+        }
+
+        let channels = self.track.channels;
+        let mut format = self.track.format.lock().unwrap();
+        let mut decoder = self.track.decoder.lock().unwrap();
+
+        let packet = loop {
+            match format.next_packet() {
+                Ok(packet) => break packet,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return None;
+                }
+                // 連結された Ogg などでストリームが切り替わった
+                Err(Error::ResetRequired) => decoder.reset(),
+                // シークなどのために待ちを中断した
+                Err(_) if self.track.is_interrupted() => return Some(None),
+                Err(e) => {
+                    err!("Failed to read the track: {}", e);
+                    return None;
+                }
+            }
+        };
+
+        if packet.track_id() != self.track.track_id {
+            return Some(None);
+        }
+
+        let mut samples = match decoder.decode(&packet) {
+            Ok(decoded) => {
+                self.decode_errors = 0;
+                convert_samples(decoded)
+            }
+            Err(Error::ResetRequired) => {
+                decoder.reset();
+                return Some(None);
+            }
+            // 壊れたパケットは飛ばすが、続く場合は諦める
+            Err(Error::DecodeError(e)) => {
+                self.decode_errors += 1;
+                if self.decode_errors >= MAX_DECODE_ERRORS {
+                    err!(
+                        "Giving up after {} undecodable packets: {}",
+                        MAX_DECODE_ERRORS,
+                        e
+                    );
+                    return None;
+                }
+                return Some(None);
+            }
+            Err(e) => {
+                err!("Failed to decode the track: {}", e);
+                return None;
+            }
+        };
+        drop(decoder);
+        drop(format);
+
+        // エンコーダーの遅延とパディングを取り除く
+        if self.trim_start > 0 {
+            let frames = (samples.len() / channels) as u64;
+            let trim = self.trim_start.min(frames);
+            samples.drain(..trim as usize * channels);
+            self.trim_start -= trim;
+        }
+        if let Some(left) = self.frames_left.as_mut() {
+            let frames = (samples.len() / channels) as u64;
+            let keep = frames.min(*left);
+            samples.truncate(keep as usize * channels);
+            *left -= keep;
+        }
+        Some(Some(samples))
+    }
+
+    /// Runs resampled samples through the rest of the pipeline into
+    /// `current_samples`.
+    fn finish_packet(&mut self, samples: Vec<f32>) {
+        let mut samples = self.channel_map.apply(samples);
+        if self.gain != 1.0 {
+            for sample in samples.iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+
+        if let Some(fade) = self.fade.as_mut() {
+            fade.mix(&mut samples);
+            if fade.is_done() {
+                self.fade = None;
+            }
+        }
+
+        // クロスフェード用に末尾を保持しておく
+        if self.hold > 0 {
+            self.tail.extend(samples);
+            let ready = self.tail.len().saturating_sub(self.hold);
+            samples = self.tail.drain(..ready).collect();
+        }
+
+        self.current_samples = samples;
+        self.current_index = 0;
+    }
+
+    /// Takes the samples still held back at the end of the track.
+    fn take_tail(&mut self) -> Vec<f32> {
+        let mut tail: Vec<f32> = self.tail.drain(..).collect();
+        if let Some(fade) = self.fade.take() {
+            tail.extend(fade.into_remainder());
+        }
+        tail
+    }
+
+    /// Pushes out whatever is left of the track before moving on.
+    fn drain(&mut self, samples: Vec<f32>) {
+        self.current_samples = samples;
+        self.current_index = 0;
+        self.draining = true;
+    }
+}
+
+fn convert_samples(buffer: AudioBufferRef) -> Vec<f32> {
+    let spec = *buffer.spec();
+    let duration = buffer.frames();
+
+    let mut sample_buf = SampleBuffer::<f32>::new(duration as u64, spec);
+    sample_buf.copy_interleaved_ref(buffer);
+    sample_buf.samples().to_vec()
+}
+
+/// Takes the next queued track and starts a segment for it.
+fn start_next(shared: &Shared) -> Option<Decoding> {
+    let previous = shared.decoding.lock().unwrap().clone();
+    let mut queue = shared.queue.lock().unwrap();
+    let Some(track) = queue.pop_front() else {
+        shared.idle.store(true, Ordering::Relaxed);
+        *shared.decoding.lock().unwrap() = None;
+        return None;
+    };
+    shared.idle.store(false, Ordering::Relaxed);
+
+    // 前後の曲が同じアルバムならアルバムゲインを使う
+    let same_album =
+        |other: &AlbumInfo| track.album.title.is_some() && other.title == track.album.title;
+    let album = previous.as_ref().is_some_and(|p| same_album(&p.album))
+        || queue.front().is_some_and(|next| same_album(&next.album));
+    // 測定が間に合わなければタグのゲインのまま再生する
+    let replay_gain = track
+        .measured_gain
+        .as_ref()
+        .and_then(|measured| measured.get())
+        .copied()
+        .unwrap_or(track.replay_gain);
+    let gain = replay_gain.factor(&shared.replay_gain, album);
+
+    // キューのロックを保持したまま登録し、曲が一瞬どこにも無い状態を避ける
+    let track = Arc::new(track);
+    shared.segments.lock().unwrap().push_back(Segment {
+        id: track.id,
+        start: shared.pushed.load(Ordering::Relaxed),
+        base: Duration::ZERO,
+    });
+    *shared.decoding.lock().unwrap() = Some(Arc::clone(&track));
+    drop(queue);
+
+    Some(Decoding::new(track, shared, gain))
+}
+
+/// Returns the crossfade to apply if the next queued track should overlap
+/// with the end of `track`.
+fn crossfade_into_next(shared: &Shared, track: &Track) -> Option<super::Crossfade> {
+    let crossfade = shared.crossfade?;
+    let queue = shared.queue.lock().unwrap();
+    let next = queue.front()?;
+    (!next.album.continues(&track.album)).then_some(crossfade)
+}
+
+/// Drops the skipped track and tells the sink to discard what is left of it.
+fn skip_track(shared: &Shared, id: TrackId, current: &mut Option<Decoding>) {
+    if current.as_ref().is_some_and(|c| c.track.id == id) {
+        *current = None;
+    }
+
+    let pushed = shared.pushed.load(Ordering::Relaxed);
+    let segments = shared.segments.lock().unwrap();
+    let until = segments
+        .iter()
+        .rposition(|s| s.id == id)
+        .and_then(|last| segments.get(last + 1))
+        .map(|s| s.start)
+        .unwrap_or(pushed);
+
+    shared.skip_until.fetch_max(until, Ordering::Relaxed);
+}
+
+/// Applies the newest seek request, if it is still for the current track.
+fn seek_track(
+    shared: &Shared,
+    current: &mut Option<Decoding>,
+    id: TrackId,
+    to: Duration,
+    generation: u64,
+) {
+    let start = current
+        .as_mut()
+        .filter(|c| c.track.id == id)
+        .and_then(|c| c.seek(shared, to));
+
+    let mut pending = shared.pending_seek.lock();
+    if let Some(seek) = pending.as_mut()
+        && seek.generation == generation
+    {
+        match start {
+            Some(start) => seek.start = Some(start),
+            None => *pending = None,
+        }
+    }
+}
+
+// DECODER THREAD
+pub(super) fn run(
+    shared: Arc<Shared>,
+    mut producer: HeapProducer<f32>,
+    commands: Receiver<Command>,
+    retired_dsp: Receiver<DspChain>,
+) {
+    let mut current: Option<Decoding> = None;
+
+    loop {
+        // シンクが差し替えた DSP チェーンはここで解放する
+        while retired_dsp.try_recv().is_ok() {}
+
+        // 連続したシークはまとめて最後の一回だけ行う
+        let mut seek = None;
+        loop {
+            match commands.try_recv() {
+                Ok(Command::Seek { id, to, generation }) => seek = Some((id, to, generation)),
+                Ok(Command::Skip(id)) => skip_track(&shared, id, &mut current),
+                Ok(Command::Stop) | Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        if let Some((id, to, generation)) = seek {
+            seek_track(&shared, &mut current, id, to, generation);
+        }
+
+        if current.is_none() {
+            current = start_next(&shared);
+            if current.is_none() {
+                std::thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+        }
+        let Some(decoding) = current.as_mut() else {
+            continue;
+        };
+
+        let free_space = producer.free_len();
+
+        if free_space > 2048 {
+            let mut samples_added = 0;
+            let mut decode_count = 0;
+            let mut ended = false;
+
+            while samples_added < free_space && decode_count < MAX_DECODE_PER_CYCLE {
+                if decoding.current_index >= decoding.current_samples.len() {
+                    if !decoding.decode_next() {
+                        ended = true;
+                        break;
+                    }
+                    decode_count += 1;
+                }
+
+                let pending = &decoding.current_samples[decoding.current_index..];
+                let pushed = producer.push_slice(pending);
+                if pushed == 0 {
+                    break;
+                }
+                decoding.current_index += pushed;
+                samples_added += pushed;
+                shared.pushed.fetch_add(pushed as u64, Ordering::Relaxed);
+            }
+
+            if ended {
+                let tail = decoding.take_tail();
+                if tail.is_empty() {
+                    // 次の曲をすぐに同じバッファへ続けてデコードする
+                    current = None;
+                } else if let Some(crossfade) = crossfade_into_next(&shared, &decoding.track) {
+                    match start_next(&shared) {
+                        Some(mut next) => {
+                            next.fade =
+                                Some(FadeOut::new(tail, next.output_channels, crossfade.curve));
+                            current = Some(next);
+                        }
+                        // 次の曲が無くなっていたら残りを流し切ってから終える
+                        None => {
+                            shared.idle.store(false, Ordering::Relaxed);
+                            *shared.decoding.lock().unwrap() = Some(Arc::clone(&decoding.track));
+                            decoding.drain(tail);
+                        }
+                    }
+                } else {
+                    decoding.drain(tail);
+                }
+                continue;
+            }
+
+            std::thread::sleep(Duration::from_millis(5));
+        } else {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
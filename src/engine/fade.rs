@@ -0,0 +1,106 @@
+use std::f32::consts::FRAC_PI_2;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Overlap between the end of one track and the start of the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Crossfade {
+    pub duration: Duration,
+    pub curve: FadeCurve,
+}
+
+/// Gain curve used while crossfading.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FadeCurve {
+    /// Keeps the perceived loudness constant through the overlap
+    #[default]
+    EqualPower,
+    /// Gains change linearly; dips slightly in the middle
+    Linear,
+}
+
+impl FadeCurve {
+    /// Returns the `(fade_in, fade_out)` gains at `t` in `0.0..=1.0`.
+    pub fn gains(self, t: f32) -> (f32, f32) {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::EqualPower => ((t * FRAC_PI_2).sin(), (t * FRAC_PI_2).cos()),
+            FadeCurve::Linear => (t, 1.0 - t),
+        }
+    }
+}
+
+impl FromStr for FadeCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "equal-power" | "equal_power" => Ok(FadeCurve::EqualPower),
+            "linear" => Ok(FadeCurve::Linear),
+            _ => Err(format!(
+                "unknown fade curve '{}' (expected equal-power or linear)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FadeCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FadeCurve::EqualPower => write!(f, "equal-power"),
+            FadeCurve::Linear => write!(f, "linear"),
+        }
+    }
+}
+
+/// The held-back end of the previous track, mixed into the start of the
+/// next one.
+pub(super) struct FadeOut {
+    tail: Vec<f32>,
+    pos: usize,
+    channels: usize,
+    curve: FadeCurve,
+}
+
+impl FadeOut {
+    pub fn new(tail: Vec<f32>, channels: usize, curve: FadeCurve) -> Self {
+        Self {
+            tail,
+            pos: 0,
+            channels: channels.max(1),
+            curve,
+        }
+    }
+
+    fn gains_at(&self, pos: usize) -> (f32, f32) {
+        let frames = (self.tail.len() / self.channels).max(1);
+        let frame = pos / self.channels;
+        self.curve.gains(frame as f32 / frames as f32)
+    }
+
+    /// Fades `samples` in while mixing the previous track over them.
+    pub fn mix(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            if self.pos >= self.tail.len() {
+                break;
+            }
+            let (fade_in, fade_out) = self.gains_at(self.pos);
+            *sample = *sample * fade_in + self.tail[self.pos] * fade_out;
+            self.pos += 1;
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pos >= self.tail.len()
+    }
+
+    /// What is left of the previous track, faded out over silence. Used
+    /// when the next track ends before the overlap does.
+    pub fn into_remainder(self) -> Vec<f32> {
+        (self.pos..self.tail.len())
+            .map(|pos| self.tail[pos] * self.gains_at(pos).1)
+            .collect()
+    }
+}
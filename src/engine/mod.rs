@@ -0,0 +1,445 @@
+mod decoder;
+mod fade;
+
+use crate::dsp::resample::ResamplerQuality;
+use crate::dsp::{DspChain, DspSettings};
+use crate::error::{MinauError, Result};
+use crate::player::player_structs::Player;
+use crate::replaygain::ReplayGainOptions;
+use crate::sink::{AudioSink, ResampleMode, SinkSpec};
+use decoder::{Command, Track};
+pub use fade::{Crossfade, FadeCurve};
+use ringbuf::HeapRb;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// DSP chains in flight between the engine and the sink, each way. The
+/// channels are bounded so the sink never allocates or frees their slots.
+const DSP_CHANNEL_LEN: usize = 8;
+
+/// Identifies a track queued on a [`PlaybackEngine`].
+pub type TrackId = u64;
+
+/// Settings used when starting a [`PlaybackEngine`].
+#[derive(Clone, Debug, Default)]
+pub struct EngineOptions {
+    /// Where the decoded audio is sent
+    pub output: SinkSpec,
+    /// Whether the device may be opened at the track's rate
+    pub resample: ResampleMode,
+    /// Converter used for tracks whose rate differs from the sink's
+    pub resampler_quality: ResamplerQuality,
+    /// Overlap between consecutive tracks, skipped for album continuations
+    pub crossfade: Option<Crossfade>,
+    /// How ReplayGain tags of queued tracks are applied
+    pub replay_gain: ReplayGainOptions,
+    /// Effects applied to the output, changeable with [`PlaybackEngine::set_dsp`]
+    pub dsp: DspSettings,
+}
+
+/// A run of samples in the ring buffer that belongs to one track.
+///
+/// A new segment starts whenever a track begins or is seeked, so the
+/// playback position can be derived from the number of samples the sink
+/// has consumed.
+struct Segment {
+    id: TrackId,
+    /// Total number of samples pushed before this segment
+    start: u64,
+    /// Track position at the first sample of the segment
+    base: Duration,
+}
+
+/// A seek that was requested but is not audible yet.
+#[derive(Clone, Copy, Debug)]
+struct PendingSeek {
+    generation: u64,
+    target: Duration,
+    /// First sample after the seek, once the decoder has applied it
+    start: Option<u64>,
+}
+
+/// State shared between the engine handle, the decoder thread and the sink.
+struct Shared {
+    paused: AtomicBool,
+    /// Bits of the `f32` volume, read by the sink without locking
+    volume: AtomicU32,
+    /// Settings of the DSP chain owned by the sink
+    dsp: parking_lot::Mutex<DspSettings>,
+    /// Incremented for every seek request
+    seek_generation: AtomicU64,
+    /// Newest seek whose audio is not audible yet
+    pending_seek: parking_lot::Mutex<Option<PendingSeek>>,
+    /// The sink discards samples until `played` reaches this value
+    skip_until: AtomicU64,
+    /// Samples pushed into the ring buffer by the decoder
+    pushed: AtomicU64,
+    /// Samples consumed by the sink
+    played: AtomicU64,
+    /// Silence the sink was given since the last real sample
+    trailing_silence: AtomicU64,
+    /// Set while the decoder has no track to decode
+    idle: AtomicBool,
+    output_rate: u32,
+    output_channels: u16,
+    resampler_quality: ResamplerQuality,
+    crossfade: Option<Crossfade>,
+    replay_gain: ReplayGainOptions,
+    next_id: AtomicU64,
+    queue: Mutex<VecDeque<Track>>,
+    decoding: Mutex<Option<Arc<Track>>>,
+    segments: Mutex<VecDeque<Segment>>,
+}
+
+/// Decode, resample and output pipeline shared by every [`Source`](crate::source::Source).
+///
+/// Tracks are queued with [`enqueue`](Self::enqueue). A decoder thread pulls
+/// packets from the current track, resamples them to the sink rate and
+/// pushes them into a ring buffer that the [`AudioSink`] drains. When a
+/// track ends the next queued one is decoded straight into the same buffer,
+/// so consecutive tracks play without a gap or a device reopen.
+pub struct PlaybackEngine {
+    sink: Box<dyn AudioSink>,
+    shared: Arc<Shared>,
+    commands: Sender<Command>,
+    /// Rebuilt DSP chains for the sink to switch to
+    dsp_updates: SyncSender<DspChain>,
+}
+
+impl PlaybackEngine {
+    /// Opens the output and starts an empty engine. `preferred` is the
+    /// `(sample_rate, channels)` of the first track, used by sinks that are
+    /// not bound to a device.
+    pub fn new(options: &EngineOptions, preferred: (u32, u16)) -> Result<Self> {
+        let mut sink = options.output.open(preferred, options.resample)?;
+        let output_rate = sink.sample_rate();
+        let output_channels = sink.channels();
+
+        let shared = Arc::new(Shared {
+            paused: AtomicBool::new(false),
+            volume: AtomicU32::new(1.0f32.to_bits()),
+            dsp: parking_lot::Mutex::new(options.dsp.clone()),
+            seek_generation: AtomicU64::new(0),
+            pending_seek: parking_lot::Mutex::new(None),
+            skip_until: AtomicU64::new(0),
+            pushed: AtomicU64::new(0),
+            played: AtomicU64::new(0),
+            trailing_silence: AtomicU64::new(0),
+            idle: AtomicBool::new(true),
+            output_rate,
+            output_channels,
+            resampler_quality: options.resampler_quality,
+            crossfade: options.crossfade.filter(|c| !c.duration.is_zero()),
+            replay_gain: options.replay_gain,
+            next_id: AtomicU64::new(1),
+            queue: Mutex::new(VecDeque::new()),
+            decoding: Mutex::new(None),
+            segments: Mutex::new(VecDeque::new()),
+        });
+
+        let (producer, mut consumer) = HeapRb::<f32>::new(output_rate as usize).split();
+
+        // DSP チェーンはコールバックが持ち、設定変更は作り直したものを受け取る。
+        // 使い終わったものはデコーダースレッドで解放する
+        let mut dsp = DspChain::new(options.dsp.clone(), output_rate, output_channels);
+        let (dsp_updates, dsp_rx) = mpsc::sync_channel::<DspChain>(DSP_CHANNEL_LEN);
+        let (retired_tx, retired_rx) = mpsc::sync_channel::<DspChain>(DSP_CHANNEL_LEN);
+        let mut retired: Option<DspChain> = None;
+
+        let (commands, receiver) = mpsc::channel();
+        let decoder_shared = Arc::clone(&shared);
+        std::thread::spawn(move || decoder::run(decoder_shared, producer, receiver, retired_rx));
+
+        let stream_shared = Arc::clone(&shared);
+        sink.start(Box::new(move |data: &mut [f32]| {
+            loop {
+                // 古いチェーンを渡せるまでは切り替えない
+                if let Some(old) = retired.take()
+                    && let Err(TrySendError::Full(old) | TrySendError::Disconnected(old)) =
+                        retired_tx.try_send(old)
+                {
+                    retired = Some(old);
+                    break;
+                }
+                let Ok(mut chain) = dsp_rx.try_recv() else {
+                    break;
+                };
+                chain.continue_from(&dsp);
+                retired = Some(std::mem::replace(&mut dsp, chain));
+            }
+
+            // スキップされた曲の残りを捨てる
+            let played = stream_shared.played.load(Ordering::Relaxed);
+            let skip_until = stream_shared.skip_until.load(Ordering::Relaxed);
+            if skip_until > played {
+                let skipped = consumer.skip((skip_until - played) as usize);
+                stream_shared
+                    .played
+                    .fetch_add(skipped as u64, Ordering::Relaxed);
+            }
+
+            if stream_shared.paused.load(Ordering::Relaxed) {
+                data.fill(0.0);
+                stream_shared
+                    .trailing_silence
+                    .fetch_add(data.len() as u64, Ordering::Relaxed);
+                return 0;
+            }
+
+            let vol = f32::from_bits(stream_shared.volume.load(Ordering::Relaxed));
+
+            let rendered = consumer.pop_slice(data);
+            dsp.process(&mut data[..rendered]);
+            for sample in data[..rendered].iter_mut() {
+                *sample *= vol;
+            }
+            data[rendered..].fill(0.0);
+
+            let silence = (data.len() - rendered) as u64;
+            if rendered > 0 {
+                stream_shared
+                    .trailing_silence
+                    .store(silence, Ordering::Relaxed);
+            } else {
+                stream_shared
+                    .trailing_silence
+                    .fetch_add(silence, Ordering::Relaxed);
+            }
+
+            stream_shared
+                .played
+                .fetch_add(rendered as u64, Ordering::Relaxed);
+            rendered
+        }))?;
+
+        Ok(PlaybackEngine {
+            sink,
+            shared,
+            commands,
+            dsp_updates,
+        })
+    }
+
+    /// Sample rate the output was opened at.
+    pub fn sample_rate(&self) -> u32 {
+        self.shared.output_rate
+    }
+
+    /// Queues a track to play after everything queued before it.
+    pub fn enqueue(&self, player: Player) -> TrackId {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        self.shared
+            .queue
+            .lock()
+            .unwrap()
+            .push_back(Track::new(id, player));
+        id
+    }
+
+    /// Samples that have actually been heard: those consumed by the sink,
+    /// minus the ones still waiting in the device buffer.
+    fn audible(&self) -> u64 {
+        let played = self.shared.played.load(Ordering::Relaxed);
+        let samples_per_sec =
+            self.shared.output_rate as f64 * self.shared.output_channels.max(1) as f64;
+        let delay = (self.sink.delay().as_secs_f64() * samples_per_sec) as u64;
+        // 一時停止中などに渡した無音はまだ鳴っていない曲のサンプルではない
+        let pending = delay.saturating_sub(self.shared.trailing_silence.load(Ordering::Relaxed));
+        played.saturating_sub(pending)
+    }
+
+    /// The track currently audible, if any.
+    pub fn current_track(&self) -> Option<TrackId> {
+        let played = self.audible();
+        let pushed = self.shared.pushed.load(Ordering::Relaxed);
+        let mut segments = self.shared.segments.lock().unwrap();
+
+        while segments.len() > 1 && segments[1].start <= played {
+            segments.pop_front();
+        }
+
+        let segment = segments.front()?;
+        if played >= pushed && self.shared.idle.load(Ordering::Relaxed) {
+            return None;
+        }
+        Some(segment.id)
+    }
+
+    /// Returns true once every sample of track `id` has been handed to the
+    /// sink, or the track was skipped.
+    pub fn is_finished(&self, id: TrackId) -> bool {
+        let queue = self.shared.queue.lock().unwrap();
+        if queue.iter().any(|track| track.id == id) {
+            return false;
+        }
+        if self
+            .shared
+            .decoding
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|track| track.id == id)
+        {
+            return false;
+        }
+
+        let played = self.audible();
+        let segments = self.shared.segments.lock().unwrap();
+        match segments.iter().rposition(|s| s.id == id) {
+            Some(last) => match segments.get(last + 1) {
+                Some(next) => next.start <= played,
+                None => played >= self.shared.pushed.load(Ordering::Relaxed),
+            },
+            None => true,
+        }
+    }
+
+    /// Returns true once every queued track has been decoded and every
+    /// sample has been handed to the sink.
+    pub fn is_empty(&self) -> bool {
+        let queue = self.shared.queue.lock().unwrap();
+        queue.is_empty()
+            && self.shared.idle.load(Ordering::Relaxed)
+            && self.audible() >= self.shared.pushed.load(Ordering::Relaxed)
+    }
+
+    /// Stops the current track and moves on to the next queued one.
+    pub fn skip(&self) {
+        if let Some(id) = self.current_track() {
+            if let Some(track) = self.decoding_current() {
+                track.interrupt();
+            }
+            let _ = self.commands.send(Command::Skip(id));
+        }
+    }
+
+    pub fn pause(&self) {
+        self.shared.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.shared.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn get_volume(&self) -> f32 {
+        f32::from_bits(self.shared.volume.load(Ordering::Relaxed))
+    }
+
+    pub fn set_volume(self, vol: f32) -> Self {
+        self.set_volume_mut(vol);
+        self
+    }
+
+    pub fn set_volume_mut(&self, vol: f32) {
+        let vol = vol.clamp(0.0, 1.0);
+        self.shared.volume.store(vol.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Current effect settings.
+    pub fn dsp(&self) -> DspSettings {
+        self.shared.dsp.lock().clone()
+    }
+
+    /// Replaces the effect settings; takes effect on the next buffer. The
+    /// filters are built here, so the sink only has to switch to them.
+    pub fn set_dsp(&self, settings: DspSettings) {
+        let mut current = self.shared.dsp.lock();
+        let chain = DspChain::new(
+            settings.clone(),
+            self.shared.output_rate,
+            self.shared.output_channels,
+        );
+        let _ = self.dsp_updates.send(chain);
+        *current = settings;
+    }
+
+    /// Whether the current track can be seeked.
+    pub fn is_seekable(&self) -> bool {
+        self.decoding_current().is_some_and(|track| track.seekable)
+    }
+
+    /// The track that is both audible and still being decoded.
+    fn decoding_current(&self) -> Option<Arc<Track>> {
+        let current = self.current_track()?;
+        self.shared
+            .decoding
+            .lock()
+            .unwrap()
+            .clone()
+            .filter(|track| track.id == current)
+    }
+
+    /// Asks the decoder to continue the current track from `dur`. Returns
+    /// at once; audio that was buffered before the seek is dropped. Seeks
+    /// requested before the decoder gets to them are coalesced, and
+    /// [`PlaybackEngine::get_pos`] reports the newest target meanwhile.
+    pub fn seek(&self, dur: Duration) -> Result<()> {
+        let Some(track) = self.decoding_current() else {
+            return Err(MinauError::Seek("The track is about to end".into()));
+        };
+        if !track.seekable {
+            return Err(MinauError::Seek(
+                "Seek is not supported in stream mode".into(),
+            ));
+        }
+
+        // データを待っているソースを起こしてから頼む
+        track.interrupt();
+        let generation = self.shared.seek_generation.fetch_add(1, Ordering::Relaxed) + 1;
+        *self.shared.pending_seek.lock() = Some(PendingSeek {
+            generation,
+            target: dur,
+            start: None,
+        });
+        self.commands
+            .send(Command::Seek {
+                id: track.id,
+                to: dur,
+                generation,
+            })
+            .map_err(|_| MinauError::Seek("The decoder has stopped".into()))
+    }
+
+    /// Position within the current track, compensated for output latency.
+    pub fn get_pos(&self) -> Duration {
+        let played = self.audible();
+        {
+            // シーク後の音が聞こえるまでは目標位置を返す
+            let mut pending = self.shared.pending_seek.lock();
+            match *pending {
+                Some(seek) if seek.start.is_none_or(|start| played < start) => {
+                    return seek.target;
+                }
+                Some(_) => *pending = None,
+                None => {}
+            }
+        }
+
+        let segments = self.shared.segments.lock().unwrap();
+
+        let Some(segment) = segments.iter().rev().find(|s| s.start <= played) else {
+            return Duration::ZERO;
+        };
+
+        let frames = (played - segment.start) / self.shared.output_channels.max(1) as u64;
+        let nanos = frames as u128 * 1_000_000_000 / self.shared.output_rate.max(1) as u128;
+        segment.base + Duration::from_nanos(nanos as u64)
+    }
+}
+
+impl Drop for PlaybackEngine {
+    fn drop(&mut self) {
+        // デコーダースレッドを停止させる
+        if let Some(track) = self.shared.decoding.lock().unwrap().as_ref() {
+            track.interrupt();
+        }
+        let _ = self.commands.send(Command::Stop);
+    }
+}
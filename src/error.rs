@@ -0,0 +1,75 @@
+use std::fmt;
+use std::io;
+
+use lofty::error::LoftyError;
+use symphonia::core::errors::Error as SymphoniaError;
+
+pub type Result<T> = std::result::Result<T, MinauError>;
+
+/// Errors that can occur while opening, decoding or playing audio.
+#[derive(Debug)]
+pub enum MinauError {
+    /// Failed to open or read a file
+    Io(io::Error),
+    /// The container format could not be recognised
+    Probe(SymphoniaError),
+    /// The container has no track that can be decoded
+    NoTrack,
+    /// Failed to create a decoder or decode the stream
+    Decode(String),
+    /// Failed to read tags or audio properties
+    Metadata(LoftyError),
+    /// No usable output device, or the output stream could not be started
+    Device(String),
+    /// Failed to fetch a remote stream
+    Http(String),
+    /// Failed to read or parse a playlist
+    Playlist(String),
+    /// Failed to seek within the current track
+    Seek(String),
+    /// The file's tag cannot hold the requested items
+    TagWrite(String),
+    /// Failed to set up the terminal
+    Terminal(io::Error),
+}
+
+impl fmt::Display for MinauError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MinauError::Io(e) => write!(f, "{}", e),
+            MinauError::Probe(e) => write!(f, "Failed to probe format: {}", e),
+            MinauError::NoTrack => write!(f, "No supported audio track found"),
+            MinauError::Decode(e) => write!(f, "Failed to decode: {}", e),
+            MinauError::Metadata(e) => write!(f, "Failed to read metadata: {}", e),
+            MinauError::Device(e) => write!(f, "Audio device error: {}", e),
+            MinauError::Http(e) => write!(f, "HTTP error: {}", e),
+            MinauError::Playlist(e) => write!(f, "Playlist error: {}", e),
+            MinauError::Seek(e) => write!(f, "Seek failed: {}", e),
+            MinauError::TagWrite(e) => write!(f, "Failed to write tags: {}", e),
+            MinauError::Terminal(e) => write!(f, "Failed to initialize terminal: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MinauError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MinauError::Io(e) | MinauError::Terminal(e) => Some(e),
+            MinauError::Probe(e) => Some(e),
+            MinauError::Metadata(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MinauError {
+    fn from(e: io::Error) -> Self {
+        MinauError::Io(e)
+    }
+}
+
+impl From<LoftyError> for MinauError {
+    fn from(e: LoftyError) -> Self {
+        MinauError::Metadata(e)
+    }
+}
@@ -0,0 +1,153 @@
+//! Guessing the container of a remote stream.
+//!
+//! The first bytes are checked for a known signature, then the
+//! `Content-Type` header and the extension of the URL. What none of them
+//! identifies is left to symphonia's own probe.
+
+use symphonia::core::probe::Hint;
+
+/// Containers recognised before probing. Codecs inside them are up to
+/// symphonia; Opus for one is found but has no decoder there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Mp3,
+    /// AAC in ADTS frames
+    Adts,
+    Flac,
+    /// Ogg with Vorbis, Opus or FLAC inside
+    Ogg,
+    Wav,
+    Aiff,
+    Caf,
+    /// MP4 / M4A, including ALAC
+    Mp4,
+    WebM,
+    Matroska,
+}
+
+impl Format {
+    /// Extension symphonia registers the matching reader under.
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::Adts => "aac",
+            Self::Flac => "flac",
+            Self::Ogg => "ogg",
+            Self::Wav => "wav",
+            Self::Aiff => "aiff",
+            Self::Caf => "caf",
+            Self::Mp4 => "m4a",
+            Self::WebM => "webm",
+            Self::Matroska => "mka",
+        }
+    }
+
+    fn from_signature(data: &[u8]) -> Option<Self> {
+        let tag = |range: std::ops::Range<usize>| data.get(range).unwrap_or_default();
+
+        if tag(0..3) == b"ID3" {
+            // タグの後ろがまだ届いていなければ Content-Type と拡張子に任せる
+            return Some(Self::from_signature(skip_id3(data)?).unwrap_or(Self::Mp3));
+        }
+        match tag(0..4) {
+            b"fLaC" => return Some(Self::Flac),
+            b"OggS" => return Some(Self::Ogg),
+            b"caff" => return Some(Self::Caf),
+            b"RIFF" if tag(8..12) == b"WAVE" => return Some(Self::Wav),
+            b"FORM" if matches!(tag(8..12), b"AIFF" | b"AIFC") => return Some(Self::Aiff),
+            [0x1a, 0x45, 0xdf, 0xa3] => {
+                // EBML ヘッダーの DocType で WebM と Matroska を分ける
+                let header = &data[..data.len().min(64)];
+                let webm = header.windows(4).any(|w| w == b"webm");
+                return Some(if webm { Self::WebM } else { Self::Matroska });
+            }
+            _ => {}
+        }
+        if matches!(tag(4..8), b"ftyp" | b"moov" | b"mdat" | b"free" | b"wide") {
+            return Some(Self::Mp4);
+        }
+        match data {
+            [0xff, b, ..] if b & 0xf6 == 0xf0 => Some(Self::Adts),
+            // フレーム同期。レイヤー 00 は予約
+            [0xff, b, ..] if b & 0xe0 == 0xe0 && b & 0x06 != 0 => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+
+    fn from_mime(mime: &str) -> Option<Self> {
+        let format = match mime {
+            "audio/mpeg" | "audio/mp3" | "audio/mpeg3" | "audio/x-mpeg" | "audio/x-mp3" => {
+                Self::Mp3
+            }
+            "audio/aac" | "audio/aacp" | "audio/x-aac" | "audio/x-hx-aac-adts" => Self::Adts,
+            "audio/flac" | "audio/x-flac" => Self::Flac,
+            "audio/ogg" | "application/ogg" | "audio/vorbis" | "audio/opus" => Self::Ogg,
+            "audio/wav" | "audio/wave" | "audio/x-wav" | "audio/vnd.wave" => Self::Wav,
+            "audio/aiff" | "audio/x-aiff" => Self::Aiff,
+            "audio/x-caf" => Self::Caf,
+            "audio/mp4" | "audio/x-m4a" | "audio/m4a" | "video/mp4" => Self::Mp4,
+            "audio/webm" | "video/webm" => Self::WebM,
+            "audio/x-matroska" | "video/x-matroska" => Self::Matroska,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    fn from_extension(ext: &str) -> Option<Self> {
+        let format = match ext {
+            "mp3" | "mp2" | "mpga" => Self::Mp3,
+            "aac" | "adts" => Self::Adts,
+            "flac" => Self::Flac,
+            "ogg" | "oga" | "opus" => Self::Ogg,
+            "wav" | "wave" => Self::Wav,
+            "aif" | "aiff" | "aifc" => Self::Aiff,
+            "caf" => Self::Caf,
+            "m4a" | "m4b" | "mp4" | "alac" => Self::Mp4,
+            "webm" => Self::WebM,
+            "mka" | "mkv" => Self::Matroska,
+            _ => return None,
+        };
+        Some(format)
+    }
+}
+
+/// Builds the hint for a stream served as `content_type` from `url` that
+/// starts with `data`.
+pub fn detect_hint(content_type: Option<&str>, url: &str, data: &[u8]) -> Hint {
+    // text/html; charset=... のようなパラメーターを落とす
+    let mime = content_type
+        .and_then(|t| t.split(';').next())
+        .map(|t| t.trim().to_ascii_lowercase())
+        .filter(|t| !t.is_empty());
+    let ext = url::Url::parse(url).ok().and_then(|url| {
+        let name = url.path_segments()?.next_back()?.to_string();
+        let (_, ext) = name.rsplit_once('.')?;
+        Some(ext.to_ascii_lowercase())
+    });
+
+    let format = Format::from_signature(data)
+        .or_else(|| mime.as_deref().and_then(Format::from_mime))
+        .or_else(|| ext.as_deref().and_then(Format::from_extension));
+
+    let mut hint = Hint::new();
+    if let Some(format) = format {
+        hint.with_extension(format.extension());
+    }
+    if let Some(mime) = &mime {
+        hint.mime_type(mime);
+    }
+    hint
+}
+
+/// Returns the bytes after an ID3v2 tag, or `None` if they are not in
+/// `data` yet.
+fn skip_id3(data: &[u8]) -> Option<&[u8]> {
+    let header = data.get(..10)?;
+    // サイズは 7 ビットずつの syncsafe 整数
+    let size = header[6..10]
+        .iter()
+        .fold(0usize, |size, &b| (size << 7) | usize::from(b & 0x7f));
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    data.get(10 + size + footer..)
+        .filter(|rest| rest.len() >= 12)
+}
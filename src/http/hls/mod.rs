@@ -0,0 +1,437 @@
+//! HTTP Live Streaming.
+//!
+//! A master playlist is resolved to one media playlist, whose segments are
+//! then fetched one after another. Live playlists are reloaded as they grow.
+//! Segments are turned into one continuous stream that symphonia can probe:
+//! MPEG-TS is demuxed to ADTS or MP3 frames, fMP4 segments follow their
+//! initialisation section, and packed audio is passed through.
+mod playlist;
+mod ts;
+
+pub use playlist::is_hls;
+
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use symphonia::core::probe::Hint;
+
+use self::playlist::{MediaPlaylist, Playlist, Segment};
+use self::ts::{TsCodec, TsDemuxer};
+use super::{HttpClient, RetryPolicy, StreamState, next_chunk, resolve};
+use crate::error::{MinauError, Result};
+
+/// Master playlists may point to further master playlists; give up after
+/// this many
+const MAX_PLAYLIST_DEPTH: usize = 4;
+/// How many segments from the end a live stream starts at
+const LIVE_START_SEGMENTS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SegmentFormat {
+    Ts,
+    Fmp4,
+    /// Raw ADTS or MP3 frames, possibly behind an ID3 tag
+    Packed,
+}
+
+/// A media playlist being played, segment by segment.
+pub struct HlsStream {
+    client: HttpClient,
+    /// URL of the media playlist, for reloads and relative segment URIs
+    url: String,
+    ended: bool,
+    target_duration: Duration,
+    queue: VecDeque<Segment>,
+    /// Sequence number of the next segment not queued yet
+    next_sequence: u64,
+    format: SegmentFormat,
+    ts: TsDemuxer,
+    /// URI of the initialisation section last sent
+    map: Option<String>,
+    /// Demuxed bytes of the first segment, fetched to find the format
+    first: Option<Vec<u8>>,
+    downloaded: Arc<Mutex<u64>>,
+    retry: RetryPolicy,
+    state: Arc<Mutex<StreamState>>,
+}
+
+impl HlsStream {
+    /// Starts playing the playlist `text` that was served from `url`.
+    /// Fetches the first segment so the format is known.
+    ///
+    /// Must run inside a Tokio context, e.g. wrapped in `async_compat::Compat`.
+    pub async fn open(
+        client: HttpClient,
+        url: &str,
+        text: &str,
+        retry: RetryPolicy,
+    ) -> Result<Self> {
+        let mut url = url.to_string();
+        let mut playlist = playlist::parse(text)?;
+
+        for _ in 0..MAX_PLAYLIST_DEPTH {
+            let Playlist::Master(master) = &playlist else {
+                break;
+            };
+            let uri = master
+                .select()
+                .ok_or_else(|| MinauError::Playlist("HLS playlist has no variants".into()))?;
+            url = resolve(&url, uri)?;
+            let text = fetch_bytes(&client, &url).await?;
+            playlist = playlist::parse(&String::from_utf8_lossy(&text))?;
+        }
+        let Playlist::Media(playlist) = playlist else {
+            return Err(MinauError::Playlist("HLS playlists nest too deeply".into()));
+        };
+
+        let mut stream = Self {
+            client,
+            url,
+            ended: false,
+            target_duration: Duration::ZERO,
+            queue: VecDeque::new(),
+            next_sequence: 0,
+            format: SegmentFormat::Packed,
+            ts: TsDemuxer::new(),
+            map: None,
+            first: None,
+            downloaded: Arc::new(Mutex::new(0)),
+            retry,
+            state: Arc::new(Mutex::new(StreamState::Streaming)),
+        };
+        stream.enqueue(playlist, true);
+
+        let Some(segment) = stream.queue.pop_front() else {
+            return Err(MinauError::Playlist("HLS playlist has no segments".into()));
+        };
+        let data = stream.fetch(&segment.uri).await?;
+        stream.format = detect_format(&data);
+        let first = stream.demux(&segment, data).await?;
+        // TS は PMT を見るまで何も出てこないことがある
+        if stream.format == SegmentFormat::Ts && stream.ts.codec().is_none() {
+            return Err(MinauError::Playlist(
+                "HLS segment has no supported audio stream".into(),
+            ));
+        }
+        stream.first = Some(first);
+        Ok(stream)
+    }
+
+    /// Counter of the segment bytes downloaded so far.
+    pub fn downloaded(&self) -> Arc<Mutex<u64>> {
+        Arc::clone(&self.downloaded)
+    }
+
+    /// State of the connection, e.g. while reconnecting.
+    pub fn state(&self) -> Arc<Mutex<StreamState>> {
+        Arc::clone(&self.state)
+    }
+
+    /// Tells symphonia which container the stream is in.
+    pub fn hint(&self) -> Hint {
+        let mut hint = Hint::new();
+        let first = self.first.as_deref().unwrap_or_default();
+        match self.format {
+            SegmentFormat::Ts => match self.ts.codec() {
+                Some(TsCodec::Mpeg) => hint.with_extension("mp3"),
+                _ => hint.with_extension("aac"),
+            },
+            SegmentFormat::Fmp4 => hint.with_extension("mp4"),
+            SegmentFormat::Packed if is_adts(first) => hint.with_extension("aac"),
+            SegmentFormat::Packed => hint.with_extension("mp3"),
+        };
+        hint
+    }
+
+    /// Returns the stream data of the next segment, waiting for live
+    /// playlists to grow. Returns `None` once a finished playlist has been
+    /// played to the end.
+    ///
+    /// Must run inside a Tokio context, e.g. wrapped in `async_compat::Compat`.
+    pub async fn next_segment(&mut self) -> Result<Option<Vec<u8>>> {
+        if let Some(first) = self.first.take() {
+            return Ok(Some(first));
+        }
+
+        loop {
+            if let Some(segment) = self.queue.pop_front() {
+                let data = self.fetch(&segment.uri).await?;
+                let data = self.demux(&segment, data).await?;
+                return Ok(Some(data));
+            }
+            if self.ended {
+                return Ok(None);
+            }
+
+            // 新しいセグメントが出るまで待ってから読み直す
+            smol::Timer::after(self.target_duration.max(Duration::from_secs(1)) / 2).await;
+            let text = self.fetch(&self.url.clone()).await?;
+            match playlist::parse(&String::from_utf8_lossy(&text))? {
+                Playlist::Media(playlist) => self.enqueue(playlist, false),
+                Playlist::Master(_) => {
+                    return Err(MinauError::Playlist(
+                        "HLS media playlist turned into a master playlist".into(),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Queues the segments of `playlist` that have not been queued yet.
+    fn enqueue(&mut self, playlist: MediaPlaylist, initial: bool) {
+        self.ended = playlist.ended;
+        self.target_duration = playlist.target_duration;
+
+        let mut segments = playlist.segments;
+        if initial && !playlist.ended {
+            // ライブは終端の少し手前から始める
+            let skip = segments.len().saturating_sub(LIVE_START_SEGMENTS);
+            segments.drain(..skip);
+        }
+        for segment in segments {
+            if !initial && segment.sequence < self.next_sequence {
+                continue;
+            }
+            self.next_sequence = segment.sequence + 1;
+            self.queue.push_back(segment);
+        }
+    }
+
+    /// Fetches `uri`, relative to the media playlist, retrying as the
+    /// policy allows.
+    async fn fetch(&self, uri: &str) -> Result<Vec<u8>> {
+        let url = resolve(&self.url, uri)?;
+        let mut attempt = 0;
+        loop {
+            match fetch_bytes(&self.client, &url).await {
+                Ok(data) => {
+                    *self.downloaded.lock() += data.len() as u64;
+                    *self.state.lock() = StreamState::Streaming;
+                    return Ok(data);
+                }
+                Err(e) => {
+                    attempt += 1;
+                    if !self.retry.wait(attempt, &self.state).await {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Turns a fetched segment into stream data.
+    async fn demux(&mut self, segment: &Segment, data: Vec<u8>) -> Result<Vec<u8>> {
+        if segment.discontinuity {
+            self.ts = TsDemuxer::new();
+        }
+        match self.format {
+            SegmentFormat::Ts => Ok(self.ts.feed(&data)),
+            SegmentFormat::Fmp4 => {
+                // 初期化セクションは変わったときだけ送る
+                let mut out = Vec::new();
+                if segment.map.is_some() && segment.map != self.map {
+                    let map = segment.map.clone().unwrap_or_default();
+                    out = self.fetch(&map).await?;
+                    self.map = segment.map.clone();
+                }
+                out.extend_from_slice(&data);
+                Ok(out)
+            }
+            SegmentFormat::Packed => Ok(strip_id3(&data).to_vec()),
+        }
+    }
+}
+
+async fn fetch_bytes(client: &HttpClient, url: &str) -> Result<Vec<u8>> {
+    let mut body = super::get(client, url, None).await?.response.into_body();
+    let mut data = Vec::new();
+    while let Some(chunk) = next_chunk(&mut body, client.read_timeout()).await {
+        data.extend_from_slice(&chunk?);
+    }
+    Ok(data)
+}
+
+fn detect_format(data: &[u8]) -> SegmentFormat {
+    if data.first() == Some(&0x47) && data.get(188).is_none_or(|&b| b == 0x47) {
+        SegmentFormat::Ts
+    } else if data
+        .get(4..8)
+        .is_some_and(|kind| matches!(kind, b"ftyp" | b"styp" | b"moof" | b"sidx"))
+    {
+        SegmentFormat::Fmp4
+    } else {
+        SegmentFormat::Packed
+    }
+}
+
+fn is_adts(data: &[u8]) -> bool {
+    data.len() >= 2 && data[0] == 0xff && data[1] & 0xf6 == 0xf0
+}
+
+/// Packed audio segments start with an ID3 tag holding their timestamp.
+fn strip_id3(data: &[u8]) -> &[u8] {
+    if data.len() < 10 || &data[..3] != b"ID3" {
+        return data;
+    }
+    // サイズは 7 ビットずつの syncsafe 整数
+    let size = data[6..10]
+        .iter()
+        .fold(0usize, |size, &b| (size << 7) | usize::from(b & 0x7f));
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    data.get(10 + size + footer..).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpOptions;
+    use crate::http::test_server::{TestResponse, TestServer};
+    use async_compat::CompatExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A segment body whose bytes name the segment.
+    fn segment(name: &str) -> Vec<u8> {
+        let mut data = vec![0xff, 0xf1];
+        data.extend_from_slice(name.as_bytes());
+        data
+    }
+
+    fn open(server: &TestServer, path: &str) -> HlsStream {
+        let client = HttpClient::new(HttpOptions::default()).unwrap();
+        let url = server.url(path);
+        smol::block_on(
+            async {
+                let text = fetch_bytes(&client, &url).await?;
+                let text = String::from_utf8_lossy(&text).into_owned();
+                HlsStream::open(client, &url, &text, RetryPolicy::default()).await
+            }
+            .compat(),
+        )
+        .unwrap()
+    }
+
+    fn read_all(stream: &mut HlsStream) -> Vec<Vec<u8>> {
+        let mut segments = Vec::new();
+        smol::block_on(
+            async {
+                while let Some(data) = stream.next_segment().await.unwrap() {
+                    segments.push(data);
+                }
+            }
+            .compat(),
+        );
+        segments
+    }
+
+    #[test]
+    fn detects_segment_format() {
+        let mut ts = vec![0u8; 376];
+        ts[0] = 0x47;
+        ts[188] = 0x47;
+        assert_eq!(detect_format(&ts), SegmentFormat::Ts);
+        assert_eq!(detect_format(b"\0\0\0\x18ftypiso6"), SegmentFormat::Fmp4);
+        assert_eq!(
+            detect_format(b"\0\0\0\x10moof\0\0\0\0"),
+            SegmentFormat::Fmp4
+        );
+        assert_eq!(detect_format(&[0xff, 0xf1, 0x50]), SegmentFormat::Packed);
+        assert_eq!(detect_format(b"ID3\x04\0\0\0\0\0\0"), SegmentFormat::Packed);
+    }
+
+    #[test]
+    fn strips_id3_tag() {
+        let mut data = b"ID3\x04\0\0\0\0\x01\x00".to_vec();
+        data.extend_from_slice(&[0; 128]);
+        data.extend_from_slice(&[0xff, 0xf1, 0x50]);
+        assert_eq!(strip_id3(&data), [0xff, 0xf1, 0x50]);
+        assert_eq!(strip_id3(&[0xff, 0xfb]), [0xff, 0xfb]);
+    }
+
+    #[test]
+    fn plays_media_playlist_of_master() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/master.m3u8" => TestResponse::ok(
+                "#EXTM3U\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS=\"mp4a.40.2\"\n\
+                 lo/index.m3u8\n\
+                 #EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\"\n\
+                 hi/index.m3u8\n",
+            ),
+            "/hi/index.m3u8" => TestResponse::ok(
+                "#EXTM3U\n#EXT-X-TARGETDURATION:2\n\
+                 #EXTINF:2,\na.aac\n#EXTINF:2,\nb.aac\n#EXT-X-ENDLIST\n",
+            ),
+            "/hi/a.aac" => {
+                let mut data = b"ID3\x04\0\0\0\0\0\x04tag!".to_vec();
+                data.extend(segment("a"));
+                TestResponse::ok(data)
+            }
+            "/hi/b.aac" => TestResponse::ok(segment("b")),
+            _ => TestResponse::not_found(),
+        });
+
+        let mut stream = open(&server, "/master.m3u8");
+        assert_eq!(stream.format, SegmentFormat::Packed);
+        assert_eq!(read_all(&mut stream), [segment("a"), segment("b")]);
+        assert_eq!(
+            *stream.downloaded().lock(),
+            (14 + segment("a").len() + segment("b").len()) as u64
+        );
+    }
+
+    #[test]
+    fn sends_fmp4_map_when_it_changes() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/index.m3u8" => TestResponse::ok(
+                "#EXTM3U\n#EXT-X-TARGETDURATION:2\n\
+                 #EXT-X-MAP:URI=\"init1.mp4\"\n\
+                 #EXTINF:2,\n1.m4s\n#EXTINF:2,\n2.m4s\n\
+                 #EXT-X-DISCONTINUITY\n#EXT-X-MAP:URI=\"init2.mp4\"\n\
+                 #EXTINF:2,\n3.m4s\n#EXT-X-ENDLIST\n",
+            ),
+            path => TestResponse::ok(format!("\0\0\0\x08moof{}", path)),
+        });
+
+        let mut stream = open(&server, "/index.m3u8");
+        assert_eq!(stream.format, SegmentFormat::Fmp4);
+        let segments: Vec<String> = read_all(&mut stream)
+            .iter()
+            .map(|data| String::from_utf8_lossy(data).replace("\0\0\0\x08moof", "|"))
+            .collect();
+        assert_eq!(
+            segments,
+            ["|/init1.mp4|/1.m4s", "|/2.m4s", "|/init2.mp4|/3.m4s"]
+        );
+    }
+
+    #[test]
+    fn reloads_live_playlist() {
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let count = Arc::clone(&reloads);
+        let server = TestServer::start(move |req| {
+            if req.path != "/live.m3u8" {
+                return TestResponse::ok(segment(&req.path));
+            }
+            // 2 回目からは新しいセグメントが増えて終わる
+            let (last, end) = match count.fetch_add(1, Ordering::SeqCst) {
+                0 => (14, ""),
+                _ => (16, "#EXT-X-ENDLIST\n"),
+            };
+            let mut text =
+                "#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:10\n".to_string();
+            for n in 10..=last {
+                text.push_str(&format!("#EXTINF:1,\n{}.aac\n", n));
+            }
+            TestResponse::ok(text + end)
+        });
+
+        let mut stream = open(&server, "/live.m3u8");
+        let names: Vec<_> = ["/12.aac", "/13.aac", "/14.aac", "/15.aac", "/16.aac"]
+            .iter()
+            .map(|name| segment(name))
+            .collect();
+        assert_eq!(read_all(&mut stream), names);
+        assert_eq!(reloads.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,343 @@
+//! Parsing of HLS master and media playlists (RFC 8216).
+
+use std::time::Duration;
+
+use crate::error::{MinauError, Result};
+
+/// Video codecs, used to tell audio-only variants apart
+const VIDEO_CODECS: [&str; 8] = [
+    "avc1", "avc3", "hvc1", "hev1", "dvh1", "vp09", "av01", "mp4v",
+];
+
+pub enum Playlist {
+    Master(MasterPlaylist),
+    Media(MediaPlaylist),
+}
+
+/// The variants and alternative renditions offered by a master playlist.
+#[derive(Debug, Default)]
+pub struct MasterPlaylist {
+    pub variants: Vec<Variant>,
+    pub audio: Vec<Rendition>,
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    pub uri: String,
+    /// Peak bits per second
+    pub bandwidth: u64,
+    pub codecs: Option<String>,
+    /// `GROUP-ID` of the audio renditions to use with this variant
+    pub audio_group: Option<String>,
+}
+
+/// An `#EXT-X-MEDIA` entry of type `AUDIO`.
+#[derive(Debug)]
+pub struct Rendition {
+    pub group: String,
+    pub uri: Option<String>,
+    pub default: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct MediaPlaylist {
+    pub target_duration: Duration,
+    pub segments: Vec<Segment>,
+    /// Whether `#EXT-X-ENDLIST` was seen, i.e. no segments will be added
+    pub ended: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub uri: String,
+    /// Media sequence number
+    pub sequence: u64,
+    /// Initialisation section (`#EXT-X-MAP`) for fMP4 segments
+    pub map: Option<String>,
+    /// Follows an `#EXT-X-DISCONTINUITY` tag
+    pub discontinuity: bool,
+}
+
+/// Returns true if `text` is an HLS playlist rather than a list of files.
+pub fn is_hls(text: &str) -> bool {
+    text.lines().any(|line| {
+        let line = line.trim();
+        line.starts_with("#EXT-X-TARGETDURATION") || line.starts_with("#EXT-X-STREAM-INF")
+    })
+}
+
+pub fn parse(text: &str) -> Result<Playlist> {
+    if text
+        .lines()
+        .any(|l| l.trim().starts_with("#EXT-X-STREAM-INF"))
+    {
+        Ok(Playlist::Master(parse_master(text)))
+    } else {
+        parse_media(text).map(Playlist::Media)
+    }
+}
+
+fn parse_master(text: &str) -> MasterPlaylist {
+    let mut playlist = MasterPlaylist::default();
+    let mut pending: Option<Vec<(String, String)>> = None;
+
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            pending = Some(attributes(attrs));
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attrs = attributes(attrs);
+            if attr(&attrs, "TYPE") != Some("AUDIO") {
+                continue;
+            }
+            playlist.audio.push(Rendition {
+                group: attr(&attrs, "GROUP-ID").unwrap_or_default().to_string(),
+                uri: attr(&attrs, "URI").map(String::from),
+                default: attr(&attrs, "DEFAULT") == Some("YES"),
+            });
+        } else if !line.starts_with('#')
+            && let Some(attrs) = pending.take()
+        {
+            playlist.variants.push(Variant {
+                uri: line.to_string(),
+                bandwidth: attr(&attrs, "BANDWIDTH")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0),
+                codecs: attr(&attrs, "CODECS").map(String::from),
+                audio_group: attr(&attrs, "AUDIO").map(String::from),
+            });
+        }
+    }
+    playlist
+}
+
+fn parse_media(text: &str) -> Result<MediaPlaylist> {
+    let mut playlist = MediaPlaylist::default();
+    let mut sequence = 0;
+    let mut map = None;
+    let mut discontinuity = false;
+
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            let secs: f64 = value.trim().parse().unwrap_or(0.0);
+            playlist.target_duration = Duration::from_secs_f64(secs.max(0.0));
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            sequence = value.trim().parse().unwrap_or(0);
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MAP:") {
+            if attr(&attributes(attrs), "BYTERANGE").is_some() {
+                return Err(unsupported("byte range initialisation sections"));
+            }
+            map = attr(&attributes(attrs), "URI").map(String::from);
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-KEY:") {
+            if attr(&attributes(attrs), "METHOD").is_some_and(|m| m != "NONE") {
+                return Err(unsupported("encrypted segments"));
+            }
+        } else if line.starts_with("#EXT-X-BYTERANGE") {
+            return Err(unsupported("byte range segments"));
+        } else if line == "#EXT-X-DISCONTINUITY" {
+            discontinuity = true;
+        } else if line == "#EXT-X-ENDLIST" {
+            playlist.ended = true;
+        } else if !line.starts_with('#') {
+            playlist.segments.push(Segment {
+                uri: line.to_string(),
+                sequence,
+                map: map.clone(),
+                discontinuity: std::mem::take(&mut discontinuity),
+            });
+            sequence += 1;
+        }
+    }
+    Ok(playlist)
+}
+
+fn unsupported(what: &str) -> MinauError {
+    MinauError::Playlist(format!("HLS {} are not supported", what))
+}
+
+impl MasterPlaylist {
+    /// Picks the URI of the media playlist to play: the highest bandwidth
+    /// variant, preferring audio-only ones, or its audio rendition if it has
+    /// a separate one.
+    pub fn select(&self) -> Option<&str> {
+        let variant = self
+            .variants
+            .iter()
+            .max_by_key(|v| (v.is_audio_only(), v.bandwidth))?;
+
+        // 音声が別のプレイリストになっていればそちらを使う
+        let rendition = variant.audio_group.as_ref().and_then(|group| {
+            let mut group = self
+                .audio
+                .iter()
+                .filter(|r| &r.group == group && r.uri.is_some());
+            let first = group.clone().next();
+            group.find(|r| r.default).or(first)
+        });
+        match rendition.and_then(|r| r.uri.as_deref()) {
+            Some(uri) => Some(uri),
+            None => Some(&variant.uri),
+        }
+    }
+}
+
+impl Variant {
+    fn is_audio_only(&self) -> bool {
+        self.codecs.as_deref().is_some_and(|codecs| {
+            !codecs
+                .split(',')
+                .any(|codec| VIDEO_CODECS.iter().any(|v| codec.trim().starts_with(v)))
+        })
+    }
+}
+
+/// Splits an attribute list: `KEY=VALUE,KEY="quoted, value"`.
+fn attributes(list: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut rest = list.trim();
+
+    while !rest.is_empty() {
+        let Some((key, value)) = rest.split_once('=') else {
+            break;
+        };
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let after = quoted.get(end + 1..).unwrap_or("");
+                (&quoted[..end], after)
+            }
+            None => match value.find(',') {
+                Some(end) => (&value[..end], &value[end..]),
+                None => (value, ""),
+            },
+        };
+        attrs.push((key.trim().to_string(), value.to_string()));
+        rest = next.trim_start_matches(',').trim();
+    }
+    attrs
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=1280000,CODECS=\"avc1.4d401f,mp4a.40.2\",RESOLUTION=1280x720
+video-hi.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS=\"mp4a.40.5\"
+audio-lo.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\"
+audio-hi.m3u8
+";
+
+    fn media(text: &str) -> MediaPlaylist {
+        match parse(text).unwrap() {
+            Playlist::Media(playlist) => playlist,
+            Playlist::Master(_) => panic!("parsed as a master playlist"),
+        }
+    }
+
+    fn master(text: &str) -> MasterPlaylist {
+        match parse(text).unwrap() {
+            Playlist::Master(playlist) => playlist,
+            Playlist::Media(_) => panic!("parsed as a media playlist"),
+        }
+    }
+
+    #[test]
+    fn detects_hls() {
+        assert!(is_hls(MASTER));
+        assert!(is_hls("#EXTM3U\n#EXT-X-TARGETDURATION:6\nseg0.ts\n"));
+        assert!(!is_hls("#EXTM3U\n#EXTINF:120,Song\nsong.mp3\n"));
+    }
+
+    #[test]
+    fn tells_master_from_media() {
+        assert_eq!(master(MASTER).variants.len(), 3);
+        let playlist = media("#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6,\nseg0.ts\n");
+        assert_eq!(playlist.segments.len(), 1);
+    }
+
+    #[test]
+    fn selects_highest_audio_only_variant() {
+        assert_eq!(master(MASTER).select(), Some("audio-hi.m3u8"));
+    }
+
+    #[test]
+    fn selects_highest_bandwidth_without_codecs() {
+        let text = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=96000
+mid.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=256000
+hi.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=32000
+lo.m3u8
+";
+        assert_eq!(master(text).select(), Some("hi.m3u8"));
+    }
+
+    #[test]
+    fn selects_default_audio_rendition() {
+        let text = "#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"English\",URI=\"en.m3u8\"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"Japanese\",DEFAULT=YES,URI=\"ja.m3u8\"
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"sub\",NAME=\"English\",URI=\"subs.m3u8\"
+#EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc1.4d401f,mp4a.40.2\",AUDIO=\"aud\"
+video.m3u8
+";
+        let playlist = master(text);
+        assert_eq!(playlist.audio.len(), 2);
+        assert_eq!(playlist.select(), Some("ja.m3u8"));
+    }
+
+    #[test]
+    fn parses_media_segments() {
+        let playlist = media(
+            "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-MEDIA-SEQUENCE:7
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:4.0,
+a.m4s
+#EXT-X-DISCONTINUITY
+#EXTINF:4.0,
+b.m4s
+#EXT-X-ENDLIST
+",
+        );
+        assert_eq!(playlist.target_duration, Duration::from_secs(4));
+        assert!(playlist.ended);
+        let uris: Vec<_> = playlist.segments.iter().map(|s| s.uri.as_str()).collect();
+        assert_eq!(uris, ["a.m4s", "b.m4s"]);
+        assert_eq!(playlist.segments[0].sequence, 7);
+        assert_eq!(playlist.segments[1].sequence, 8);
+        assert_eq!(playlist.segments[1].map.as_deref(), Some("init.mp4"));
+        assert!(!playlist.segments[0].discontinuity);
+        assert!(playlist.segments[1].discontinuity);
+    }
+
+    #[test]
+    fn rejects_encryption_and_byte_ranges() {
+        let encrypted = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key\"\nseg0.ts\n";
+        assert!(parse(encrypted).is_err());
+        let unencrypted = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXT-X-KEY:METHOD=NONE\nseg0.ts\n";
+        assert!(parse(unencrypted).is_ok());
+        let ranged = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXT-X-BYTERANGE:1000@0\nseg0.ts\n";
+        assert!(parse(ranged).is_err());
+    }
+
+    #[test]
+    fn splits_quoted_attributes() {
+        let attrs = attributes("BANDWIDTH=1000,CODECS=\"mp4a.40.2,avc1.4d401f\",NAME=x");
+        assert_eq!(attr(&attrs, "BANDWIDTH"), Some("1000"));
+        assert_eq!(attr(&attrs, "CODECS"), Some("mp4a.40.2,avc1.4d401f"));
+        assert_eq!(attr(&attrs, "NAME"), Some("x"));
+    }
+}
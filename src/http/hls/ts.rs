@@ -0,0 +1,258 @@
+//! Extraction of the audio elementary stream from MPEG transport streams.
+//!
+//! Only what HLS audio needs is handled: the first program, and its first
+//! AAC (ADTS) or MPEG audio stream. The payload of that stream is returned as
+//! plain ADTS or MP3 frames, which symphonia can read directly.
+
+const PACKET_LEN: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+
+/// Codec of the audio stream carried in the transport stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TsCodec {
+    /// AAC in ADTS frames
+    Aac,
+    /// MPEG-1/2 audio (MP3)
+    Mpeg,
+}
+
+#[derive(Default)]
+pub struct TsDemuxer {
+    /// Bytes of a packet split across two calls
+    partial: Vec<u8>,
+    pmt_pid: Option<u16>,
+    audio: Option<(u16, TsCodec)>,
+}
+
+impl TsDemuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The codec of the audio stream, once the program table has been seen.
+    pub fn codec(&self) -> Option<TsCodec> {
+        self.audio.map(|(_, codec)| codec)
+    }
+
+    /// Returns the audio payload carried by `data`.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<u8> {
+        self.partial.extend_from_slice(data);
+        let mut out = Vec::new();
+        let mut pos = 0;
+
+        while self.partial.len() - pos >= PACKET_LEN {
+            // 同期バイトがずれていたら探し直す
+            if self.partial[pos] != SYNC_BYTE {
+                pos += 1;
+                continue;
+            }
+            let packet: [u8; PACKET_LEN] = self.partial[pos..pos + PACKET_LEN]
+                .try_into()
+                .expect("slice has packet length");
+            self.packet(&packet, &mut out);
+            pos += PACKET_LEN;
+        }
+        self.partial.drain(..pos);
+        out
+    }
+
+    fn packet(&mut self, packet: &[u8; PACKET_LEN], out: &mut Vec<u8>) {
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from(packet[1] & 0x1f) << 8 | u16::from(packet[2]);
+        let adaptation = (packet[3] >> 4) & 0x3;
+
+        let mut start = 4;
+        if adaptation & 0x2 != 0 {
+            start += 1 + packet[4] as usize;
+        }
+        if adaptation & 0x1 == 0 || start >= PACKET_LEN {
+            return;
+        }
+        let payload = &packet[start..];
+
+        if pid == 0 {
+            if unit_start && let Some(pmt_pid) = parse_pat(payload) {
+                self.pmt_pid = Some(pmt_pid);
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if unit_start && let Some(audio) = parse_pmt(payload) {
+                self.audio = Some(audio);
+            }
+        } else if self.audio.is_some_and(|(audio, _)| audio == pid) {
+            if unit_start {
+                out.extend_from_slice(pes_payload(payload).unwrap_or_default());
+            } else {
+                out.extend_from_slice(payload);
+            }
+        }
+    }
+}
+
+/// The table section following the pointer field, cut to its length
+/// without the CRC.
+fn section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let table = payload.get(1 + pointer..)?;
+    let len = (usize::from(table.get(1)? & 0x0f) << 8) | usize::from(*table.get(2)?);
+    table.get(..(3 + len).checked_sub(4)?)
+}
+
+/// Returns the PID of the first program's map table.
+fn parse_pat(payload: &[u8]) -> Option<u16> {
+    let table = section(payload)?;
+    table.get(8..)?.chunks_exact(4).find_map(|entry| {
+        let program = u16::from_be_bytes([entry[0], entry[1]]);
+        // プログラム番号 0 はネットワーク情報
+        (program != 0).then(|| u16::from(entry[2] & 0x1f) << 8 | u16::from(entry[3]))
+    })
+}
+
+/// Returns the PID and codec of the first supported audio stream.
+fn parse_pmt(payload: &[u8]) -> Option<(u16, TsCodec)> {
+    let table = section(payload)?;
+    let info_len = (usize::from(table.get(10)? & 0x0f) << 8) | usize::from(*table.get(11)?);
+    let mut streams = table.get(12 + info_len..)?;
+
+    while streams.len() >= 5 {
+        let stream_type = streams[0];
+        let pid = u16::from(streams[1] & 0x1f) << 8 | u16::from(streams[2]);
+        let es_info_len = (usize::from(streams[3] & 0x0f) << 8) | usize::from(streams[4]);
+
+        let codec = match stream_type {
+            0x0f => Some(TsCodec::Aac),
+            0x03 | 0x04 => Some(TsCodec::Mpeg),
+            _ => None,
+        };
+        if let Some(codec) = codec {
+            return Some((pid, codec));
+        }
+        streams = streams.get(5 + es_info_len..)?;
+    }
+    None
+}
+
+/// Skips the PES header at the start of a packetised elementary stream.
+fn pes_payload(payload: &[u8]) -> Option<&[u8]> {
+    if payload.get(..3)? != [0, 0, 1] {
+        return None;
+    }
+    let header_len = *payload.get(8)? as usize;
+    payload.get(9 + header_len..)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PMT_PID: u16 = 0x1000;
+    const VIDEO_PID: u16 = 0x100;
+    const AUDIO_PID: u16 = 0x101;
+
+    /// One packet of `pid`, its payload padded with adaptation stuffing.
+    fn packet(pid: u16, unit_start: bool, payload: &[u8]) -> Vec<u8> {
+        assert!(payload.len() <= PACKET_LEN - 4);
+        let mut packet = vec![
+            SYNC_BYTE,
+            u8::from(unit_start) << 6 | (pid >> 8) as u8,
+            pid as u8,
+        ];
+        let stuffing = PACKET_LEN - 4 - payload.len();
+        if stuffing == 0 {
+            packet.push(0x10);
+        } else {
+            packet.push(0x30);
+            packet.push((stuffing - 1) as u8);
+            if stuffing > 1 {
+                packet.push(0);
+                packet.resize(packet.len() + stuffing - 2, 0xff);
+            }
+        }
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// A table section behind a zero pointer field, with a dummy CRC.
+    fn table(id: u8, header: &[u8], body: &[u8]) -> Vec<u8> {
+        let len = header.len() + body.len() + 4;
+        let mut section = vec![0, id, 0xb0 | (len >> 8) as u8, len as u8];
+        section.extend_from_slice(header);
+        section.extend_from_slice(body);
+        section.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        section
+    }
+
+    fn pat() -> Vec<u8> {
+        let body = [
+            0,
+            0,
+            0xe0,
+            0x10, // ネットワーク情報
+            0,
+            1,
+            0xe0 | (PMT_PID >> 8) as u8,
+            PMT_PID as u8,
+        ];
+        packet(0, true, &table(0, &[0, 1, 0xc1, 0, 0], &body))
+    }
+
+    fn pmt(audio_type: u8) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (stream_type, pid) in [(0x1b, VIDEO_PID), (audio_type, AUDIO_PID)] {
+            body.extend_from_slice(&[stream_type, 0xe0 | (pid >> 8) as u8, pid as u8, 0xf0, 0]);
+        }
+        let header = [0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0];
+        packet(PMT_PID, true, &table(2, &header, &body))
+    }
+
+    /// Splits `data` into the packets of one PES packet.
+    fn pes(pid: u16, data: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0, 0, 1, 0xc0, 0, 0, 0x80, 0x80, 5, 0x21, 0, 1, 0, 1];
+        pes.extend_from_slice(data);
+        pes.chunks(PACKET_LEN - 4)
+            .enumerate()
+            .flat_map(|(i, chunk)| packet(pid, i == 0, chunk))
+            .collect()
+    }
+
+    fn stream(audio_type: u8, audio: &[u8]) -> Vec<u8> {
+        let mut stream = pat();
+        stream.extend(pmt(audio_type));
+        stream.extend(pes(VIDEO_PID, &[0x55; 300]));
+        stream.extend(pes(AUDIO_PID, audio));
+        stream
+    }
+
+    fn audio() -> Vec<u8> {
+        (0..1000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn extracts_aac_payload() {
+        let mut demuxer = TsDemuxer::new();
+        assert_eq!(demuxer.codec(), None);
+        assert_eq!(demuxer.feed(&stream(0x0f, &audio())), audio());
+        assert_eq!(demuxer.codec(), Some(TsCodec::Aac));
+    }
+
+    #[test]
+    fn detects_mpeg_audio() {
+        let mut demuxer = TsDemuxer::new();
+        demuxer.feed(&stream(0x03, &audio()));
+        assert_eq!(demuxer.codec(), Some(TsCodec::Mpeg));
+    }
+
+    #[test]
+    fn joins_packets_split_across_feeds() {
+        let data = stream(0x0f, &audio());
+        let mut demuxer = TsDemuxer::new();
+        let out: Vec<u8> = data.chunks(100).flat_map(|c| demuxer.feed(c)).collect();
+        assert_eq!(out, audio());
+    }
+
+    #[test]
+    fn resynchronises_after_garbage() {
+        let mut data = vec![0x00, 0x12, 0x34];
+        data.extend(stream(0x0f, &audio()));
+        assert_eq!(TsDemuxer::new().feed(&data), audio());
+    }
+}
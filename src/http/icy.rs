@@ -0,0 +1,258 @@
+//! Shoutcast / Icecast in-band metadata.
+//!
+//! A server that is sent `Icy-MetaData: 1` may answer with an `icy-metaint`
+//! header. The body then carries a metadata block after every `metaint`
+//! audio bytes: one length byte (in units of 16 bytes) followed by text such
+//! as `StreamTitle='Artist - Song';`.
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+use super::Fetched;
+
+/// Station details from the response headers and the current song from the
+/// metadata blocks, shared with the thread reading the body.
+#[derive(Clone)]
+pub struct IcyMetadata {
+    shared: Arc<Mutex<IcyInfo>>,
+}
+
+struct IcyInfo {
+    name: Option<String>,
+    genre: Option<String>,
+    bitrate: Option<u32>,
+    stream_title: Option<String>,
+}
+
+impl IcyMetadata {
+    /// Reads the `icy-*` headers of a response. Returns `None` if the server
+    /// sent none of them.
+    pub fn from_response(fetched: &Fetched) -> Option<Self> {
+        let header = |name: &str| {
+            fetched
+                .response
+                .headers()
+                .get(name)
+                .map(|v| decode_text(v.as_bytes()).trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let info = IcyInfo {
+            name: header("icy-name"),
+            genre: header("icy-genre"),
+            // "128" のほか "128,s" のような表記もある
+            bitrate: header("icy-br")
+                .and_then(|v| v.split(',').next().and_then(|v| v.trim().parse().ok())),
+            stream_title: None,
+        };
+        if info.name.is_none()
+            && info.genre.is_none()
+            && info.bitrate.is_none()
+            && fetched.icy_metaint().is_none()
+        {
+            return None;
+        }
+        Some(Self {
+            shared: Arc::new(Mutex::new(info)),
+        })
+    }
+
+    pub fn get_name(&self) -> Option<String> {
+        self.shared.lock().name.clone()
+    }
+
+    pub fn get_genre(&self) -> Option<String> {
+        self.shared.lock().genre.clone()
+    }
+
+    /// Bitrate in kbit/s as announced by the server
+    pub fn get_bitrate(&self) -> Option<u32> {
+        self.shared.lock().bitrate
+    }
+
+    /// The song currently playing, from the last `StreamTitle`
+    pub fn get_stream_title(&self) -> Option<String> {
+        self.shared.lock().stream_title.clone()
+    }
+
+    fn set_stream_title(&self, title: String) {
+        let title = Some(title).filter(|t| !t.trim().is_empty());
+        self.shared.lock().stream_title = title;
+    }
+}
+
+/// Separates the metadata blocks from the audio bytes of an ICY body.
+pub struct IcyDemuxer {
+    metaint: usize,
+    metadata: IcyMetadata,
+    /// Audio bytes left before the next length byte
+    audio_left: usize,
+    /// Length of the block being read, once its length byte has been seen
+    block_len: Option<usize>,
+    block: Vec<u8>,
+}
+
+impl IcyDemuxer {
+    pub fn new(metaint: usize, metadata: IcyMetadata) -> Self {
+        Self {
+            metaint,
+            metadata,
+            audio_left: metaint,
+            block_len: None,
+            block: Vec::new(),
+        }
+    }
+
+    /// Returns the audio part of `chunk`. Completed metadata blocks update
+    /// the shared [`IcyMetadata`].
+    pub fn feed(&mut self, mut chunk: &[u8]) -> Vec<u8> {
+        let mut audio = Vec::with_capacity(chunk.len());
+
+        while !chunk.is_empty() {
+            if self.audio_left > 0 {
+                let n = self.audio_left.min(chunk.len());
+                audio.extend_from_slice(&chunk[..n]);
+                self.audio_left -= n;
+                chunk = &chunk[n..];
+                continue;
+            }
+
+            let len = match self.block_len {
+                Some(len) => len,
+                None => {
+                    let len = chunk[0] as usize * 16;
+                    chunk = &chunk[1..];
+                    self.block_len = Some(len);
+                    len
+                }
+            };
+
+            let n = (len - self.block.len()).min(chunk.len());
+            self.block.extend_from_slice(&chunk[..n]);
+            chunk = &chunk[n..];
+
+            if self.block.len() == len {
+                // 長さ 0 のブロックは「変更なし」
+                if let Some(title) = parse_stream_title(&self.block) {
+                    self.metadata.set_stream_title(title);
+                }
+                self.block.clear();
+                self.block_len = None;
+                self.audio_left = self.metaint;
+            }
+        }
+        audio
+    }
+}
+
+/// Extracts the `StreamTitle` value from a metadata block.
+fn parse_stream_title(block: &[u8]) -> Option<String> {
+    let text = decode_text(block);
+    let text = text.trim_end_matches('\0');
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &text[start..];
+    // タイトル内の ' を誤って終端と見なさないように "';" を探す
+    let end = rest
+        .find("';")
+        .unwrap_or_else(|| rest.trim_end_matches('\'').len());
+    Some(rest[..end].trim().to_string())
+}
+
+/// Metadata is usually UTF-8, but older servers send Latin-1.
+fn decode_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> IcyMetadata {
+        IcyMetadata {
+            shared: Arc::new(Mutex::new(IcyInfo {
+                name: None,
+                genre: None,
+                bitrate: None,
+                stream_title: None,
+            })),
+        }
+    }
+
+    /// A metadata block padded to a multiple of 16 bytes, with its length byte.
+    fn block(text: &str) -> Vec<u8> {
+        let mut block = text.as_bytes().to_vec();
+        block.resize(text.len().div_ceil(16) * 16, 0);
+        block.insert(0, (block.len() / 16) as u8);
+        block
+    }
+
+    #[test]
+    fn reads_block_split_across_chunks() {
+        let mut body = b"abcd".to_vec();
+        body.extend(block("StreamTitle='Artist - Song';"));
+        body.extend_from_slice(b"efgh");
+
+        // 長さバイトやブロックの途中で区切られても同じ結果になる
+        for split in 1..body.len() {
+            let metadata = metadata();
+            let mut demuxer = IcyDemuxer::new(4, metadata.clone());
+            let mut audio = demuxer.feed(&body[..split]);
+            audio.extend(demuxer.feed(&body[split..]));
+            assert_eq!(audio, b"abcdefgh", "split at {}", split);
+            assert_eq!(
+                metadata.get_stream_title().as_deref(),
+                Some("Artist - Song"),
+                "split at {}",
+                split
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_title_over_empty_block() {
+        let metadata = metadata();
+        let mut demuxer = IcyDemuxer::new(2, metadata.clone());
+        let mut body = b"ab".to_vec();
+        body.extend(block("StreamTitle='First';"));
+        body.extend_from_slice(b"cd\0ef");
+
+        assert_eq!(demuxer.feed(&body), b"abcdef");
+        assert_eq!(metadata.get_stream_title().as_deref(), Some("First"));
+        // 長さ 0 のブロックだけのチャンク
+        assert_eq!(demuxer.feed(b"\0"), b"");
+        assert_eq!(demuxer.feed(b"gh\0ij"), b"ghij");
+        assert_eq!(metadata.get_stream_title().as_deref(), Some("First"));
+    }
+
+    #[test]
+    fn clears_empty_title() {
+        let metadata = metadata();
+        let mut demuxer = IcyDemuxer::new(1, metadata.clone());
+        let mut body = b"a".to_vec();
+        body.extend(block("StreamTitle='Song';"));
+        body.push(b'b');
+        body.extend(block("StreamTitle='';"));
+        demuxer.feed(&body);
+        assert_eq!(metadata.get_stream_title(), None);
+    }
+
+    #[test]
+    fn parses_stream_title() {
+        let parse = |text: &str| parse_stream_title(&block(text)[1..]);
+        assert_eq!(parse("StreamTitle='It's';").as_deref(), Some("It's"));
+        // サーバーは ' をエスケープしないので、そのまま残す
+        assert_eq!(parse("StreamTitle='It''s';").as_deref(), Some("It''s"));
+        assert_eq!(
+            parse("StreamTitle='A; B';StreamUrl='http://example.com';").as_deref(),
+            Some("A; B")
+        );
+        assert_eq!(parse("StreamTitle='No end'").as_deref(), Some("No end"));
+        assert_eq!(parse("StreamUrl='http://example.com';"), None);
+        assert_eq!(
+            parse_stream_title(b"StreamTitle='Caf\xe9';").as_deref(),
+            Some("Café")
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! HTTP access for remote streams.
+mod client;
+mod format;
+pub mod hls;
+mod icy;
+mod proxy;
+mod range;
+mod retry;
+#[cfg(test)]
+pub(crate) mod test_server;
+
+pub use client::{Auth, HostOptions, HttpClient, HttpOptions};
+pub use format::detect_hint;
+pub use hls::HlsStream;
+pub use icy::{IcyDemuxer, IcyMetadata};
+pub use range::RangeSource;
+pub(crate) use retry::next_chunk;
+pub use retry::{RetryPolicy, StreamState};
+
+use hyper::Response;
+use hyper::body::Incoming;
+
+use crate::error::{MinauError, Result};
+
+const MAX_REDIRECTS: usize = 10;
+
+/// A response together with the URL it was served from after redirects.
+pub struct Fetched {
+    pub response: Response<Incoming>,
+    pub url: String,
+}
+
+impl Fetched {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+    }
+
+    /// Length of the body, or of the whole resource for a range response.
+    pub fn content_length(&self) -> Option<u64> {
+        if let Some(total) = self
+            .header("content-range")
+            .and_then(|v| v.rsplit('/').next())
+            .and_then(|v| v.parse().ok())
+        {
+            return Some(total);
+        }
+        self.header("content-length").and_then(|v| v.parse().ok())
+    }
+
+    /// The `Content-Type` header, e.g. `audio/mpeg`.
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("content-type")
+    }
+
+    /// Interval of the ICY metadata blocks, if the server interleaves them
+    /// with the audio.
+    pub fn icy_metaint(&self) -> Option<usize> {
+        self.header("icy-metaint")
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|&n| n > 0)
+    }
+
+    /// Whether the server accepts byte range requests for this resource.
+    pub fn accepts_ranges(&self) -> bool {
+        self.response.status() == hyper::StatusCode::PARTIAL_CONTENT
+            || self
+                .header("accept-ranges")
+                .is_some_and(|v| v.eq_ignore_ascii_case("bytes"))
+    }
+}
+
+fn http_err(e: &dyn std::fmt::Display) -> MinauError {
+    MinauError::Http(e.to_string())
+}
+
+/// Sends a GET request, following redirects. With `from`, only the bytes
+/// from that offset on are requested; otherwise ICY metadata is asked for.
+/// The global credentials are dropped once a redirect leaves the origin of
+/// `url`.
+///
+/// Must run inside a Tokio context, e.g. wrapped in `async_compat::Compat`.
+pub async fn get(client: &HttpClient, url: &str, from: Option<u64>) -> Result<Fetched> {
+    let mut current_url = url.to_string();
+    let mut redirect_count = 0;
+    let origin = url::Url::parse(url).map_err(|e| http_err(&e))?.origin();
+    let mut same_origin = true;
+
+    loop {
+        let mut req = client.request(&current_url, same_origin)?;
+        match from {
+            Some(from) => req = req.header("Range", format!("bytes={}-", from)),
+            None => req = req.header("Icy-MetaData", "1"),
+        }
+        let req = req.body(String::new()).map_err(|e| http_err(&e))?;
+
+        let resp = client.send(req).await?;
+        let status = resp.status();
+
+        if status.is_redirection() {
+            if redirect_count >= MAX_REDIRECTS {
+                return Err(MinauError::Http("Too many redirects".into()));
+            }
+
+            let Some(location) = resp.headers().get("location") else {
+                return Err(MinauError::Http("Redirect without Location header".into()));
+            };
+            let location = location.to_str().map_err(|e| http_err(&e))?;
+            current_url = resolve(&current_url, location)?;
+            // 別のオリジンに移ったら、戻ってきても認証情報は送らない
+            same_origin &= url::Url::parse(&current_url).is_ok_and(|u| u.origin() == origin);
+            redirect_count += 1;
+            continue;
+        }
+
+        if !status.is_success() {
+            return Err(MinauError::Http(format!("HTTP Error: {}", status)));
+        }
+
+        return Ok(Fetched {
+            response: resp,
+            url: current_url,
+        });
+    }
+}
+
+/// Resolves a `Location` header against the URL that returned it.
+fn resolve(base: &str, location: &str) -> Result<String> {
+    let base = url::Url::parse(base).map_err(|e| http_err(&e))?;
+    base.join(location)
+        .map(|url| url.to_string())
+        .map_err(|e| http_err(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_server::{TestResponse, TestServer};
+    use super::*;
+    use async_compat::CompatExt;
+
+    fn client(auth_scope: Vec<String>) -> HttpClient {
+        HttpClient::new(HttpOptions {
+            auth: Some(Auth::Bearer("secret".into())),
+            auth_scope,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn fetch(client: &HttpClient, url: &str) -> String {
+        smol::block_on(get(client, url, None).compat()).unwrap().url
+    }
+
+    /// Authorization headers the server received, by path.
+    fn auth_headers(server: &TestServer) -> Vec<(String, Option<String>)> {
+        server
+            .requests()
+            .into_iter()
+            .map(|r| {
+                let auth = r.header("authorization").map(String::from);
+                (r.path, auth)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_auth_on_same_origin_redirect() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/old" => TestResponse::redirect("/new"),
+            _ => TestResponse::ok("audio"),
+        });
+        let url = fetch(&client(Vec::new()), &server.url("/old"));
+        assert_eq!(url, server.url("/new"));
+
+        let bearer = Some("Bearer secret".to_string());
+        assert_eq!(
+            auth_headers(&server),
+            [("/old".into(), bearer.clone()), ("/new".into(), bearer)]
+        );
+    }
+
+    #[test]
+    fn drops_auth_on_cross_origin_redirect() {
+        let other = TestServer::start(|_| TestResponse::ok("audio"));
+        let target = other.url("/stream");
+        let elsewhere = TestServer::start(|_| TestResponse::ok("audio"));
+        let elsewhere_url = elsewhere.url("/again");
+        let server = TestServer::start(move |req| match req.path.as_str() {
+            "/start" => TestResponse::redirect(&target),
+            _ => TestResponse::ok("audio"),
+        });
+        let scope = vec![server.url("/start")];
+
+        fetch(&client(scope.clone()), &server.url("/start"));
+        assert_eq!(
+            auth_headers(&server),
+            [("/start".into(), Some("Bearer secret".into()))]
+        );
+        assert_eq!(auth_headers(&other), [("/stream".into(), None)]);
+
+        // スコープ外のサーバーには最初から送らない
+        fetch(&client(scope), &elsewhere_url);
+        assert_eq!(auth_headers(&elsewhere), [("/again".into(), None)]);
+    }
+
+    #[test]
+    fn drops_global_headers_on_cross_origin_redirect() {
+        let other = TestServer::start(|_| TestResponse::ok("audio"));
+        let target = other.url("/stream");
+        let server = TestServer::start(move |_| TestResponse::redirect(&target));
+        let client = HttpClient::new(HttpOptions {
+            headers: vec![("X-Api-Key".into(), "key".into())],
+            auth_scope: vec![server.url("/start")],
+            hosts: vec![HostOptions {
+                host: other.url("").trim_start_matches("http://").into(),
+                headers: vec![("X-Client".into(), "minau".into())],
+                auth: None,
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+
+        fetch(&client, &server.url("/start"));
+        let start = &server.requests()[0];
+        assert_eq!(start.header("x-api-key"), Some("key"));
+        let redirected = &other.requests()[0];
+        assert_eq!(redirected.header("x-api-key"), None);
+        // ホスト別のヘッダーはそのまま送る
+        assert_eq!(redirected.header("x-client"), Some("minau"));
+    }
+}
@@ -0,0 +1,282 @@
+//! Connecting through an HTTP proxy.
+//!
+//! Plain HTTP requests are sent to the proxy in absolute form; HTTPS
+//! connections are tunnelled with `CONNECT` and TLS runs on top of the tunnel.
+
+use hyper::Uri;
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper_util::client::legacy::connect::{Connected, Connection, HttpConnector};
+use hyper_util::rt::TokioIo;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+use super::client::{base64, percent_decode};
+use super::http_err;
+use crate::error::Result;
+
+/// Longest proxy response header accepted for a `CONNECT`
+const MAX_CONNECT_RESPONSE: usize = 16 * 1024;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A proxy server and the credentials for it.
+#[derive(Clone, Debug)]
+struct ProxyServer {
+    uri: Uri,
+    /// Value of the `Proxy-Authorization` header
+    auth: Option<String>,
+}
+
+/// Which proxy, if any, each request goes through.
+#[derive(Clone, Debug, Default)]
+pub struct Proxy {
+    http: Option<ProxyServer>,
+    https: Option<ProxyServer>,
+    /// Hosts reached directly, from `NO_PROXY`
+    no_proxy: Vec<String>,
+}
+
+impl Proxy {
+    /// A proxy for all requests; an empty `url` disables proxying.
+    pub fn from_url(url: &str) -> Result<Self> {
+        if url.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        let server = ProxyServer::parse(url)?;
+        Ok(Self {
+            http: Some(server.clone()),
+            https: Some(server),
+            no_proxy: Vec::new(),
+        })
+    }
+
+    /// Reads the proxy from the environment like curl does. Invalid values
+    /// are ignored.
+    pub fn from_env() -> Self {
+        let var = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| std::env::var(name).ok().filter(|v| !v.trim().is_empty()))
+        };
+        let all = var(&["ALL_PROXY", "all_proxy"]);
+        // CGI では HTTP_PROXY がリクエストヘッダーから来るので小文字だけを見る
+        let http_names: &[&str] = if std::env::var_os("REQUEST_METHOD").is_some() {
+            &["http_proxy"]
+        } else {
+            &["http_proxy", "HTTP_PROXY"]
+        };
+        let server = |value: Option<String>| {
+            value
+                .or_else(|| all.clone())
+                .and_then(|v| ProxyServer::parse(&v).ok())
+        };
+
+        Self {
+            http: server(var(http_names)),
+            https: server(var(&["HTTPS_PROXY", "https_proxy"])),
+            no_proxy: var(&["NO_PROXY", "no_proxy"])
+                .map(|list| {
+                    list.split(',')
+                        .map(|h| h.trim().trim_start_matches('.').to_ascii_lowercase())
+                        .filter(|h| !h.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn server_for(&self, dst: &Uri) -> Option<&ProxyServer> {
+        let host = dst.host()?.trim_matches(|c| c == '[' || c == ']');
+        let host = host.to_ascii_lowercase();
+        let bypass = self
+            .no_proxy
+            .iter()
+            .any(|entry| entry == "*" || host == *entry || host.ends_with(&format!(".{}", entry)));
+        if bypass {
+            return None;
+        }
+        match dst.scheme_str() {
+            Some("https") => self.https.as_ref(),
+            _ => self.http.as_ref(),
+        }
+    }
+
+    /// `Proxy-Authorization` for a plain HTTP request, which the proxy sees
+    /// directly rather than through a tunnel.
+    pub fn plain_http_auth(&self, dst: &Uri) -> Option<&str> {
+        if dst.scheme_str() == Some("https") {
+            return None;
+        }
+        self.server_for(dst)?.auth.as_deref()
+    }
+}
+
+impl ProxyServer {
+    fn parse(url: &str) -> Result<Self> {
+        // スキームがなければ http とみなす
+        let url = if url.contains("://") {
+            url.to_string()
+        } else {
+            format!("http://{}", url)
+        };
+        let parsed = url::Url::parse(&url).map_err(|e| http_err(&e))?;
+        if parsed.scheme() != "http" {
+            return Err(http_err(&format!(
+                "Unsupported proxy scheme: {}",
+                parsed.scheme()
+            )));
+        }
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| http_err(&"Proxy URL has no host"))?;
+        let port = parsed.port().unwrap_or(80);
+        let uri = format!("http://{}:{}", host, port)
+            .parse()
+            .map_err(|e| http_err(&e))?;
+
+        let auth = (!parsed.username().is_empty()).then(|| {
+            let credentials = format!(
+                "{}:{}",
+                percent_decode(parsed.username()),
+                percent_decode(parsed.password().unwrap_or_default())
+            );
+            format!("Basic {}", base64(credentials.as_bytes()))
+        });
+        Ok(Self { uri, auth })
+    }
+}
+
+/// Opens TCP connections, through the proxy where one applies.
+#[derive(Clone)]
+pub struct ProxyConnector {
+    http: HttpConnector,
+    proxy: Arc<Proxy>,
+}
+
+impl ProxyConnector {
+    pub fn new(http: HttpConnector, proxy: Arc<Proxy>) -> Self {
+        Self { http, proxy }
+    }
+}
+
+impl Service<Uri> for ProxyConnector {
+    type Response = ProxyStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<ProxyStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), BoxError>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let Some(server) = self.proxy.server_for(&dst).cloned() else {
+            let connecting = self.http.call(dst);
+            return Box::pin(async move {
+                Ok(ProxyStream {
+                    io: connecting.await?,
+                    proxied: false,
+                })
+            });
+        };
+
+        let connecting = self.http.call(server.uri.clone());
+        Box::pin(async move {
+            let mut io = connecting.await?;
+            if dst.scheme_str() != Some("https") {
+                return Ok(ProxyStream { io, proxied: true });
+            }
+            tunnel(io.inner_mut(), &dst, server.auth.as_deref()).await?;
+            Ok(ProxyStream { io, proxied: false })
+        })
+    }
+}
+
+/// Asks the proxy to open a tunnel to `dst`.
+async fn tunnel(stream: &mut TcpStream, dst: &Uri, auth: Option<&str>) -> io::Result<()> {
+    let host = dst.host().unwrap_or_default();
+    let port = dst.port_u16().unwrap_or(443);
+    let mut request = format!(
+        "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n",
+        host = host,
+        port = port
+    );
+    if let Some(auth) = auth {
+        request.push_str(&format!("Proxy-Authorization: {}\r\n", auth));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // ヘッダーの終わりまで読む。トンネルの中身を読み過ぎないよう 1 バイトずつ
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_CONNECT_RESPONSE {
+            return Err(io::Error::other("Proxy response too long"));
+        }
+        let mut byte = [0];
+        if stream.read(&mut byte).await? == 0 {
+            return Err(io::Error::other("Proxy closed the connection"));
+        }
+        response.push(byte[0]);
+    }
+
+    let status_line = String::from_utf8_lossy(&response);
+    let status = status_line
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or_default();
+    if !status.starts_with('2') {
+        return Err(io::Error::other(format!(
+            "Proxy refused the tunnel: {}",
+            status_line.lines().next().unwrap_or_default()
+        )));
+    }
+    Ok(())
+}
+
+/// A connection to the server or to the proxy in front of it.
+pub struct ProxyStream {
+    io: TokioIo<TcpStream>,
+    /// Requests are sent to a proxy and must use absolute URIs
+    proxied: bool,
+}
+
+impl Connection for ProxyStream {
+    fn connected(&self) -> Connected {
+        self.io.connected().proxy(self.proxied)
+    }
+}
+
+impl Read for ProxyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl Write for ProxyStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,558 @@
+use async_compat::CompatExt;
+use hyper::body::Incoming;
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::io::{Read, Result as IoResult, Seek, SeekFrom};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use super::retry::{RetryPolicy, StreamState, next_chunk};
+use super::{Fetched, HttpClient};
+use crate::err;
+
+const BLOCK_LEN: u64 = 64 * 1024;
+/// Blocks kept at most, 16 MiB in all; the least recently used go first
+const MAX_BLOCKS: usize = 256;
+/// The download pauses once it is this far ahead of the reader, so the
+/// blocks still to be read always fit in the cache
+const MAX_AHEAD: u64 = MAX_BLOCKS as u64 / 2 * BLOCK_LEN;
+/// A running download is reused if it will reach the wanted byte within this
+/// many bytes; otherwise a new range request is made
+const REUSE_DISTANCE: u64 = 512 * 1024;
+/// How often a waiting read checks whether it was interrupted
+const WAIT_SLICE: Duration = Duration::from_millis(100);
+
+/// Bytes fetched so far, in fixed-size blocks that fill from their start.
+#[derive(Default)]
+struct Cache {
+    blocks: HashMap<u64, Block>,
+    /// Incremented on every block access, to find the least recently used
+    clock: u64,
+    /// Where the reader is
+    read_pos: u64,
+    /// Set while the download waits for the reader to catch up
+    throttled: bool,
+    /// The download currently running
+    fetch: Option<Fetch>,
+    /// Incremented whenever a download is replaced, so old ones stop
+    generation: u64,
+}
+
+#[derive(Default)]
+struct Block {
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Fetch {
+    generation: u64,
+    /// Offset of the next byte the download will deliver
+    next: u64,
+    done: bool,
+}
+
+impl Cache {
+    /// Copies cached bytes at `pos` into `buf`.
+    fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> usize {
+        let offset = (pos % BLOCK_LEN) as usize;
+        self.clock += 1;
+        match self.blocks.get_mut(&(pos / BLOCK_LEN)) {
+            Some(block) if block.data.len() > offset => {
+                block.last_used = self.clock;
+                let n = (block.data.len() - offset).min(buf.len());
+                buf[..n].copy_from_slice(&block.data[offset..offset + n]);
+                n
+            }
+            _ => 0,
+        }
+    }
+
+    /// First byte at or after `pos` that is not cached yet, within the block.
+    fn first_missing(&self, pos: u64) -> u64 {
+        let block = pos / BLOCK_LEN;
+        let cached = self.blocks.get(&block).map_or(0, |b| b.data.len() as u64);
+        block * BLOCK_LEN + cached
+    }
+
+    /// Whether download `generation` should wait for the reader to catch up.
+    fn is_ahead(&self, generation: u64) -> bool {
+        self.fetch
+            .is_some_and(|f| f.generation == generation && f.next >= self.read_pos + MAX_AHEAD)
+    }
+
+    /// Drops the least recently used blocks over [`MAX_BLOCKS`], keeping
+    /// those between the reader and the download.
+    fn evict(&mut self) {
+        let first = self.read_pos / BLOCK_LEN;
+        let last = self.fetch.map_or(first, |f| f.next / BLOCK_LEN);
+        while self.blocks.len() > MAX_BLOCKS {
+            let oldest = self
+                .blocks
+                .iter()
+                .filter(|&(&index, _)| !(first..=last).contains(&index))
+                .min_by_key(|(_, block)| block.last_used)
+                .map(|(&index, _)| index);
+            match oldest {
+                Some(index) => self.blocks.remove(&index),
+                None => break,
+            };
+        }
+    }
+
+    /// Stores bytes delivered by download `generation` at its current offset.
+    /// Returns the number of new bytes, or `None` if the download was
+    /// replaced and should stop.
+    fn write(&mut self, generation: u64, mut data: &[u8]) -> Option<u64> {
+        let fetch = self.fetch.as_mut().filter(|f| f.generation == generation)?;
+        let mut stored = 0;
+
+        while !data.is_empty() {
+            let offset = (fetch.next % BLOCK_LEN) as usize;
+            self.clock += 1;
+            let block = self.blocks.entry(fetch.next / BLOCK_LEN).or_default();
+            block.last_used = self.clock;
+            let room = BLOCK_LEN as usize - offset;
+            let n = room.min(data.len());
+            // 既に持っている部分は読み飛ばす
+            if block.data.len() == offset {
+                block.data.extend_from_slice(&data[..n]);
+                stored += n as u64;
+            }
+            fetch.next += n as u64;
+            data = &data[n..];
+        }
+        self.evict();
+        Some(stored)
+    }
+}
+
+struct Shared {
+    cache: Mutex<Cache>,
+    ready: Condvar,
+    /// Bytes cached so far
+    downloaded: Arc<Mutex<u64>>,
+    state: Arc<Mutex<StreamState>>,
+}
+
+/// Where the resource is and how to fetch it.
+#[derive(Clone)]
+struct Remote {
+    client: HttpClient,
+    url: String,
+    len: u64,
+    retry: RetryPolicy,
+}
+
+/// A seekable HTTP resource, fetched with range requests. The most recently
+/// used fetched bytes are kept, so seeking back a little does not download
+/// them again.
+pub struct RangeSource {
+    remote: Remote,
+    pos: u64,
+    shared: Arc<Shared>,
+    /// Set by the engine to stop waiting for data
+    interrupt: Arc<AtomicBool>,
+}
+
+impl RangeSource {
+    /// Wraps a response from a server that accepts range requests; its body
+    /// is used for the start of the resource.
+    pub fn new(client: HttpClient, fetched: Fetched, len: u64, retry: RetryPolicy) -> Self {
+        let source = Self {
+            remote: Remote {
+                client,
+                url: fetched.url,
+                len,
+                retry,
+            },
+            pos: 0,
+            shared: Arc::new(Shared {
+                cache: Mutex::new(Cache::default()),
+                ready: Condvar::new(),
+                downloaded: Arc::new(Mutex::new(0)),
+                state: Arc::new(Mutex::new(StreamState::Streaming)),
+            }),
+            interrupt: Arc::new(AtomicBool::new(false)),
+        };
+        source.spawn_download(0, Some(fetched.response.into_body()));
+        source
+    }
+
+    /// Counter of the bytes cached so far. Bytes fetched again after a seek
+    /// are not counted twice.
+    pub fn downloaded(&self) -> Arc<Mutex<u64>> {
+        Arc::clone(&self.shared.downloaded)
+    }
+
+    /// Flag that makes a read waiting for data fail at once, e.g. so the
+    /// decoder gets to a seek. It stays set until cleared.
+    pub fn interrupt(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupt)
+    }
+
+    /// State of the download, e.g. while reconnecting.
+    pub fn state(&self) -> Arc<Mutex<StreamState>> {
+        Arc::clone(&self.shared.state)
+    }
+
+    /// Waits until `len` bytes from the start are cached, or the timeout
+    /// passes, and returns what is there.
+    pub fn peek(&self, len: usize, timeout: Duration) -> Vec<u8> {
+        let len = len.min(self.remote.len as usize);
+        let mut cache = self.shared.cache.lock();
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            let cached = cache.first_missing(0).min(BLOCK_LEN) as usize;
+            let done = cache.fetch.is_none_or(|f| f.done);
+            if cached >= len.min(BLOCK_LEN as usize) || done {
+                let mut buf = vec![0u8; cached.min(len)];
+                let n = cache.read_at(0, &mut buf);
+                buf.truncate(n);
+                return buf;
+            }
+            if self
+                .shared
+                .ready
+                .wait_until(&mut cache, deadline)
+                .timed_out()
+            {
+                return Vec::new();
+            }
+        }
+    }
+
+    /// Tells the download where the reader is, letting it continue if it
+    /// was waiting.
+    fn move_reader(&self, cache: &mut Cache) {
+        cache.read_pos = self.pos;
+        if cache.throttled {
+            self.shared.ready.notify_all();
+        }
+    }
+
+    /// Starts downloading from `start`, replacing the current download.
+    /// Without a `body`, a range request is made first.
+    fn spawn_download(&self, start: u64, body: Option<Incoming>) {
+        let generation = {
+            let mut cache = self.shared.cache.lock();
+            cache.read_pos = self.pos;
+            cache.generation += 1;
+            cache.fetch = Some(Fetch {
+                generation: cache.generation,
+                next: start,
+                done: false,
+            });
+            cache.generation
+        };
+        let shared = Arc::clone(&self.shared);
+        let remote = self.remote.clone();
+
+        std::thread::spawn(move || {
+            smol::block_on(download(&shared, &remote, generation, body).compat());
+            finish(&shared, generation);
+        });
+    }
+}
+
+/// Copies the resource into the cache from the download's current offset,
+/// reconnecting when the connection drops, until the end is reached or the
+/// download is replaced.
+async fn download(shared: &Shared, remote: &Remote, generation: u64, mut body: Option<Incoming>) {
+    let mut attempt = 0;
+
+    loop {
+        let Some(current) = body.as_mut() else {
+            // 途切れた位置から取り直す
+            let Some(next) = resume_point(shared, generation, remote.len) else {
+                return;
+            };
+            if attempt > 0 && !remote.retry.wait(attempt, &shared.state).await {
+                return;
+            }
+            match super::get(&remote.client, &remote.url, Some(next)).await {
+                Ok(fetched) if fetched.response.status() == hyper::StatusCode::PARTIAL_CONTENT => {
+                    body = Some(fetched.response.into_body());
+                }
+                Ok(fetched) => {
+                    err!(
+                        "Range request was answered with {}",
+                        fetched.response.status()
+                    );
+                    return;
+                }
+                Err(_) => attempt += 1,
+            }
+            continue;
+        };
+
+        match next_chunk(current, remote.client.read_timeout()).await {
+            Some(Ok(data)) => {
+                attempt = 0;
+                let stored = {
+                    let mut cache = shared.cache.lock();
+                    let stored = cache.write(generation, &data);
+                    shared.ready.notify_all();
+                    // 読む側が追いつくまで待つ
+                    while cache.is_ahead(generation) {
+                        cache.throttled = true;
+                        shared.ready.wait(&mut cache);
+                    }
+                    cache.throttled = false;
+                    stored
+                };
+                let Some(stored) = stored else {
+                    return;
+                };
+                *shared.downloaded.lock() += stored;
+                *shared.state.lock() = StreamState::Streaming;
+            }
+            Some(Err(_)) | None => {
+                body = None;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Offset to resume download `generation` at, or `None` if it has reached
+/// the end or was replaced.
+fn resume_point(shared: &Shared, generation: u64, len: u64) -> Option<u64> {
+    shared
+        .cache
+        .lock()
+        .fetch
+        .filter(|f| f.generation == generation && f.next < len)
+        .map(|f| f.next)
+}
+
+fn finish(shared: &Shared, generation: u64) {
+    if let Some(fetch) = shared
+        .cache
+        .lock()
+        .fetch
+        .as_mut()
+        .filter(|f| f.generation == generation)
+    {
+        fetch.done = true;
+    }
+    shared.ready.notify_all();
+}
+
+impl Read for RangeSource {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if buf.is_empty() || self.pos >= self.remote.len {
+            return Ok(0);
+        }
+        let mut refetched = false;
+
+        loop {
+            let mut cache = self.shared.cache.lock();
+            self.move_reader(&mut cache);
+            let n = cache.read_at(self.pos, buf);
+            if n > 0 {
+                self.pos += n as u64;
+                self.move_reader(&mut cache);
+                return Ok(n);
+            }
+
+            // 今のダウンロードがすぐに届かない位置なら取り直す
+            let missing = cache.first_missing(self.pos);
+            let reachable = cache.fetch.is_some_and(|f| {
+                !f.done && f.next <= missing && missing <= f.next + REUSE_DISTANCE
+            });
+            if !reachable {
+                if refetched {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Range request returned no data",
+                    ));
+                }
+                drop(cache);
+                self.spawn_download(missing, None);
+                refetched = true;
+                continue;
+            }
+
+            // 再接続中も含めて待つが、止められたらすぐに抜ける。
+            // symphonia は Interrupted の読み込みをやり直すので別の種類で返す
+            if self.interrupt.load(Ordering::Relaxed) {
+                return Err(std::io::Error::other("Interrupted while waiting for data"));
+            }
+            self.shared.ready.wait_for(&mut cache, WAIT_SLICE);
+        }
+    }
+}
+
+impl Drop for RangeSource {
+    fn drop(&mut self) {
+        // 待っているダウンロードも含めて止める
+        let mut cache = self.shared.cache.lock();
+        cache.generation += 1;
+        cache.fetch = None;
+        self.shared.ready.notify_all();
+    }
+}
+
+impl Seek for RangeSource {
+    fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::End(offset) => self.remote.len as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+        if pos < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek before the start of the stream",
+            ));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+impl symphonia::core::io::MediaSource for RangeSource {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.remote.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_server::{TestResponse, TestServer};
+    use super::super::{HttpOptions, get};
+    use super::*;
+
+    /// Byte `i` of the served resource.
+    fn byte(i: u64) -> u8 {
+        (i * 7 % 251) as u8
+    }
+
+    /// Serves `len` bytes, honouring `Range: bytes=N-`.
+    fn range_server(len: u64) -> TestServer {
+        let data: Arc<Vec<u8>> = Arc::new((0..len).map(byte).collect());
+        TestServer::start(move |req| {
+            let from = req
+                .header("range")
+                .and_then(|r| r.strip_prefix("bytes="))
+                .and_then(|r| r.trim_end_matches('-').parse::<u64>().ok());
+            let mut response = TestResponse::ok(data[from.unwrap_or(0) as usize..].to_vec());
+            response
+                .headers
+                .push(("Accept-Ranges".into(), "bytes".into()));
+            if let Some(from) = from {
+                response.status = 206;
+                response.headers.push((
+                    "Content-Range".into(),
+                    format!("bytes {}-{}/{}", from, len - 1, len),
+                ));
+            }
+            response
+        })
+    }
+
+    fn open(server: &TestServer, len: u64) -> RangeSource {
+        let client = HttpClient::new(HttpOptions::default()).unwrap();
+        let fetched = smol::block_on(get(&client, &server.url("/audio"), None).compat()).unwrap();
+        RangeSource::new(client, fetched, len, RetryPolicy::default())
+    }
+
+    fn read_exact_at(source: &mut RangeSource, pos: u64, len: usize) -> Vec<u8> {
+        source.seek(SeekFrom::Start(pos)).unwrap();
+        let mut buf = vec![0u8; len];
+        source.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    fn cached_blocks(source: &RangeSource) -> usize {
+        source.shared.cache.lock().blocks.len()
+    }
+
+    #[test]
+    fn holds_download_close_to_reader() {
+        let len = 3 * MAX_AHEAD;
+        let server = range_server(len);
+        let mut source = open(&server, len);
+
+        assert_eq!(
+            read_exact_at(&mut source, 0, 1000),
+            (0..1000).map(byte).collect::<Vec<_>>()
+        );
+        std::thread::sleep(Duration::from_millis(500));
+        let fetch = source.shared.cache.lock().fetch.unwrap();
+        // 受け取ったチャンクの分だけは越えうる
+        assert!(fetch.next < MAX_AHEAD + 1024 * 1024, "{}", fetch.next);
+        assert!(!fetch.done);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_blocks() {
+        let len = 3 * MAX_BLOCKS as u64 * BLOCK_LEN;
+        let server = range_server(len);
+        let mut source = open(&server, len);
+
+        let mut buf = vec![0u8; 256 * 1024];
+        let mut pos = 0;
+        while pos < len {
+            let n = source.read(&mut buf).unwrap();
+            assert!(n > 0);
+            assert!(buf[..n].iter().zip(pos..).all(|(&b, i)| b == byte(i)));
+            pos += n as u64;
+            assert!(cached_blocks(&source) <= MAX_BLOCKS);
+        }
+
+        // 追い出された先頭のブロックは頭から取り直す
+        let start = read_exact_at(&mut source, 10, 100);
+        assert_eq!(start, (10..110).map(byte).collect::<Vec<_>>());
+        assert!(cached_blocks(&source) <= MAX_BLOCKS);
+        let ranges: Vec<_> = server
+            .requests()
+            .into_iter()
+            .filter_map(|r| r.header("range").map(String::from))
+            .collect();
+        assert_eq!(ranges, ["bytes=0-"]);
+    }
+
+    #[test]
+    fn stops_waiting_when_interrupted() {
+        // 先頭だけ送って止まるサーバー
+        let server = TestServer::start(|_| TestResponse {
+            stall: true,
+            ..TestResponse::ok(vec![0u8; 1000])
+        });
+        let mut source = open(&server, 1000 + (1 << 20));
+        let interrupt = source.interrupt();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            interrupt.store(true, Ordering::Relaxed);
+        });
+
+        let started = std::time::Instant::now();
+        source.seek(SeekFrom::Start(2000)).unwrap();
+        let e = source.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Other);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn stops_download_when_dropped() {
+        let len = 3 * MAX_AHEAD;
+        let server = range_server(len);
+        let mut source = open(&server, len);
+        read_exact_at(&mut source, 0, 10);
+        let shared = Arc::clone(&source.shared);
+        drop(source);
+
+        std::thread::sleep(Duration::from_millis(200));
+        let cache = shared.cache.lock();
+        assert!(cache.fetch.is_none());
+        assert!(!cache.throttled);
+    }
+}
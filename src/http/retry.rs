@@ -0,0 +1,88 @@
+//! Reconnecting dropped connections.
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use parking_lot::Mutex;
+use std::time::Duration;
+
+use crate::error::{MinauError, Result};
+
+/// How often and how patiently dropped connections are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Reconnect attempts in a row before giving up; 0 disables reconnecting
+    pub max_retries: u32,
+    /// Wait before the first attempt, doubled for each further one
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// What the connection of a remote stream is doing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StreamState {
+    #[default]
+    Streaming,
+    /// Playback caught up with the download and waits for it to refill
+    Buffering,
+    /// The connection dropped; reconnect attempt `attempt` is pending
+    Reconnecting { attempt: u32 },
+    /// Retries are exhausted; playback ends once the buffer runs out
+    Failed,
+}
+
+impl RetryPolicy {
+    /// Wait before reconnect attempt `attempt`, counted from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+
+    /// Waits before reconnect attempt `attempt`, reporting it in `state`.
+    /// Returns false once the retries are exhausted.
+    pub async fn wait(&self, attempt: u32, state: &Mutex<StreamState>) -> bool {
+        if attempt > self.max_retries {
+            *state.lock() = StreamState::Failed;
+            return false;
+        }
+        *state.lock() = StreamState::Reconnecting { attempt };
+        smol::Timer::after(self.delay(attempt)).await;
+        true
+    }
+}
+
+/// Reads the next chunk of data from `body`. Returns `None` at the end of
+/// the body; waiting longer than `stall_timeout` is reported as an error.
+pub async fn next_chunk(body: &mut Incoming, stall_timeout: Duration) -> Option<Result<Bytes>> {
+    loop {
+        let frame = smol::future::or(async { Some(body.frame().await) }, async {
+            smol::Timer::after(stall_timeout).await;
+            None
+        })
+        .await;
+
+        match frame {
+            None => return Some(Err(MinauError::Http("Stream stalled".into()))),
+            Some(None) => return None,
+            Some(Some(Err(e))) => return Some(Err(MinauError::Http(e.to_string()))),
+            // トレーラーなどデータ以外のフレームは読み飛ばす
+            Some(Some(Ok(frame))) => {
+                if let Ok(data) = frame.into_data() {
+                    return Some(Ok(data));
+                }
+            }
+        }
+    }
+}
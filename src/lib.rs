@@ -0,0 +1,32 @@
+#![doc(
+    html_favicon_url = "https://raw.githubusercontent.com/sirasaki-konoha/minau/refs/heads/master/icon/minau-icon.png"
+)]
+#![doc(
+    html_logo_url = "https://raw.githubusercontent.com/sirasaki-konoha/minau/refs/heads/master/icon/minau-icon.png"
+)]
+//! A simple, minimal music player.
+//!
+//! The `minau` binary is a thin command-line frontend over this library.
+//! Local files are opened with [`Player`], which yields a [`MusicPlay`] handle
+//! once playback starts; remote streams are played through [`UrlPlayer`].
+//! Both are driven by the same [`PlaybackEngine`], fed from a [`Source`].
+pub mod dsp;
+pub mod engine;
+pub mod error;
+pub mod http;
+pub mod loudness;
+mod macros;
+pub mod play_url;
+pub mod player;
+pub mod playlist;
+pub mod replaygain;
+pub mod sink;
+pub mod source;
+
+pub use engine::{EngineOptions, PlaybackEngine};
+pub use error::{MinauError, Result};
+pub use play_url::UrlPlayer;
+pub use player::metadata::MetaData;
+pub use player::play::MusicPlay;
+pub use player::player_structs::Player;
+pub use source::Source;
@@ -0,0 +1,346 @@
+//! EBU R128 / ITU-R BS.1770 loudness measurement.
+
+use crate::dsp::biquad::Biquad;
+use crate::error::{MinauError, Result};
+use crate::player::player_structs::Player;
+use std::f64::consts::PI;
+use std::time::Duration;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{SeekMode, SeekTo};
+use symphonia::core::units::Time;
+
+/// Loudness that ReplayGain 2.0 gains are relative to, in LUFS.
+pub const REPLAYGAIN_REFERENCE: f64 = -18.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// Gating blocks are 400 ms long and start every 100 ms
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+/// Result of measuring one track.
+#[derive(Clone, Debug, Default)]
+pub struct Loudness {
+    /// Mean square of every 400 ms gating block, kept for album measurement
+    blocks: Vec<f64>,
+    /// Largest absolute sample value
+    pub peak: f32,
+}
+
+impl Loudness {
+    /// Integrated loudness in LUFS, or `None` for silence.
+    pub fn integrated(&self) -> Option<f64> {
+        gated_loudness(self.blocks.iter().copied())
+    }
+
+    /// ReplayGain 2.0 gain in dB that brings the track to the reference
+    /// loudness.
+    pub fn replay_gain(&self) -> Option<f64> {
+        self.integrated().map(|lufs| REPLAYGAIN_REFERENCE - lufs)
+    }
+}
+
+/// Integrated loudness of several tracks played as one album.
+pub fn album_loudness(tracks: &[Loudness]) -> Option<f64> {
+    gated_loudness(tracks.iter().flat_map(|t| t.blocks.iter().copied()))
+}
+
+fn block_lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+fn gated_loudness(blocks: impl Iterator<Item = f64>) -> Option<f64> {
+    let above_absolute: Vec<f64> = blocks
+        .filter(|&z| z > 0.0 && block_lufs(z) > ABSOLUTE_GATE)
+        .collect();
+    let relative = block_lufs(mean(&above_absolute)?) + RELATIVE_GATE;
+    let above_relative: Vec<f64> = above_absolute
+        .into_iter()
+        .filter(|&z| block_lufs(z) > relative)
+        .collect();
+    mean(&above_relative).map(block_lufs)
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// The two K-weighting stages (high shelf, then high pass) for `rate`.
+fn k_weighting(rate: u32) -> [Biquad; 2] {
+    let rate = rate as f64;
+
+    // ハイシェルフ (頭部の音響効果)
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // ハイパス (RLB)
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    [shelf, high_pass]
+}
+
+/// Channel weight from BS.1770, assuming the usual L R C LFE Ls Rs order.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6.., 3) => 0.0,
+        (6.., 4 | 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Accumulates interleaved samples and produces a [`Loudness`].
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Frames in a 100 ms sub-block
+    sub_block_len: usize,
+    sub_block_frames: usize,
+    sub_block_sum: f64,
+    /// Weighted energy of the last sub-blocks, newest last
+    recent: Vec<f64>,
+    loudness: Loudness,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            channels,
+            filters: vec![k_weighting(sample_rate); channels],
+            weights: (0..channels)
+                .map(|ch| channel_weight(ch, channels))
+                .collect(),
+            sub_block_len: (sample_rate as usize / 10).max(1),
+            sub_block_frames: 0,
+            sub_block_sum: 0.0,
+            recent: Vec::with_capacity(SUB_BLOCKS_PER_BLOCK),
+            loudness: Loudness::default(),
+        }
+    }
+
+    /// Feeds interleaved samples.
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (ch, &sample) in frame.iter().enumerate() {
+                self.loudness.peak = self.loudness.peak.max(sample.abs());
+                if self.weights[ch] == 0.0 {
+                    continue;
+                }
+                let [shelf, high_pass] = &mut self.filters[ch];
+                let filtered = high_pass.process(shelf.process(sample as f64));
+                self.sub_block_sum += self.weights[ch] * filtered * filtered;
+            }
+
+            self.sub_block_frames += 1;
+            if self.sub_block_frames == self.sub_block_len {
+                self.finish_sub_block();
+            }
+        }
+    }
+
+    fn finish_sub_block(&mut self) {
+        if self.recent.len() == SUB_BLOCKS_PER_BLOCK {
+            self.recent.remove(0);
+        }
+        self.recent.push(self.sub_block_sum);
+        self.sub_block_sum = 0.0;
+        self.sub_block_frames = 0;
+
+        if self.recent.len() == SUB_BLOCKS_PER_BLOCK {
+            let frames = (self.sub_block_len * SUB_BLOCKS_PER_BLOCK) as f64;
+            let mean_square = self.recent.iter().sum::<f64>() / frames;
+            self.loudness.blocks.push(mean_square);
+        }
+    }
+
+    pub fn finish(self) -> Loudness {
+        self.loudness
+    }
+}
+
+/// Decodes the track, or only its clip when one is set, and measures its
+/// loudness.
+pub fn analyze(player: &Player) -> Result<Loudness> {
+    let mut meter = LoudnessMeter::new(player.sample_rate, player.channels);
+    let mut format = player.format.lock().unwrap();
+    let mut decoder = player.decoder.lock().unwrap();
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+    // クリップの範囲はパケット単位で測る
+    let to_ts = |time: Duration| match player.time_base {
+        Some(time_base) => time_base.calc_timestamp(Time::from(time)),
+        None => (time.as_secs_f64() * player.sample_rate as f64).round() as u64,
+    };
+    let (start_ts, end_ts) = match player.clip {
+        Some(clip) => (to_ts(clip.start), clip.end.map(to_ts)),
+        None => (0, None),
+    };
+    if start_ts > 0 {
+        format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts: start_ts,
+                    track_id: player.track_id,
+                },
+            )
+            .map_err(|e| MinauError::Decode(e.to_string()))?;
+        decoder.reset();
+    }
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            // 連結された Ogg などでストリームが切り替わった
+            Err(Error::ResetRequired) => {
+                decoder.reset();
+                continue;
+            }
+            Err(e) => return Err(MinauError::Decode(e.to_string())),
+        };
+        if packet.track_id() != player.track_id {
+            continue;
+        }
+        if end_ts.is_some_and(|end| packet.ts() >= end) {
+            break;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::DecodeError(_)) => continue,
+            Err(Error::ResetRequired) => {
+                decoder.reset();
+                continue;
+            }
+            Err(e) => return Err(MinauError::Decode(e.to_string())),
+        };
+        let (ts, dur) = (packet.ts(), packet.dur());
+        if ts + dur <= start_ts {
+            continue;
+        }
+
+        let spec = *decoded.spec();
+        let frames = decoded.frames() as u64;
+        let buf = match sample_buf.as_mut() {
+            Some(buf) if buf.capacity() >= decoded.capacity() * spec.channels.count() => buf,
+            _ => sample_buf.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buf.copy_interleaved_ref(decoded);
+
+        // クリップの境界にかかるパケットは範囲外のフレームを除く
+        let to_frames = |ts_offset: u64| (ts_offset * frames).checked_div(dur).unwrap_or(0);
+        let skip = to_frames(start_ts.saturating_sub(ts)).min(frames);
+        let keep = end_ts.map_or(frames, |end| to_frames(end - ts).min(frames));
+        let channels = spec.channels.count();
+        if keep > skip {
+            meter.push(&buf.samples()[skip as usize * channels..keep as usize * channels]);
+        }
+    }
+
+    Ok(meter.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    const RATE: u32 = 48000;
+
+    /// Writes a mono 16-bit WAV file of a 1 kHz tone, with one amplitude per
+    /// second of audio.
+    fn write_tone(name: &str, amplitudes: &[f32]) -> PathBuf {
+        let samples: Vec<i16> = amplitudes
+            .iter()
+            .flat_map(|&amp| {
+                (0..RATE).map(move |i| {
+                    let t = i as f32 / RATE as f32;
+                    ((2.0 * std::f32::consts::PI * 1000.0 * t).sin() * amp * i16::MAX as f32) as i16
+                })
+            })
+            .collect();
+        let data_len = samples.len() as u32 * 2;
+
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&RATE.to_le_bytes());
+        wav.extend_from_slice(&(RATE * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        let path = std::env::temp_dir().join(format!("minau-{}-{}.wav", std::process::id(), name));
+        std::fs::write(&path, wav).unwrap();
+        path
+    }
+
+    fn measure(path: &Path, clip: Option<(u64, Option<u64>)>) -> Loudness {
+        let mut player = Player::new(path).unwrap();
+        if let Some((start, end)) = clip {
+            player.set_clip(Duration::from_secs(start), end.map(Duration::from_secs));
+        }
+        analyze(&player).unwrap()
+    }
+
+    #[test]
+    fn matches_bs1770_reference_level() {
+        // BS.1770: 0 dBFS の 1 kHz 正弦波を 1 チャンネルに入れると -3.01 LKFS
+        let path = write_tone("reference", &[1.0, 1.0, 1.0]);
+        let lufs = measure(&path, None).integrated().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!((lufs - -3.01).abs() < 0.05, "{}", lufs);
+    }
+
+    #[test]
+    fn measures_only_the_clip() {
+        let path = write_tone("clip", &[0.5, 0.5, 0.05, 0.05]);
+        let loud = measure(&path, Some((0, Some(2))));
+        let quiet = measure(&path, Some((2, None)));
+        let whole = measure(&path, None);
+        std::fs::remove_file(&path).unwrap();
+
+        let loud_lufs = loud.integrated().unwrap();
+        let quiet_lufs = quiet.integrated().unwrap();
+        assert!(
+            (loud_lufs - quiet_lufs - 20.0).abs() < 0.5,
+            "{} vs {}",
+            loud_lufs,
+            quiet_lufs
+        );
+        assert!((loud.peak - 0.5).abs() < 0.01);
+        assert!(quiet.peak < 0.06);
+        // 相対ゲートで静かな部分は無視される
+        assert!((whole.integrated().unwrap() - loud_lufs).abs() < 0.5);
+    }
+}
@@ -4,26 +4,34 @@
 #![doc(
     html_logo_url = "https://raw.githubusercontent.com/sirasaki-konoha/minau/refs/heads/master/icon/minau-icon.png"
 )]
-mod display_image;
-mod display_info;
-mod info;
-mod input;
-mod m3u;
-mod macros;
-mod play_music;
-mod play_url;
-mod player;
-use std::{path::Path, process::exit};
-
-use async_compat::CompatExt;
-use clap::Parser;
-use url::Url;
+mod cli;
+use std::path::PathBuf;
+use std::{process::exit, str::FromStr, time::Duration};
+
+use clap::{Parser, Subcommand};
+use cli::devices::{self, DevicesArgs};
+use cli::export::{self, PlaylistArgs};
+use cli::queue::Queue;
+use cli::scan::{self, ScanArgs};
+use cli::{Settings, config};
+use minau::dsp::resample::ResamplerQuality;
+use minau::dsp::{DspSettings, EqBand, EqPreset};
+use minau::engine::{Crossfade, FadeCurve};
+use minau::http::{Auth, HostOptions, HttpOptions, RetryPolicy};
+use minau::play_url::StreamOptions;
+use minau::playlist::{PathStyle, PlaylistEntry};
+use minau::replaygain::{ReplayGainMode, ReplayGainOptions};
+use minau::sink::{ResampleMode, SinkSpec};
+use minau::{EngineOptions, MinauError, err, loudness, playlist};
 
 #[derive(Parser)]
 #[command(name = env!("CARGO_PKG_NAME"))]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(about = env!("CARGO_PKG_DESCRIPTION"))]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Files to play (multiple selections allowed)
     files: Vec<String>,
     /// Specify the default playback volume (minimum: 1, maximum: 100)
@@ -32,17 +40,140 @@ struct Cli {
     /// Display album art in a GUI
     #[arg(short, long)]
     gui: bool,
+    /// Audio output: cpal, null, null:fast or wav:<path> [default: cpal]
+    #[arg(short, long)]
+    output: Option<SinkSpec>,
+    /// Output device name, part of it, or index from `minau devices`
+    #[arg(short, long, value_name = "NAME|INDEX")]
+    device: Option<String>,
+    /// Audio host (API) to open the device on, e.g. ALSA, JACK or WASAPI
+    #[arg(long)]
+    host: Option<String>,
+    /// Open the device at the track's rate (auto, never) or its default rate (always) [default: auto]
+    #[arg(long, value_name = "MODE")]
+    resample: Option<ResampleMode>,
+    /// Resampler: fast, linear, medium, high or best [default: high]
+    #[arg(long, value_name = "QUALITY")]
+    resampler_quality: Option<ResamplerQuality>,
+    /// Overlap consecutive tracks by this many seconds (0 disables)
+    #[arg(long, value_name = "SECS")]
+    crossfade: Option<f32>,
+    /// Crossfade curve: equal-power or linear [default: equal-power]
+    #[arg(long, value_name = "CURVE")]
+    crossfade_curve: Option<FadeCurve>,
+    /// Apply ReplayGain tags: off, track, album or auto [default: off]
+    #[arg(long, value_name = "MODE")]
+    replaygain: Option<ReplayGainMode>,
+    /// Extra gain in dB added to tagged ReplayGain values
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    replaygain_preamp: Option<f32>,
+    /// Measure untagged files and normalize them to the target loudness
+    #[arg(long)]
+    normalize: bool,
+    /// Loudness that --normalize aims for, in LUFS [default: -18]
+    #[arg(long, value_name = "LUFS", allow_negative_numbers = true)]
+    target_lufs: Option<f32>,
+    /// Add a parametric EQ band as FREQ:GAIN[:Q] (repeatable)
+    #[arg(long, value_name = "BAND", allow_negative_numbers = true)]
+    eq: Vec<EqBand>,
+    /// EQ preset: flat, rock, pop, jazz, classical, vocal, bass-boost or treble-boost
+    #[arg(long, value_name = "NAME")]
+    eq_preset: Option<EqPreset>,
+    /// Bass shelf gain in dB
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    bass: Option<f32>,
+    /// Treble shelf gain in dB
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    treble: Option<f32>,
+    /// Stereo balance from -1.0 (left) to 1.0 (right)
+    #[arg(long, value_name = "BALANCE", allow_negative_numbers = true)]
+    balance: Option<f32>,
+    /// Mix all channels down to mono
+    #[arg(long)]
+    mono: bool,
+    /// Gain in dB applied before the equalizer
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    preamp: Option<f32>,
+    /// Keep peaks below full scale with a soft limiter
+    #[arg(long)]
+    limiter: bool,
+    /// Reconnect attempts after a stream drops, 0 to give up at once [default: 5]
+    #[arg(long, value_name = "N")]
+    retries: Option<u32>,
+    /// Seconds before the first reconnect attempt, doubled for each further one [default: 1]
+    #[arg(long, value_name = "SECS")]
+    retry_delay: Option<f32>,
+    /// Send an extra HTTP header as "NAME: VALUE" (repeatable)
+    #[arg(long, value_name = "HEADER")]
+    header: Vec<String>,
+    /// HTTP Basic credentials as USER[:PASSWORD]
+    #[arg(long, value_name = "USER:PASSWORD")]
+    user: Option<String>,
+    /// Send an HTTP Bearer token
+    #[arg(long, value_name = "TOKEN", conflicts_with = "user")]
+    bearer_token: Option<String>,
+    /// HTTP proxy URL, "" to connect directly [default: HTTP_PROXY/HTTPS_PROXY]
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+    /// PEM file with additional trusted CA certificates
+    #[arg(long, value_name = "PATH")]
+    ca_file: Option<PathBuf>,
+    /// Accept invalid TLS certificates
+    #[arg(long)]
+    insecure: bool,
+    /// Seconds to wait for a connection [default: 10]
+    #[arg(long, value_name = "SECS")]
+    connect_timeout: Option<f32>,
+    /// Seconds without data before a stream counts as dropped [default: 15]
+    #[arg(long, value_name = "SECS")]
+    read_timeout: Option<f32>,
+    /// Playlist file the `s` key saves the queue to [default: minau-queue.m3u]
+    #[arg(long, value_name = "PATH")]
+    save_playlist: Option<PathBuf>,
+    /// Save local files as absolute or relative paths [default: absolute]
+    #[arg(long, value_name = "STYLE")]
+    save_paths: Option<PathStyle>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Measure loudness (EBU R128) and optionally write ReplayGain tags
+    Scan(ScanArgs),
+    /// List output devices and the formats they support
+    Devices(DevicesArgs),
+    /// Write playlists
+    Playlist(PlaylistArgs),
 }
 
 const DEFAULT_VOLUME: u16 = 100;
+const DEFAULT_SAVE_PLAYLIST: &str = "minau-queue.m3u";
 const MIN_VOLUME: u16 = 1;
 const MAX_VOLUME: u16 = 100;
 
 fn main() {
-    let args = Cli::parse();
+    let mut args = Cli::parse();
+
+    if let Some(command) = args.command {
+        match command {
+            Command::Scan(scan_args) => finish(scan::run(scan_args), "scanned"),
+            Command::Devices(devices_args) => {
+                if let Err(e) = devices::run(devices_args) {
+                    err!("{}", e);
+                    exit(1);
+                }
+            }
+            Command::Playlist(playlist_args) => finish(export::run(playlist_args), "exported"),
+        }
+        return;
+    }
+    let config = config::load().unwrap_or_else(|e| {
+        err!("{}", e);
+        exit(1);
+    });
 
     let volume = args
         .volume
+        .or(config.volume)
         .map(|vol| {
             if (MIN_VOLUME..=MAX_VOLUME).contains(&vol) {
                 Ok(vol as f32 / 100.0)
@@ -61,43 +192,299 @@ fn main() {
         exit(1);
     }
 
-    for path in args.files {
-        let path_extens: &Path = path.as_ref();
-        if let Some(ext) = path_extens.extension()
-            && (ext == "m3u" || ext == "m3u8")
-        {
-            smol::block_on(async {
-                m3u::play_m3u(&path, volume, args.gui).compat().await;
-            });
-            continue;
+    let mut output = match args.output.take() {
+        Some(output) => output,
+        None => parse_config_value(config.output.as_deref(), "output").unwrap_or_default(),
+    };
+    match &mut output {
+        SinkSpec::Cpal(spec) => {
+            spec.device = args
+                .device
+                .take()
+                .or(spec.device.take())
+                .or(config.device.clone());
+            spec.host = args.host.take().or(config.host.clone());
+        }
+        _ if args.device.is_some() || args.host.is_some() => {
+            err!("--device and --host only apply to the cpal output");
+            exit(1);
+        }
+        _ => {}
+    }
+
+    let resample = args
+        .resample
+        .or_else(|| parse_config_value(config.resample.as_deref(), "resample"))
+        .unwrap_or_default();
+
+    let resampler_quality = args
+        .resampler_quality
+        .or_else(|| parse_config_value(config.resampler_quality.as_deref(), "resampler_quality"))
+        .unwrap_or_default();
+
+    let crossfade_secs = args.crossfade.or(config.crossfade).unwrap_or(0.0);
+    if !crossfade_secs.is_finite() || crossfade_secs < 0.0 {
+        err!("{} is not available crossfade length", crossfade_secs);
+        exit(1);
+    }
+    let curve = args
+        .crossfade_curve
+        .or_else(|| parse_config_value(config.crossfade_curve.as_deref(), "crossfade_curve"))
+        .unwrap_or_default();
+    let crossfade = (crossfade_secs > 0.0).then(|| Crossfade {
+        duration: Duration::from_secs_f32(crossfade_secs),
+        curve,
+    });
+
+    let mut replay_gain = ReplayGainOptions {
+        mode: args
+            .replaygain
+            .or_else(|| parse_config_value(config.replaygain.as_deref(), "replaygain"))
+            .unwrap_or_default(),
+        preamp: args
+            .replaygain_preamp
+            .or(config.replaygain_preamp)
+            .unwrap_or(0.0),
+    };
+    if !replay_gain.preamp.is_finite() {
+        err!("{} is not available preamp", replay_gain.preamp);
+        exit(1);
+    }
+
+    let normalize = args.normalize || config.normalize.unwrap_or(false);
+    if normalize {
+        let target = args
+            .target_lufs
+            .or(config.target_lufs)
+            .unwrap_or(loudness::REPLAYGAIN_REFERENCE as f32);
+        if !target.is_finite() {
+            err!("{} is not available target loudness", target);
+            exit(1);
+        }
+        // 測定したゲインもタグのゲインも -18 LUFS 基準なので差分をプリアンプに加える
+        replay_gain.preamp += target - loudness::REPLAYGAIN_REFERENCE as f32;
+        if replay_gain.mode == ReplayGainMode::Off {
+            replay_gain.mode = ReplayGainMode::Track;
         }
+    }
+
+    let dsp = dsp_settings(&args, &config);
+
+    let stream = stream_options(&args, &config);
+
+    let save_playlist = args
+        .save_playlist
+        .take()
+        .or(config.save_playlist.clone())
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SAVE_PLAYLIST));
+    let save_paths = args
+        .save_paths
+        .or_else(|| parse_config_value(config.save_paths.as_deref(), "save_paths"))
+        .unwrap_or_default();
+
+    let mut settings = Settings {
+        volume,
+        gui: args.gui || config.gui.unwrap_or(false),
+        normalize,
+        engine: EngineOptions {
+            output,
+            resample,
+            resampler_quality,
+            crossfade,
+            replay_gain,
+            dsp,
+        },
+        stream,
+        save_playlist,
+        save_paths,
+    };
+
+    let mut entries = Vec::new();
+    let mut failed: Vec<(String, MinauError)> = Vec::new();
 
-        let bind = path.clone();
-        if (bind.starts_with("file://")
-            || bind.starts_with("http://")
-            || bind.starts_with("https://"))
-            && let Ok(url) = Url::parse(&bind)
-        {
-            if let Ok(file_url) = url.to_file_path() {
-                smol::block_on(async {
-                    play_music::play_music(
-                        file_url.to_string_lossy().to_string(),
-                        volume,
-                        args.gui,
-                        None,
-                    )
-                    .await;
-                });
-                continue;
+    for path in args.files {
+        // リモートのプレイリストはキューが取得して展開する
+        let remote = path.starts_with("http://") || path.starts_with("https://");
+        if !remote && playlist::is_playlist_file(path.as_ref()) {
+            match playlist::load(&path) {
+                Ok(list) => entries.extend(list),
+                Err(e) => {
+                    err!("Skipping {}: {}", path, e);
+                    failed.push((path, e));
+                }
             }
-            smol::block_on(async {
-                play_url::play_url(&bind, volume, None).compat().await;
-            });
             continue;
         }
 
-        smol::block_on(async {
-            play_music::play_music(&path, volume, args.gui, None).await;
+        entries.push(PlaylistEntry::new(&path));
+    }
+
+    // 認証情報は指定された URL のオリジンにだけ送り、リモートのプレイリストに
+    // 書かれた他のサーバーには渡さない
+    settings.stream.http.auth_scope = entries
+        .iter()
+        .filter(|entry| {
+            entry.location.starts_with("http://") || entry.location.starts_with("https://")
+        })
+        .map(|entry| entry.location.clone())
+        .collect();
+    failed.extend(Queue::new(settings).play_all(entries));
+    finish(failed, "played");
+}
+
+/// Collects the DSP chain settings from the arguments and the config file.
+fn dsp_settings(args: &Cli, config: &config::Config) -> DspSettings {
+    let mut eq = args
+        .eq_preset
+        .or_else(|| parse_config_value(config.eq_preset.as_deref(), "eq_preset"))
+        .unwrap_or_default()
+        .bands();
+    if args.eq.is_empty() {
+        for band in config.eq.iter().flatten() {
+            eq.extend(parse_config_value::<EqBand>(Some(band), "eq"));
+        }
+    } else {
+        eq.extend(args.eq.iter().copied());
+    }
+
+    let settings = DspSettings {
+        preamp: args.preamp.or(config.preamp).unwrap_or(0.0),
+        eq,
+        bass: args.bass.or(config.bass).unwrap_or(0.0),
+        treble: args.treble.or(config.treble).unwrap_or(0.0),
+        balance: args.balance.or(config.balance).unwrap_or(0.0),
+        mono: args.mono || config.mono.unwrap_or(false),
+        limiter: args.limiter || config.limiter.unwrap_or(false),
+        ..Default::default()
+    };
+
+    for (name, value) in [
+        ("preamp", settings.preamp),
+        ("bass", settings.bass),
+        ("treble", settings.treble),
+    ] {
+        if !value.is_finite() {
+            err!("{} is not available {}", value, name);
+            exit(1);
+        }
+    }
+    if !(-1.0..=1.0).contains(&settings.balance) {
+        err!("{} is not available balance", settings.balance);
+        exit(1);
+    }
+    settings
+}
+
+/// Collects the reconnect and HTTP client settings from the arguments and
+/// the config file.
+fn stream_options(args: &Cli, config: &config::Config) -> StreamOptions {
+    let mut retry = RetryPolicy::default();
+    if let Some(retries) = args.retries.or(config.retries) {
+        retry.max_retries = retries;
+    }
+    if let Some(delay) = args.retry_delay.or(config.retry_delay) {
+        retry.initial_delay = parse_secs(delay, "retry delay");
+    }
+
+    let mut http = HttpOptions::default();
+    // 設定ファイルのヘッダーを先に入れ、同名ならコマンドラインが勝つ
+    for header in config.headers.iter().chain(&args.header) {
+        http.headers.push(parse_header(header));
+    }
+    http.auth = match (&args.user, &args.bearer_token) {
+        (Some(user), _) => Some(parse_user(user)),
+        (_, Some(token)) => Some(Auth::Bearer(token.clone())),
+        _ => config_auth(
+            config.user.as_deref(),
+            config.bearer_token.as_deref(),
+            "config",
+        ),
+    };
+    http.proxy = args.proxy.clone().or(config.proxy.clone());
+    http.ca_file = args.ca_file.clone().or(config.ca_file.clone());
+    http.insecure = args.insecure || config.insecure.unwrap_or(false);
+    if let Some(secs) = args.connect_timeout.or(config.connect_timeout) {
+        http.connect_timeout = Some(parse_secs(secs, "connect timeout"));
+    }
+    if let Some(secs) = args.read_timeout.or(config.read_timeout) {
+        http.read_timeout = parse_secs(secs, "read timeout");
+    }
+    for (host, host_config) in &config.hosts {
+        http.hosts.push(HostOptions {
+            host: host.clone(),
+            headers: host_config
+                .headers
+                .iter()
+                .map(|h| parse_header(h))
+                .collect(),
+            auth: config_auth(
+                host_config.user.as_deref(),
+                host_config.bearer_token.as_deref(),
+                host,
+            ),
         });
     }
+
+    StreamOptions { retry, http }
+}
+
+fn parse_secs(secs: f32, name: &str) -> Duration {
+    if !secs.is_finite() || secs < 0.0 {
+        err!("{} is not available {}", secs, name);
+        exit(1);
+    }
+    Duration::from_secs_f32(secs)
+}
+
+fn parse_header(header: &str) -> (String, String) {
+    match header.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            (name.trim().to_string(), value.trim().to_string())
+        }
+        _ => {
+            err!("Invalid header \"{}\", expected \"NAME: VALUE\"", header);
+            exit(1);
+        }
+    }
+}
+
+fn parse_user(user: &str) -> Auth {
+    let (user, password) = user.split_once(':').unwrap_or((user, ""));
+    Auth::Basic {
+        user: user.to_string(),
+        password: password.to_string(),
+    }
+}
+
+fn config_auth(user: Option<&str>, token: Option<&str>, section: &str) -> Option<Auth> {
+    match (user, token) {
+        (Some(_), Some(_)) => {
+            err!("Both user and bearer_token are set in {}", section);
+            exit(1);
+        }
+        (Some(user), None) => Some(parse_user(user)),
+        (None, Some(token)) => Some(Auth::Bearer(token.to_string())),
+        (None, None) => None,
+    }
+}
+
+/// Lists the entries that failed and exits with an error if there are any.
+fn finish(failed: Vec<(String, MinauError)>, action: &str) {
+    if !failed.is_empty() {
+        err!("{} entries could not be {}:", failed.len(), action);
+        for (path, e) in &failed {
+            eprintln!("  {}: {}", path, e);
+        }
+        exit(1);
+    }
+}
+
+/// Parses a string value from the config file, exiting on invalid input.
+fn parse_config_value<T: FromStr<Err = String>>(value: Option<&str>, key: &str) -> Option<T> {
+    value.map(|value| {
+        value.parse().unwrap_or_else(|e| {
+            err!("Invalid {} in config: {}", key, e);
+            exit(1);
+        })
+    })
 }
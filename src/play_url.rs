@@ -1,35 +1,44 @@
 #![allow(clippy::needless_range_loop)]
-use crate::input::deinit;
-use crate::{err, input};
-use async_channel::Receiver;
+use crate::engine::{EngineOptions, PlaybackEngine};
+use crate::err;
+use crate::error::{MinauError, Result};
+use crate::http::{
+    self, HlsStream, HttpClient, HttpOptions, IcyDemuxer, IcyMetadata, RangeSource, RetryPolicy,
+    StreamState, hls,
+};
+use crate::player::player_structs::Player;
+use crate::playlist::{self, PlaylistEntry};
+use crate::source::Source;
+use async_channel::{Receiver, Sender, TryRecvError};
+use async_compat::CompatExt;
 use bytes::Bytes;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Stream, StreamConfig};
-use crossterm::cursor::MoveToPreviousLine;
-use crossterm::terminal::{self, Clear, ClearType, SetTitle};
-use crossterm::{cursor, execute};
-use http_body_util::BodyExt;
-use hyper::Request;
-use hyper_tls::HttpsConnector;
-use hyper_util::client::legacy::Client;
-use hyper_util::rt::TokioExecutor;
+use http_body_util::{BodyExt, Limited};
+use hyper::body::Incoming;
 use parking_lot::Mutex;
-use ringbuf::HeapRb;
 use std::collections::VecDeque;
-use std::env;
-use std::io::{self, Read, Result as IoResult, Write, stdout};
-use std::process::exit;
+use std::io::{ErrorKind, Read, Result as IoResult};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex as StdMutex};
-use std::time::Duration;
-use symphonia::core::audio::{AudioBufferRef, SampleBuffer};
-use symphonia::core::codecs::{CODEC_TYPE_NULL, DecoderOptions};
+use std::time::{Duration, Instant};
 use symphonia::core::errors::Error;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
+use symphonia::core::io::MediaSource;
 use symphonia::core::probe::Hint;
-use unicode_width::UnicodeWidthStr;
+
+/// Bytes looked at to guess the container format
+const DETECT_LEN: usize = 2000;
+/// Playlists larger than this are refused rather than read into memory
+const MAX_PLAYLIST_LEN: usize = 1024 * 1024;
+/// After running dry, a stream waits for this many bytes before resuming
+const REFILL_LEN: usize = 16 * 1024;
+/// A stream that delivers nothing for this long, other than while
+/// reconnecting, ends
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a waiting stream checks whether it was interrupted
+const WAIT_SLICE: Duration = Duration::from_millis(100);
+/// Downloads are handed to the reader in pieces of at most this size
+const CHUNK_LEN: usize = 16 * 1024;
+/// Pieces a download may get ahead of playback, 1 MiB in all
+const BUFFERED_CHUNKS: usize = 64;
 
 macro_rules! eprintln {
     ($($msg: expr), *) => {
@@ -39,12 +48,18 @@ macro_rules! eprintln {
     };
 }
 
+/// Reads the chunks a download sends over a channel. Only the reader holds
+/// the receiving end, so dropping it ends the download.
 struct StreamReader {
-    buffer: Arc<StdMutex<VecDeque<u8>>>,
-    eof: Arc<AtomicBool>,
+    rx: Receiver<Bytes>,
+    buffer: VecDeque<u8>,
+    eof: bool,
+    state: Arc<Mutex<StreamState>>,
+    /// Set by the engine to stop waiting for data
+    interrupt: Arc<AtomicBool>,
 }
 
-impl symphonia::core::io::MediaSource for StreamReader {
+impl MediaSource for StreamReader {
     fn is_seekable(&self) -> bool {
         false
     }
@@ -64,612 +79,874 @@ impl std::io::Seek for StreamReader {
 }
 
 impl StreamReader {
-    fn new(rx: Receiver<Bytes>) -> Self {
-        let buffer = Arc::new(StdMutex::new(VecDeque::new()));
-        let eof = Arc::new(AtomicBool::new(false));
-
-        let buffer_clone = Arc::clone(&buffer);
-        let eof_clone = Arc::clone(&eof);
+    fn new(rx: Receiver<Bytes>, state: Arc<Mutex<StreamState>>) -> Self {
+        Self {
+            rx,
+            buffer: VecDeque::new(),
+            eof: false,
+            state,
+            interrupt: Arc::new(AtomicBool::new(false)),
+        }
+    }
 
-        std::thread::spawn(move || {
-            loop {
-                match rx.recv_blocking() {
-                    Ok(chunk) => {
-                        let mut buf = buffer_clone.lock().unwrap();
-                        buf.extend(chunk.iter());
-                    }
-                    Err(_) => {
-                        eof_clone.store(true, Ordering::Relaxed);
-                        break;
-                    }
+    /// Moves chunks that have already arrived into the buffer until it
+    /// holds `min_size` bytes. The rest stay in the channel, which holds
+    /// back the download.
+    fn take_arrived(&mut self, min_size: usize) {
+        while self.buffer.len() < min_size {
+            match self.rx.try_recv() {
+                Ok(chunk) => self.buffer.extend(chunk.iter()),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Closed) => {
+                    self.eof = true;
+                    return;
                 }
             }
-        });
-
-        Self { buffer, eof }
+        }
     }
 
-    fn wait_for_data(&self, min_size: usize, timeout: Duration) -> bool {
-        let start = std::time::Instant::now();
+    /// Waits until `min_size` bytes are buffered or the download has ended,
+    /// which an interruption counts as. Fails when nothing arrives for
+    /// `timeout` while the download is not reconnecting, or when the
+    /// download failed.
+    fn wait_for_data(&mut self, min_size: usize, timeout: Duration) -> IoResult<()> {
+        let mut deadline = Instant::now() + timeout;
 
         loop {
-            let size = {
-                let buf = self.buffer.lock().unwrap();
-                buf.len()
-            };
-
-            if size >= min_size || self.eof.load(Ordering::Relaxed) {
-                return true;
+            self.take_arrived(min_size);
+            if self.buffer.len() >= min_size || self.eof {
+                return Ok(());
             }
-
-            if start.elapsed() >= timeout {
+            // 止められたら終端として扱い、曲を静かに終わらせる
+            if self.interrupt.load(Ordering::Relaxed) {
+                self.eof = true;
+                return Ok(());
+            }
+            match *self.state.lock() {
+                StreamState::Failed => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::ConnectionAborted,
+                        "Connection lost",
+                    ));
+                }
+                // 再接続の回数と待ち時間は取得側が決める
+                StreamState::Reconnecting { .. } => deadline = Instant::now() + timeout,
+                _ => {}
+            }
+            if Instant::now() >= deadline {
                 eprintln!(
                     "[StreamReader] Timeout waiting for {} bytes (have: {})",
-                    min_size, size
+                    min_size,
+                    self.buffer.len()
                 );
-                return false;
+                return Err(std::io::Error::new(ErrorKind::TimedOut, "Stream stalled"));
             }
 
-            std::thread::sleep(Duration::from_millis(10));
+            let chunk = smol::block_on(smol::future::or(
+                async { Some(self.rx.recv().await) },
+                async {
+                    smol::Timer::after(WAIT_SLICE).await;
+                    None
+                },
+            ));
+            match chunk {
+                Some(Ok(chunk)) => self.buffer.extend(chunk.iter()),
+                Some(Err(_)) => self.eof = true,
+                None => {}
+            }
         }
     }
 }
 
 impl Read for StreamReader {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-        loop {
+        self.take_arrived(buf.len());
+        if self.buffer.is_empty() && !self.eof {
+            // 途切れたら、ある程度溜まるまで待ってから再開する。
+            // 接続が切れた場合は取得側が再接続するか、諦めて終わる
             {
-                let mut buffer = self.buffer.lock().unwrap();
-                if !buffer.is_empty() {
-                    let to_copy = buffer.len().min(buf.len());
-                    for i in 0..to_copy {
-                        buf[i] = buffer.pop_front().unwrap();
-                    }
-                    return Ok(to_copy);
+                let mut state = self.state.lock();
+                if *state == StreamState::Streaming {
+                    *state = StreamState::Buffering;
                 }
             }
-
-            if self.eof.load(Ordering::Relaxed) {
-                return Ok(0);
-            }
-
-            if !self.wait_for_data(1, Duration::from_secs(5)) {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::TimedOut,
-                    "Timeout waiting for data",
-                ));
+            self.wait_for_data(REFILL_LEN, STALL_TIMEOUT)?;
+            let mut state = self.state.lock();
+            if *state == StreamState::Buffering {
+                *state = StreamState::Streaming;
             }
         }
-    }
-}
-
-fn convert_samples(buffer: AudioBufferRef) -> Vec<f32> {
-    let spec = *buffer.spec();
-    let duration = buffer.frames();
-
-    let mut sample_buf = SampleBuffer::<f32>::new(duration as u64, spec);
-    sample_buf.copy_interleaved_ref(buffer);
-    sample_buf.samples().to_vec()
-}
 
-// シンプルな線形補間リサンプラー
-fn resample_linear(input: &[f32], from_rate: u32, to_rate: u32, channels: usize) -> Vec<f32> {
-    if from_rate == to_rate {
-        return input.to_vec();
-    }
-
-    let ratio = from_rate as f64 / to_rate as f64;
-    let input_frames = input.len() / channels;
-    let output_frames = (input_frames as f64 / ratio).ceil() as usize;
-    let mut output = Vec::with_capacity(output_frames * channels);
-
-    for out_frame in 0..output_frames {
-        let src_pos = out_frame as f64 * ratio;
-        let src_frame = src_pos.floor() as usize;
-        let frac = src_pos - src_frame as f64;
-
-        if src_frame + 1 >= input_frames {
-            // 最後のフレームをそのまま使用
-            for ch in 0..channels {
-                let idx = src_frame * channels + ch;
-                if idx < input.len() {
-                    output.push(input[idx]);
-                } else {
-                    output.push(0.0);
-                }
-            }
-        } else {
-            // 線形補間
-            for ch in 0..channels {
-                let idx1 = src_frame * channels + ch;
-                let idx2 = (src_frame + 1) * channels + ch;
-                let sample1 = input[idx1];
-                let sample2 = input[idx2];
-                let interpolated = sample1 + (sample2 - sample1) * frac as f32;
-                output.push(interpolated);
-            }
+        let len = self.buffer.len().min(buf.len());
+        for (out, byte) in buf.iter_mut().zip(self.buffer.drain(..len)) {
+            *out = byte;
         }
+        Ok(len)
     }
-
-    output
 }
 
-pub struct UrlPlayer {
-    _stream: Stream,
-    paused: Arc<AtomicBool>,
-    volume: Arc<Mutex<f32>>,
-    finished: Arc<AtomicBool>,
-    sample_rate: u32,
-    channels: u32,
+/// Byte counters of an HTTP download, shared with the fetching thread.
+#[derive(Clone)]
+pub struct DownloadProgress {
     downloaded_bytes: Arc<Mutex<u64>>,
-    total_bytes: Arc<Mutex<Option<u64>>>,
+    total_bytes: Option<u64>,
+    state: Arc<Mutex<StreamState>>,
 }
 
-unsafe impl Send for UrlPlayer {}
+impl DownloadProgress {
+    pub fn get_downloaded_bytes(&self) -> u64 {
+        *self.downloaded_bytes.lock()
+    }
 
-impl UrlPlayer {
-    pub fn set_volume(&self, volume: f32) {
-        *self.volume.lock() = volume.clamp(0.0, 1.0);
+    pub fn get_downloaded_mb(&self) -> f64 {
+        self.get_downloaded_bytes() as f64 / 1024.0 / 1024.0
+    }
+
+    pub fn get_total_bytes(&self) -> Option<u64> {
+        self.total_bytes
     }
 
-    pub fn get_volume(&self) -> f32 {
-        *self.volume.lock()
+    pub fn get_total_mb(&self) -> Option<f64> {
+        self.get_total_bytes().map(|b| b as f64 / 1024.0 / 1024.0)
     }
 
-    pub fn pause(&self) {
-        self.paused.store(true, Ordering::Relaxed);
+    pub fn get_download_progress(&self) -> Option<f32> {
+        let downloaded = self.get_downloaded_bytes();
+        self.get_total_bytes()
+            .map(|total| (downloaded as f32 / total as f32) * 100.0)
     }
 
-    pub fn resume(&self) {
-        self.paused.store(false, Ordering::Relaxed);
+    /// Whether the stream is playing, buffering or reconnecting.
+    pub fn get_state(&self) -> StreamState {
+        *self.state.lock()
     }
+}
 
-    pub fn is_paused(&self) -> bool {
-        self.paused.load(Ordering::Relaxed)
+/// Options for opening remote streams.
+#[derive(Clone, Debug, Default)]
+pub struct StreamOptions {
+    /// Reconnecting after the connection drops
+    pub retry: RetryPolicy,
+    /// Headers, credentials, proxy and TLS settings of the requests
+    pub http: HttpOptions,
+}
+
+/// An opened remote stream, ready to be queued on a [`PlaybackEngine`].
+pub struct UrlPlayer {
+    player: Player,
+    progress: DownloadProgress,
+    icy: Option<IcyMetadata>,
+}
+
+impl UrlPlayer {
+    pub fn player(&self) -> &Player {
+        &self.player
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.finished.load(Ordering::Relaxed)
+    pub fn into_player(self) -> Player {
+        self.player
     }
 
-    pub fn sample_rate(&self) -> u32 {
-        self.sample_rate
+    pub fn progress(&self) -> DownloadProgress {
+        self.progress.clone()
     }
 
-    pub fn channels(&self) -> u32 {
-        self.channels
+    /// Station name and current song, for internet radio streams.
+    pub fn icy(&self) -> Option<IcyMetadata> {
+        self.icy.clone()
     }
 
-    pub fn get_downloaded_bytes(&self) -> u64 {
-        *self.downloaded_bytes.lock()
+    pub fn sample_rate(&self) -> u32 {
+        self.player.sample_rate
     }
 
-    pub fn get_downloaded_mb(&self) -> f64 {
-        self.get_downloaded_bytes() as f64 / 1024.0 / 1024.0
+    pub fn channels(&self) -> u16 {
+        self.player.channels
     }
 
-    pub fn get_total_bytes(&self) -> Option<u64> {
-        *self.total_bytes.lock()
+    /// Whether the server allows seeking in this stream.
+    pub fn is_seekable(&self) -> bool {
+        self.player.seekable
     }
 
-    pub fn get_total_mb(&self) -> Option<f64> {
-        self.get_total_bytes().map(|b| b as f64 / 1024.0 / 1024.0)
+    /// Length of the stream, if the container reports it.
+    pub fn duration(&self) -> Option<Duration> {
+        self.player.duration()
     }
 
-    pub fn get_download_progress(&self) -> Option<f32> {
-        let downloaded = self.get_downloaded_bytes();
-        self.get_total_bytes()
-            .map(|total| (downloaded as f32 / total as f32) * 100.0)
+    /// Starts a new engine with this stream as the only queued track.
+    pub fn play_with(self, options: &EngineOptions) -> Result<PlaybackEngine> {
+        self.player.play_with(options)
     }
 }
 
-pub async fn setup_url_player(
-    url: &str,
-    volume: f32,
-) -> Result<UrlPlayer, Box<dyn std::error::Error>> {
-    let https = HttpsConnector::new();
-    let client: Client<_, String> = Client::builder(TokioExecutor::new()).build(https);
-
-    let mut current_url = url.to_string();
-    let mut redirect_count = 0;
-    let max_redirects = 10;
-
-    let response = loop {
-        let uri = current_url.parse::<hyper::Uri>()?;
-        let req = Request::builder()
-            .uri(uri)
-            .header("User-Agent", format!("minau/{}", env!("CARGO_PKG_VERSION")))
-            .body(String::new())?;
-
-        let resp = client.request(req).await?;
-        let status = resp.status();
-
-        if status.is_redirection() {
-            if redirect_count >= max_redirects {
-                return Err("Too many redirects".into());
-            }
+/// What a URL turned out to point at.
+#[allow(clippy::large_enum_variant)]
+pub enum UrlTarget {
+    Stream(UrlPlayer),
+    /// Entries of a playlist, resolved against its URL
+    Playlist(Vec<PlaylistEntry>),
+}
 
-            if let Some(location) = resp.headers().get("location") {
-                current_url = location.to_str()?.to_string();
+pub async fn setup_url_player(url: &str) -> Result<UrlPlayer> {
+    setup_url_player_with(url, &StreamOptions::default()).await
+}
 
-                if !current_url.starts_with("http") {
-                    let base_uri = url.parse::<hyper::Uri>()?;
-                    let scheme = base_uri.scheme_str().unwrap_or("https");
-                    let authority = base_uri.authority().ok_or("No authority in URL")?;
-                    current_url = format!("{}://{}{}", scheme, authority, current_url);
-                }
+/// Opens `url` for playback. Must run inside a Tokio context, e.g. wrapped
+/// in `async_compat::Compat`.
+///
+/// Playlists other than HLS are refused; use [`open_url`] to get their entries.
+pub async fn setup_url_player_with(url: &str, options: &StreamOptions) -> Result<UrlPlayer> {
+    match open_url(url, options).await? {
+        UrlTarget::Stream(player) => Ok(player),
+        UrlTarget::Playlist(_) => Err(MinauError::Playlist(format!(
+            "{} is a playlist, not an audio stream",
+            url
+        ))),
+    }
+}
 
-                redirect_count += 1;
-                continue;
-            } else {
-                return Err("Redirect without Location header".into());
+/// Opens `url` for playback, or reads the entries of the playlist it points
+/// at. Must run inside a Tokio context, e.g. wrapped in `async_compat::Compat`.
+pub async fn open_url(url: &str, options: &StreamOptions) -> Result<UrlTarget> {
+    let client = HttpClient::new(options.http.clone())?;
+    let mut fetched = http::get(&client, url, None).await?;
+
+    let total_bytes = fetched.content_length();
+    // 拡張子はリダイレクト後の URL から見る
+    let content_type = fetched.content_type().map(String::from);
+    let final_url = fetched.url.clone();
+    let icy = IcyMetadata::from_response(&fetched);
+    let metaint = fetched.icy_metaint();
+    let url = url.to_string();
+
+    if is_playlist(&fetched) {
+        let mut body = fetched.response.into_body();
+        let head = read_head(&mut body, client.read_timeout()).await?;
+        let head_text = playlist::decode(&head);
+        if playlist::detect(&head_text, &final_url, content_type.as_deref()).is_some() {
+            let rest = Limited::new(body, MAX_PLAYLIST_LEN.saturating_sub(head.len()))
+                .collect()
+                .await
+                .map_err(|e| MinauError::Http(e.to_string()))?
+                .to_bytes();
+            let text = playlist::decode(&[&head[..], &rest[..]].concat());
+            if hls::is_hls(&text) {
+                return open_hls(client, &final_url, url, &text, options.retry)
+                    .await
+                    .map(UrlTarget::Stream);
             }
+            // 相対パスはリダイレクト後の URL を基準にする
+            return match playlist::detect(&text, &final_url, content_type.as_deref()) {
+                Some(format) => Ok(UrlTarget::Playlist(playlist::resolve_remote(
+                    playlist::parse(&text, format),
+                    &final_url,
+                ))),
+                None => Err(MinauError::Playlist(format!(
+                    "{} is neither an audio stream nor a playlist",
+                    url
+                ))),
+            };
         }
+        // Content-Type が text でも中身が再生リストでなければ、音声として開き直す
+        fetched = http::get(&client, &final_url, None).await?;
+    }
 
-        if !status.is_success() {
-            return Err(format!("HTTP Error: {}", status).into());
-        }
+    // Range に対応していれば、シーク可能なソースとして開く
+    if let Some(len) = total_bytes.filter(|_| fetched.accepts_ranges() && metaint.is_none()) {
+        let source = RangeSource::new(client, fetched, len, options.retry);
+        let downloaded_bytes = source.downloaded();
+        let state = source.state();
+        let interrupt = source.interrupt();
+
+        let mut player = std::thread::spawn(move || -> Result<Player> {
+            let detect_buf = source.peek(DETECT_LEN, Duration::from_secs(10));
+            if detect_buf.is_empty() {
+                return Err(MinauError::Http("Failed to buffer initial data".into()));
+            }
+            let hint = http::detect_hint(content_type.as_deref(), &final_url, &detect_buf);
 
-        break resp;
-    };
+            open_stream(Box::new(source), hint, url)
+        })
+        .join()
+        .map_err(|_| MinauError::Decode("Stream setup thread panicked".into()))??;
+        player.interrupt = Some(interrupt);
+
+        return Ok(UrlTarget::Stream(UrlPlayer {
+            player,
+            progress: DownloadProgress {
+                downloaded_bytes,
+                total_bytes,
+                state,
+            },
+            icy,
+        }));
+    }
 
-    let total_bytes = response
-        .headers()
-        .get("content-length")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|v| v.parse::<u64>().ok());
+    let (tx, rx) = async_channel::bounded::<Bytes>(BUFFERED_CHUNKS);
+    let download = Download::new(client, &fetched, options.retry, icy.clone());
+    let downloaded_bytes = Arc::clone(&download.downloaded);
+    let state = Arc::clone(&download.state);
+    std::thread::spawn(move || smol::block_on(download.run(fetched, tx).compat()));
+
+    let detect = move |data: &[u8]| http::detect_hint(content_type.as_deref(), &final_url, data);
+    let player = open_reader(rx, detect, url, Arc::clone(&state))?;
+
+    Ok(UrlTarget::Stream(UrlPlayer {
+        player,
+        progress: DownloadProgress {
+            downloaded_bytes,
+            total_bytes,
+            state,
+        },
+        icy,
+    }))
+}
 
-    let (tx, rx) = async_channel::bounded::<Bytes>(50);
+/// A progressive HTTP download feeding a [`StreamReader`].
+struct Download {
+    client: HttpClient,
+    url: String,
+    total_bytes: Option<u64>,
+    /// Whether a dropped connection can continue with a range request
+    resumable: bool,
+    retry: RetryPolicy,
+    downloaded: Arc<Mutex<u64>>,
+    state: Arc<Mutex<StreamState>>,
+    icy: Option<IcyMetadata>,
+}
 
-    let downloaded_bytes = Arc::new(Mutex::new(0u64));
-    let downloaded_clone = Arc::clone(&downloaded_bytes);
+impl Download {
+    fn new(
+        client: HttpClient,
+        fetched: &http::Fetched,
+        retry: RetryPolicy,
+        icy: Option<IcyMetadata>,
+    ) -> Self {
+        let total_bytes = fetched.content_length();
+        Self {
+            client,
+            url: fetched.url.clone(),
+            total_bytes,
+            // 途中から取り直せるのは長さの分かるファイルだけ。Range 付きの応答には
+            // ICY メタデータが入らないので、混ざっているものは最初から読み直す
+            resumable: total_bytes.is_some()
+                && fetched.accepts_ranges()
+                && fetched.icy_metaint().is_none(),
+            retry,
+            downloaded: Arc::new(Mutex::new(0)),
+            state: Arc::new(Mutex::new(StreamState::Streaming)),
+            icy,
+        }
+    }
 
-    std::thread::spawn(move || {
-        smol::block_on(async {
-            let mut body = response.into_body();
-
-            while let Some(result) = body.frame().await {
-                match result {
-                    Ok(frame) => {
-                        if let Some(chunk) = frame.data_ref() {
-                            let chunk_size = chunk.len() as u64;
-                            *downloaded_clone.lock() += chunk_size;
-
-                            match tx.send(chunk.clone()).await {
-                                Ok(_) => {}
-                                Err(_) => {
-                                    break;
-                                }
-                            }
+    /// Sends the body of `fetched` to `tx`, reconnecting when the connection
+    /// drops before the end.
+    async fn run(self, fetched: http::Fetched, tx: Sender<Bytes>) {
+        let mut demuxer = self.demuxer(&fetched);
+        let mut body = Some(fetched.response.into_body());
+        // 届いた音声のバイト数。再接続後に重複する分は読み飛ばす
+        let mut received = 0u64;
+        let mut skip = 0u64;
+        let mut attempt = 0;
+
+        loop {
+            // 読む側がいなくなったら繋ぎ直さずに終わる
+            if tx.is_closed() {
+                break;
+            }
+            let Some(current) = body.as_mut() else {
+                if !self.retry.wait(attempt, &self.state).await {
+                    break;
+                }
+                let from = self.resumable.then_some(received);
+                match http::get(&self.client, &self.url, from).await {
+                    Ok(fetched) => {
+                        // Range が無視されたら、最初から読み直して追いつく
+                        skip = match fetched.response.status() {
+                            hyper::StatusCode::PARTIAL_CONTENT => 0,
+                            _ if self.total_bytes.is_some() => received,
+                            _ => 0,
+                        };
+                        if fetched.response.status() != hyper::StatusCode::PARTIAL_CONTENT {
+                            demuxer = self.demuxer(&fetched);
                         }
+                        body = Some(fetched.response.into_body());
                     }
                     Err(e) => {
-                        err!("Stream Error: {}", e);
-                        break;
+                        eprintln!("[Download] Reconnect failed: {}", e);
+                        attempt += 1;
                     }
                 }
-            }
-            drop(tx);
-        })
-    });
-
-    let downloaded_bytes_clone = Arc::clone(&downloaded_bytes);
-    let player = std::thread::spawn(
-        move || -> Result<UrlPlayer, Box<dyn std::error::Error + Send + Sync>> {
-            let reader = StreamReader::new(rx);
-
-            if !reader.wait_for_data(64 * 1024, Duration::from_secs(10)) {
-                return Err("Failed to buffer initial data".into());
-            }
-
-            let buffered_size = 256 * 1024;
-            let mut hint = Hint::new();
-
-            let detect_buf: Vec<u8> = {
-                let buffer = reader.buffer.lock().unwrap();
-                let detect_size = buffer.len().min(2000);
-                buffer.iter().take(detect_size).copied().collect()
+                continue;
             };
 
-            if let Some(kind) = infer::get(&detect_buf) {
-                match kind.mime_type() {
-                    "audio/mpeg" => hint.with_extension("mp3"),
-                    "audio/flac" => hint.with_extension("flac"),
-                    "audio/ogg" => hint.with_extension("ogg"),
-                    "audio/wav" => hint.with_extension("wav"),
-                    "audio/aac" => hint.with_extension("aac"),
-                    "audio/mp4" => hint.with_extension("m4a"),
-                    _ => {
-                        err!("Stream is not supported mime type!");
-                        exit(1);
+            match http::next_chunk(current, self.client.read_timeout()).await {
+                Some(Ok(chunk)) => {
+                    attempt = 0;
+                    *self.state.lock() = StreamState::Streaming;
+                    *self.downloaded.lock() += chunk.len() as u64;
+
+                    // 読み直した応答のメタデータは新しい demuxer が頭から数える
+                    let mut chunk = match demuxer.as_mut() {
+                        Some(demuxer) => Bytes::from(demuxer.feed(&chunk)),
+                        None => chunk,
+                    };
+                    let skipped = skip.min(chunk.len() as u64);
+                    skip -= skipped;
+                    let chunk = chunk.split_off(skipped as usize);
+                    received += chunk.len() as u64;
+
+                    if send_chunked(&tx, chunk).await.is_err() {
+                        break;
                     }
-                };
+                }
+                Some(Err(e)) => {
+                    eprintln!("[Download] Stream Error: {}", e);
+                    body = None;
+                    attempt = 1;
+                }
+                None => {
+                    // 長さが分かっていて足りない場合と、終わりのないラジオは繋ぎ直す
+                    let short = self.total_bytes.is_some_and(|total| received < total);
+                    if !short && self.icy.is_none() {
+                        break;
+                    }
+                    body = None;
+                    attempt = 1;
+                }
             }
+        }
+        drop(tx);
+    }
 
-            let mss = MediaSourceStream::new(
-                Box::new(reader),
-                symphonia::core::io::MediaSourceStreamOptions {
-                    buffer_len: buffered_size,
-                },
-            );
-
-            let meta_opts: MetadataOptions = Default::default();
-            let fmt_opts: FormatOptions = Default::default();
+    /// Strips ICY metadata if the response interleaves it.
+    fn demuxer(&self, fetched: &http::Fetched) -> Option<IcyDemuxer> {
+        // メタデータのブロックは StreamReader に渡す前に取り除く
+        fetched
+            .icy_metaint()
+            .zip(self.icy.clone())
+            .map(|(metaint, icy)| IcyDemuxer::new(metaint, icy))
+    }
+}
 
-            let probed =
-                symphonia::default::get_probe().format(&hint, mss, &fmt_opts, &meta_opts)?;
+/// Plays an HLS playlist served from `base`, feeding its segments to a
+/// [`StreamReader`]. `url` is the address the user asked for.
+async fn open_hls(
+    client: HttpClient,
+    base: &str,
+    url: String,
+    text: &str,
+    retry: RetryPolicy,
+) -> Result<UrlPlayer> {
+    let mut stream = HlsStream::open(client, base, text, retry).await?;
+    let hint = stream.hint();
+    let downloaded_bytes = stream.downloaded();
+    let state = stream.state();
+
+    let (tx, rx) = async_channel::bounded::<Bytes>(BUFFERED_CHUNKS);
+    std::thread::spawn(move || {
+        smol::block_on(
+            async {
+                loop {
+                    match stream.next_segment().await {
+                        Ok(Some(data)) => {
+                            if send_chunked(&tx, Bytes::from(data)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            err!("HLS Error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            .compat(),
+        )
+    });
 
-            let format = probed.format;
+    let player = open_reader(rx, |_| hint, url, Arc::clone(&state))?;
+    Ok(UrlPlayer {
+        player,
+        progress: DownloadProgress {
+            downloaded_bytes,
+            total_bytes: None,
+            state,
+        },
+        icy: None,
+    })
+}
 
-            let track = format
-                .tracks()
-                .iter()
-                .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-                .ok_or("Track not found")?;
+/// Sends `data` in pieces of [`CHUNK_LEN`], so the channel bounds the
+/// number of bytes a download gets ahead. Fails once the reader is gone.
+async fn send_chunked(tx: &Sender<Bytes>, data: Bytes) -> std::result::Result<(), ()> {
+    let mut start = 0;
+    while start < data.len() {
+        let end = (start + CHUNK_LEN).min(data.len());
+        tx.send(data.slice(start..end)).await.map_err(|_| ())?;
+        start = end;
+    }
+    Ok(())
+}
 
-            let track_id = track.id;
-            let codec_params = &track.codec_params;
+/// Buffers the start of the stream arriving on `rx` and opens a player for
+/// it. `hint` is given the first bytes to guess the format from.
+fn open_reader(
+    rx: Receiver<Bytes>,
+    hint: impl FnOnce(&[u8]) -> Hint + Send + 'static,
+    url: String,
+    state: Arc<Mutex<StreamState>>,
+) -> Result<Player> {
+    std::thread::spawn(move || -> Result<Player> {
+        let mut reader = StreamReader::new(rx, state);
+
+        if reader
+            .wait_for_data(64 * 1024, Duration::from_secs(10))
+            .is_err()
+        {
+            return Err(MinauError::Http("Failed to buffer initial data".into()));
+        }
 
-            let sample_rate = codec_params
-                .sample_rate
-                .ok_or("Samplerate is not available")?;
-            let channels = codec_params.channels.ok_or("Channels is not available")?;
-            let channels_count = channels.count() as u16;
+        let detect_buf: Vec<u8> = reader.buffer.iter().take(DETECT_LEN).copied().collect();
+        let hint = hint(&detect_buf);
 
-            let dec_opts: DecoderOptions = Default::default();
-            let decoder = symphonia::default::get_codecs().make(codec_params, &dec_opts)?;
+        let interrupt = Arc::clone(&reader.interrupt);
+        let mut player = open_stream(Box::new(reader), hint, url)?;
+        player.interrupt = Some(interrupt);
+        Ok(player)
+    })
+    .join()
+    .map_err(|_| MinauError::Decode("Stream setup thread panicked".into()))?
+}
 
-            // cpal setup
-            let host = cpal::default_host();
-            let device = host
-                .default_output_device()
-                .ok_or("No output device available")?;
-            let device_config = device.default_output_config().unwrap();
+fn open_stream(reader: Box<dyn MediaSource>, hint: Hint, name: String) -> Result<Player> {
+    let source = Source::Stream { reader, hint, name };
+    match Player::from_source(source) {
+        // どのフォーマットにも当てはまらないと最後まで読んで EOF になる
+        Err(MinauError::Probe(Error::IoError(e))) if e.kind() == ErrorKind::UnexpectedEof => {
+            Err(MinauError::Probe(Error::Unsupported(
+                "no supported audio format found in the stream",
+            )))
+        }
+        result => result,
+    }
+}
 
-            let output_sample_rate = device_config.sample_rate().0;
+/// Whether the response is, or may be, a playlist rather than audio.
+/// Text and XML responses are sniffed; those that turn out not to be a
+/// playlist are played as audio.
+fn is_playlist(fetched: &http::Fetched) -> bool {
+    let content_type = fetched.content_type();
+    let textual = content_type.is_some_and(|t| {
+        let t = t.to_ascii_lowercase();
+        t.starts_with("text/") || t.contains("xml")
+    });
+    textual || playlist::detect("", &fetched.url, content_type).is_some()
+}
 
-            eprintln!(
-                "[Audio] Source: {}Hz, Device: {}Hz, Channels: {}",
-                sample_rate, output_sample_rate, channels_count
-            );
+/// Reads the first [`DETECT_LEN`] bytes of `body`, or all of a shorter one.
+async fn read_head(body: &mut Incoming, stall_timeout: Duration) -> Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(DETECT_LEN);
+    while head.len() < DETECT_LEN {
+        match http::next_chunk(body, stall_timeout).await {
+            Some(Ok(chunk)) => head.extend_from_slice(&chunk),
+            Some(Err(e)) => return Err(e),
+            None => break,
+        }
+    }
+    Ok(head)
+}
 
-            let config = StreamConfig {
-                channels: device_config.channels(),
-                sample_rate: device_config.sample_rate(),
-                buffer_size: cpal::BufferSize::Default,
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::test_server::{TestResponse, TestServer};
+    use crate::sink::SinkSpec;
+
+    /// Silent MPEG-1 Layer III frames, 128 kbit/s at 44.1 kHz stereo.
+    fn silent_mp3(frames: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        frame.repeat(frames)
+    }
 
-            let paused = Arc::new(AtomicBool::new(false));
-            let volume_arc = Arc::new(Mutex::new(volume));
-            let finished = Arc::new(AtomicBool::new(false));
+    fn reader() -> (Sender<Bytes>, StreamReader) {
+        let (tx, rx) = async_channel::bounded(4);
+        (
+            tx,
+            StreamReader::new(rx, Arc::new(Mutex::new(StreamState::Streaming))),
+        )
+    }
 
-            let format = Arc::new(StdMutex::new(format));
-            let decoder = Arc::new(StdMutex::new(decoder));
+    #[test]
+    fn reads_until_download_ends() {
+        let (tx, mut reader) = reader();
+        tx.send_blocking(Bytes::from_static(b"abc")).unwrap();
+        tx.send_blocking(Bytes::from_static(b"def")).unwrap();
+        drop(tx);
 
-            let (mut producer, mut consumer) =
-                HeapRb::<f32>::new(output_sample_rate as usize * 2).split();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"abcdef");
+    }
 
-            let format_clone = Arc::clone(&format);
-            let decoder_clone = Arc::clone(&decoder);
-            let finished_clone = Arc::clone(&finished);
+    #[test]
+    fn fails_once_download_failed() {
+        let (tx, mut reader) = reader();
+        *reader.state.lock() = StreamState::Failed;
+        let e = reader.read(&mut [0; 16]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ConnectionAborted);
+        drop(tx);
+    }
 
-            // Decoder thread
-            std::thread::spawn(move || {
-                let mut current_samples = Vec::new();
-                let mut current_index = 0;
+    #[test]
+    fn stops_waiting_when_interrupted() {
+        let (tx, mut reader) = reader();
+        let interrupt = Arc::clone(&reader.interrupt);
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            interrupt.store(true, Ordering::Relaxed);
+        });
 
-                loop {
-                    if finished_clone.load(Ordering::Relaxed) {
-                        break;
-                    }
+        let started = Instant::now();
+        assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(tx);
+    }
 
-                    while producer.free_len() > 4096 {
-                        if current_index >= current_samples.len() {
-                            let mut format = format_clone.lock().unwrap();
-                            let mut decoder = decoder_clone.lock().unwrap();
-
-                            let packet = match format.next_packet() {
-                                Ok(packet) => packet,
-                                Err(Error::IoError(e))
-                                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
-                                {
-                                    finished_clone.store(true, Ordering::Relaxed);
-                                    break;
-                                }
-                                Err(e) => {
-                                    eprintln!("[Decoder] Error reading packet: {:?}", e);
-                                    finished_clone.store(true, Ordering::Relaxed);
-                                    break;
-                                }
-                            };
-
-                            if packet.track_id() != track_id {
-                                continue;
-                            }
+    #[test]
+    fn gives_up_on_stalled_download() {
+        let (tx, mut reader) = reader();
+        let e = reader
+            .wait_for_data(1, Duration::from_millis(200))
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::TimedOut);
+        drop(tx);
+    }
 
-                            match decoder.decode(&packet) {
-                                Ok(decoded) => {
-                                    let raw_samples = convert_samples(decoded);
-
-                                    // サンプルレート変換
-                                    current_samples = if sample_rate != output_sample_rate {
-                                        resample_linear(
-                                            &raw_samples,
-                                            sample_rate,
-                                            output_sample_rate,
-                                            channels_count as usize,
-                                        )
-                                    } else {
-                                        raw_samples
-                                    };
-
-                                    current_index = 0;
-                                }
-                                Err(_) => continue,
-                            }
-                        }
+    #[test]
+    fn holds_back_download_ahead_of_playback() {
+        let (tx, rx) = async_channel::bounded(BUFFERED_CHUNKS);
+        let mut reader = StreamReader::new(rx, Arc::new(Mutex::new(StreamState::Streaming)));
+        let sender = std::thread::spawn(move || {
+            smol::block_on(send_chunked(&tx, Bytes::from(vec![0u8; 4 << 20])))
+        });
 
-                        if current_index < current_samples.len() {
-                            let sample = current_samples[current_index];
-                            if producer.push(sample).is_err() {
-                                break;
-                            }
-                            current_index += 1;
-                        }
-                    }
+        std::thread::sleep(Duration::from_millis(200));
+        reader.read_exact(&mut [0; 4096]).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!sender.is_finished());
+        assert!(
+            reader.buffer.len() + reader.rx.len() * CHUNK_LEN <= (BUFFERED_CHUNKS + 1) * CHUNK_LEN
+        );
 
-                    std::thread::sleep(Duration::from_millis(5));
-                }
-            });
+        drop(reader);
+        assert!(sender.join().unwrap().is_err());
+    }
 
-            let paused_stream = Arc::clone(&paused);
-            let volume_stream = Arc::clone(&volume_arc);
+    #[test]
+    fn dropping_reader_ends_download() {
+        let (tx, reader) = reader();
+        drop(reader);
+        assert!(tx.send_blocking(Bytes::from_static(b"abc")).is_err());
+    }
 
-            let stream = device.build_output_stream(
-                &config,
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                    if paused_stream.load(Ordering::Relaxed) {
-                        for sample in data.iter_mut() {
-                            *sample = 0.0;
-                        }
-                        return;
-                    }
+    #[test]
+    fn skips_stalled_stream() {
+        let server = TestServer::start(|_| TestResponse {
+            headers: vec![("Content-Type".into(), "audio/mpeg".into())],
+            stall: true,
+            ..TestResponse::ok(silent_mp3(200))
+        });
+        let target =
+            smol::block_on(open_url(&server.url("/stall.mp3"), &StreamOptions::default()).compat());
+        let Ok(UrlTarget::Stream(player)) = target else {
+            panic!("stream did not open");
+        };
+        let progress = player.progress();
+        let engine = PlaybackEngine::new(
+            &EngineOptions {
+                output: SinkSpec::Null { realtime: false },
+                ..Default::default()
+            },
+            (44100, 2),
+        )
+        .unwrap();
+        let id = engine.enqueue(player.into_player());
 
-                    let vol = *volume_stream.lock();
+        let wait = |done: &dyn Fn() -> bool| {
+            let started = Instant::now();
+            while !done() {
+                assert!(started.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        wait(&|| progress.get_state() == StreamState::Buffering);
+        engine.skip();
+        wait(&|| engine.is_finished(id));
+    }
 
-                    for sample in data.iter_mut() {
-                        *sample = consumer.pop().unwrap_or(0.0) * vol;
-                    }
-                },
-                move |err| {
-                    err!("Stream error: {}", err);
-                },
-                None,
-            )?;
-
-            stream.play()?;
-
-            Ok(UrlPlayer {
-                _stream: stream,
-                paused,
-                volume: volume_arc,
-                finished,
-                sample_rate,
-                channels: channels_count as u32,
-                downloaded_bytes: downloaded_bytes_clone,
-                total_bytes: Arc::new(Mutex::new(total_bytes)),
-            })
-        },
-    )
-    .join()
-    .unwrap()
-    .unwrap();
+    #[test]
+    fn plays_audio_served_as_text() {
+        let server = TestServer::start(|req| {
+            let body = match req.path.as_str() {
+                "/list" => b"#EXTM3U\nsong.mp3\n".to_vec(),
+                _ => silent_mp3(50),
+            };
+            TestResponse {
+                headers: vec![("Content-Type".into(), "text/plain".into())],
+                ..TestResponse::ok(body)
+            }
+        });
 
-    Ok(player)
-}
+        let open =
+            |path| smol::block_on(open_url(&server.url(path), &StreamOptions::default()).compat());
+        let Ok(UrlTarget::Stream(player)) = open("/listen") else {
+            panic!("audio was not opened as a stream");
+        };
+        assert_eq!(player.player.sample_rate, 44100);
+        let Ok(UrlTarget::Playlist(entries)) = open("/list") else {
+            panic!("playlist was not recognised");
+        };
+        assert_eq!(entries[0].location, server.url("/song.mp3"));
+    }
 
-pub async fn play_url(url: &str, volume: f32, title_override: Option<String>) {
-    let p = match setup_url_player(url, volume).await {
-        Ok(player) => player,
-        Err(e) => {
-            err!("Failed to setup url player: {}", e);
-            return;
+    #[test]
+    fn reconnects_icy_stream_from_start() {
+        const METAINT: usize = 100;
+        let audio: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        let mut raw = Vec::new();
+        for block in audio.chunks(METAINT) {
+            raw.extend_from_slice(block);
+            raw.push(1);
+            raw.extend_from_slice(b"StreamTitle='x';");
         }
-    };
 
-    let title = title_override.unwrap_or_else(|| url.to_string());
-    println!(
-        "{}kHz/{}ch | Unknown",
-        p.sample_rate() as f32 / 1000.0,
-        p.channels()
-    );
-    let player = Arc::new(Mutex::new(p));
-    let key_state = Arc::new(Mutex::new(false));
-
-    println!("{}", title);
-    let thread = smol::spawn(input::get_input_url_mode(
-        Arc::clone(&player),
-        title.clone(),
-        key_state.clone(),
-    ));
-
-    set_terminal_title(&title);
-
-    let mut first = false;
-
-    loop {
-        smol::Timer::after(Duration::from_millis(200)).await;
-
-        let locked = Arc::clone(&player);
-        let locked = locked.lock();
-
-        if !first {
-            execute!(
-                stdout(),
-                cursor::MoveToColumn(0),
-                Clear(ClearType::CurrentLine)
-            )
-            .unwrap();
-        } else {
-            first = !first;
-        }
+        let dropped = AtomicBool::new(false);
+        let plain = audio.clone();
+        let server = TestServer::start(move |req| {
+            let from = req
+                .header("range")
+                .and_then(|r| r.strip_prefix("bytes="))
+                .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok());
+            let mut response = match from {
+                // Range 付きの応答にはメタデータが入らない
+                Some(from) => TestResponse {
+                    status: 206,
+                    ..TestResponse::ok(plain[from.min(plain.len())..].to_vec())
+                },
+                None => {
+                    let mut response = TestResponse {
+                        drop_after: (!dropped.swap(true, Ordering::Relaxed)).then_some(1000),
+                        ..TestResponse::ok(raw.clone())
+                    };
+                    response
+                        .headers
+                        .push(("icy-metaint".into(), METAINT.to_string()));
+                    response
+                }
+            };
+            response
+                .headers
+                .push(("Accept-Ranges".into(), "bytes".into()));
+            response
+        });
 
-        if let Some(progress) = locked.get_download_progress() {
-            print!(
-                "{:.1}% ({:.2} / {:.2} MB)",
-                progress,
-                locked.get_downloaded_mb(),
-                locked.get_total_mb().unwrap(),
-            );
-        } else {
-            print!("({:.2} MB)", locked.get_downloaded_mb());
+        let client = HttpClient::new(HttpOptions::default()).unwrap();
+        let fetched =
+            smol::block_on(http::get(&client, &server.url("/radio"), None).compat()).unwrap();
+        let icy = IcyMetadata::from_response(&fetched);
+        let retry = RetryPolicy {
+            initial_delay: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        };
+        let (tx, rx) = async_channel::bounded(BUFFERED_CHUNKS);
+        let download = Download::new(client, &fetched, retry, icy);
+        std::thread::spawn(move || smol::block_on(download.run(fetched, tx).compat()));
+
+        let mut received = Vec::new();
+        while received.len() < audio.len() {
+            let chunk = smol::block_on(smol::future::or(async { rx.recv().await.ok() }, async {
+                smol::Timer::after(Duration::from_secs(5)).await;
+                None
+            }))
+            .expect("stream ended early");
+            received.extend_from_slice(&chunk);
         }
-        io::stdout().flush().unwrap();
+        assert_eq!(received, audio);
+        let ranges = server
+            .requests()
+            .iter()
+            .filter(|r| r.header("range").is_some())
+            .count();
+        assert_eq!(ranges, 0);
+    }
 
-        if thread.is_finished() {
-            cleanup_and_exit(&title);
-            break;
+    #[test]
+    fn seeks_stalled_range_stream() {
+        let server = TestServer::start(|_| TestResponse {
+            headers: vec![
+                ("Content-Type".into(), "audio/mpeg".into()),
+                ("Accept-Ranges".into(), "bytes".into()),
+            ],
+            stall: true,
+            ..TestResponse::ok(silent_mp3(200))
+        });
+        let target =
+            smol::block_on(open_url(&server.url("/stall.mp3"), &StreamOptions::default()).compat());
+        let Ok(UrlTarget::Stream(player)) = target else {
+            panic!("stream did not open");
+        };
+        assert!(player.is_seekable());
+        let engine = PlaybackEngine::new(
+            &EngineOptions {
+                output: SinkSpec::Null { realtime: false },
+                ..Default::default()
+            },
+            (44100, 2),
+        )
+        .unwrap();
+        engine.enqueue(player.into_player());
+
+        // 届いた分を鳴らし終えて、デコーダーが続きを待っている間に頭へ戻る
+        let started = Instant::now();
+        let mut last = Duration::MAX;
+        while engine.get_pos() != last {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            last = engine.get_pos();
+            std::thread::sleep(Duration::from_millis(200));
         }
-        if locked.is_empty() {
-            *key_state.lock() = true;
-            cleanup_and_exit(&title);
-            break;
+        engine.seek(Duration::ZERO).unwrap();
+        let started = Instant::now();
+        while engine.get_pos() < Duration::from_secs(1) {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
         }
+        engine.skip();
     }
-}
-
-fn set_terminal_title(title: &str) {
-    execute!(stdout(), SetTitle(title.to_string())).unwrap();
-}
 
-fn reset_terminal_title() {
-    let cwd = env::current_dir().unwrap().display().to_string();
-    execute!(stdout(), SetTitle(cwd)).unwrap();
-    print!("\x1b]2;\x07");
-    stdout().flush().unwrap();
-}
+    #[test]
+    fn resolves_hls_segments_against_redirected_url() {
+        let server = TestServer::start(|req| match req.path.as_str() {
+            "/live" => TestResponse::redirect("/hls/index.m3u8"),
+            "/hls/index.m3u8" => TestResponse::ok(
+                "#EXTM3U\n#EXT-X-TARGETDURATION:4\n\
+                 #EXTINF:4,\nseg0.mp3\n#EXTINF:4,\nseg1.mp3\n#EXT-X-ENDLIST\n",
+            ),
+            "/hls/seg0.mp3" | "/hls/seg1.mp3" => TestResponse::ok(silent_mp3(100)),
+            _ => TestResponse::not_found(),
+        });
 
-fn cleanup_and_exit(title: &str) {
-    let text_width = UnicodeWidthStr::width(title);
-    let (cols, _rows) = terminal::size().unwrap_or((80, 24));
-    let lines_needed = (text_width as u16).div_ceil(cols).max(1) - 1;
-
-    execute!(
-        std::io::stdout(),
-        MoveToPreviousLine(2),
-        Clear(crossterm::terminal::ClearType::FromCursorDown),
-    )
-    .unwrap();
-
-    for _ in 0..lines_needed {
-        execute!(
-            std::io::stdout(),
-            MoveToPreviousLine(1),
-            Clear(ClearType::FromCursorDown),
-        )
-        .unwrap();
+        let target =
+            smol::block_on(open_url(&server.url("/live"), &StreamOptions::default()).compat());
+        let Ok(UrlTarget::Stream(player)) = target else {
+            panic!("HLS stream did not open");
+        };
+        assert_eq!(player.sample_rate(), 44100);
+        let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+        assert!(paths.contains(&"/hls/seg0.mp3".to_string()), "{:?}", paths);
+        assert!(!paths.contains(&"/seg0.mp3".to_string()), "{:?}", paths);
     }
-
-    reset_terminal_title();
-    deinit();
 }
@@ -1,16 +1,11 @@
-use std::process::exit;
-
-use crate::err;
+use crate::error::Result;
 use crate::player::metadata::MetaData;
 use crate::player::player_structs::Player;
 use lofty::probe::Probe;
 
 impl Player {
-    pub fn metadata(&self) -> MetaData {
-        let probe = Probe::open(&self.path).unwrap_or_else(|e| {
-            err!("Failed to probe metadata for {}: {}", self.path, e);
-            exit(1);
-        });
+    pub fn metadata(&self) -> Result<MetaData> {
+        let probe = Probe::open(&self.path)?;
 
         MetaData::new(probe)
     }
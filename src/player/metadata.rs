@@ -4,41 +4,44 @@ use lofty::properties::FileProperties;
 use lofty::tag::{Accessor, Tag};
 use std::fs::File;
 use std::io::BufReader;
-use std::process::exit;
 use std::time::Duration;
 
-use crate::err;
+use crate::error::Result;
+use crate::replaygain::ReplayGain;
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct MetaData {
     pub tag: Option<Tag>,
     pub prop: FileProperties,
     pub title: Option<String>,
+    pub artist: Option<String>,
+    pub replay_gain: ReplayGain,
 }
 
 impl MetaData {
-    pub fn new(probe: Probe<BufReader<File>>) -> Self {
-        let bind = match probe.read() {
-            Ok(bind) => bind,
-            Err(e) => {
-                err!("Failed to read metadata: {}", e);
-                exit(1);
-            }
-        };
+    pub fn new(probe: Probe<BufReader<File>>) -> Result<Self> {
+        let bind = probe.read()?;
+
+        // 主タグを優先し、APE など他のタグも ReplayGain の読み取りに使う
+        let replay_gain = ReplayGain::from_tags(bind.primary_tag().into_iter().chain(bind.tags()));
 
         let Some(s) = bind.primary_tag() else {
-            return Self {
+            return Ok(Self {
                 tag: None,
                 prop: bind.properties().clone(),
                 title: None,
-            };
+                artist: None,
+                replay_gain,
+            });
         };
 
-        Self {
+        Ok(Self {
             tag: Some(s.clone()),
             prop: bind.properties().clone(),
             title: None,
-        }
+            artist: None,
+            replay_gain,
+        })
     }
 
     pub fn set_title(&mut self, title: Option<String>) {
@@ -55,8 +58,14 @@ impl MetaData {
         }
     }
 
+    pub fn set_artist(&mut self, artist: Option<String>) {
+        self.artist = artist
+    }
+
     pub fn artist(&self) -> Option<String> {
-        if let Some(tag) = &self.tag.clone() {
+        if self.artist.is_some() {
+            self.artist.clone()
+        } else if let Some(tag) = &self.tag.clone() {
             tag.artist().as_ref().map(|artist| artist.to_string())
         } else {
             None
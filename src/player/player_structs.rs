@@ -1,93 +1,174 @@
 use std::path::Path;
-use std::sync::{Arc, Mutex};
-use std::{fs::File, process::exit};
-use symphonia::core::codecs::{CODEC_TYPE_NULL, Decoder, DecoderOptions};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use symphonia::core::audio::Channels;
+use symphonia::core::codecs::{CODEC_TYPE_AAC, CODEC_TYPE_NULL, Decoder, DecoderOptions};
 use symphonia::core::formats::{FormatOptions, FormatReader};
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
+use symphonia::core::io::MediaSource;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag, Value};
+use symphonia::core::units::{Time, TimeBase};
 
-use crate::err;
+use crate::dsp::channels;
+use crate::error::{MinauError, Result};
+use crate::replaygain::ReplayGain;
+use crate::source::Source;
+
+/// Encoder delay and padding that a container reports but the codec does not
+/// trim by itself.
+#[derive(Clone, Copy, Debug)]
+pub struct GaplessInfo {
+    /// Priming frames to drop at the start
+    pub delay: u64,
+    /// Number of frames of real audio following the delay
+    pub frames: Option<u64>,
+}
+
+/// Where a track sits on its album, used to tell album continuations apart
+/// from unrelated tracks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AlbumInfo {
+    pub title: Option<String>,
+    pub track: Option<u32>,
+    /// Marked as part of a gapless album: iTunes `pgap`, gapless info from
+    /// `iTunSMPB` unless `pgap` says otherwise, or a clip of a CUE sheet
+    pub gapless: bool,
+}
+
+/// Part of a file played as a track of its own, such as a track of a CUE
+/// sheet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clip {
+    pub start: Duration,
+    /// End of the part, or the end of the file if `None`
+    pub end: Option<Duration>,
+}
 
 pub struct Player {
     pub format: Arc<Mutex<Box<dyn FormatReader>>>,
     pub decoder: Arc<Mutex<Box<dyn Decoder>>>,
     pub track_id: u32,
     pub sample_rate: u32,
+    /// Unit of the track's timestamps
+    pub time_base: Option<TimeBase>,
+    /// Length of the track in timestamp units, if the container reports it
+    pub n_frames: Option<u64>,
     pub channels: u16,
+    /// Channel positions in interleaved order
+    pub layout: Channels,
+    pub seekable: bool,
+    pub gapless: Option<GaplessInfo>,
+    pub album: AlbumInfo,
+    /// Part of the file to play, set with [`Player::set_clip`]
+    pub clip: Option<Clip>,
+    /// CUE sheet embedded in the tags, such as a FLAC `CUESHEET` comment
+    pub cuesheet: Option<String>,
+    /// Gain tags, usually filled in from [`MetaData`](crate::MetaData)
+    pub replay_gain: ReplayGain,
+    /// Gain measured in the background for a file without gain tags. It is
+    /// used instead of `replay_gain` if it is ready when the track starts.
+    pub measured_gain: Option<Arc<OnceLock<ReplayGain>>>,
+    /// Set by the engine when the track is skipped or the engine stops, so a
+    /// stream source waiting for data gives up
+    pub interrupt: Option<Arc<AtomicBool>>,
     pub path: String,
 }
 
-impl Clone for Player {
-    fn clone(&self) -> Self {
-        Player::new(&self.path)
-    }
-}
-
 impl Player {
-    pub fn new<P: AsRef<Path>>(path: P) -> Self {
-        let path_str = path.as_ref().to_str().unwrap().to_string();
-
-        let file = File::open(&path).unwrap_or_else(|e| {
-            err!("Failed to open {}: {}", path_str, e);
-            exit(1);
-        });
-
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_source(Source::File(path.as_ref().to_path_buf()))
+    }
 
-        let mut hint = Hint::new();
-        if let Some(ext) = path.as_ref().extension() {
-            hint.with_extension(ext.to_str().unwrap());
-        }
+    pub fn from_source(source: Source) -> Result<Self> {
+        let path_str = source.name();
+        let (mss, hint) = source.into_stream()?;
+        let seekable = mss.is_seekable();
 
         let meta_opts: MetadataOptions = Default::default();
-        let fmt_opts: FormatOptions = Default::default();
+        // MP3 (LAME) と Ogg の遅延・パディングは symphonia が取り除く
+        let fmt_opts = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
 
-        let probed = symphonia::default::get_probe()
+        let mut probed = symphonia::default::get_probe()
             .format(&hint, mss, &fmt_opts, &meta_opts)
-            .unwrap_or_else(|e| {
-                err!("Failed to probe format: {}", e);
-                exit(1);
-            });
+            .map_err(MinauError::Probe)?;
+
+        let mut format = probed.format;
 
-        let format = probed.format;
+        let tags: Vec<Tag> = format
+            .metadata()
+            .current()
+            .map(|rev| rev.tags().to_vec())
+            .or_else(|| {
+                probed
+                    .metadata
+                    .get()
+                    .and_then(|log| log.current().map(|rev| rev.tags().to_vec()))
+            })
+            .unwrap_or_default();
+        let itunsmpb = find_itunsmpb(&tags);
+        let album = AlbumInfo::from_tags(&tags);
+        let cuesheet = tags
+            .iter()
+            .find(|tag| tag.key.eq_ignore_ascii_case("cuesheet"))
+            .map(|tag| tag.value.to_string())
+            .filter(|cuesheet| !cuesheet.trim().is_empty());
 
         let track = format
             .tracks()
             .iter()
             .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .unwrap_or_else(|| {
-                err!("No supported audio track found");
-                exit(1);
-            });
+            .ok_or(MinauError::NoTrack)?;
 
         let track_id = track.id;
         let codec_params = &track.codec_params;
 
         // codec_paramsから情報を先に取得
-        let sample_rate = codec_params.sample_rate.unwrap_or_else(|| {
-            err!("No sample rate information found");
-            exit(1);
-        });
+        let sample_rate = codec_params
+            .sample_rate
+            .ok_or_else(|| MinauError::Decode("No sample rate information found".into()))?;
 
-        let channels = codec_params.channels.map(|c| c.count() as u16).unwrap_or(2);
+        let layout = codec_params
+            .channels
+            .filter(|c| c.count() > 0)
+            .unwrap_or_else(|| channels::default_layout(2));
+        let channels = layout.count() as u16;
+        let time_base = codec_params.time_base;
+        let n_frames = codec_params.n_frames;
+
+        // AAC は iTunSMPB の情報を使って自前でトリミングする
+        let gapless = if codec_params.codec == CODEC_TYPE_AAC {
+            itunsmpb.as_deref().and_then(GaplessInfo::from_itunsmpb)
+        } else {
+            None
+        };
 
         let dec_opts: DecoderOptions = Default::default();
         let decoder = symphonia::default::get_codecs()
             .make(codec_params, &dec_opts)
-            .unwrap_or_else(|e| {
-                err!("Failed to create decoder: {}", e);
-                exit(1);
-            });
+            .map_err(|e| MinauError::Decode(e.to_string()))?;
 
-        Self {
+        Ok(Self {
             format: Arc::new(Mutex::new(format)),
             decoder: Arc::new(Mutex::new(decoder)),
             track_id,
             sample_rate,
+            time_base,
+            n_frames,
             channels,
+            layout,
+            seekable,
+            gapless,
+            album,
+            clip: None,
+            cuesheet,
+            replay_gain: ReplayGain::default(),
+            measured_gain: None,
+            interrupt: None,
             path: path_str,
-        }
+        })
     }
 
     pub fn sample_rate(&self) -> u32 {
@@ -97,4 +178,137 @@ impl Player {
     pub fn channels(&self) -> u16 {
         self.channels
     }
+
+    /// Length of the track as reported by the container, or of the clip.
+    pub fn duration(&self) -> Option<Duration> {
+        let length = self.n_frames.map(|n_frames| {
+            let time = match self.time_base {
+                Some(time_base) => time_base.calc_time(n_frames),
+                None => Time::from(n_frames as f64 / self.sample_rate as f64),
+            };
+            Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac)
+        });
+        match self.clip {
+            Some(clip) => Some(clip.end.or(length)?.saturating_sub(clip.start)),
+            None => length,
+        }
+    }
+
+    /// Plays only `start..end` of the file, as one track of a CUE sheet.
+    /// Positions and seeks are then relative to `start`.
+    ///
+    /// Clips of the same file count as a gapless album, so the engine
+    /// plays them back-to-back without a crossfade.
+    pub fn set_clip(&mut self, start: Duration, end: Option<Duration>) {
+        self.clip = Some(Clip { start, end });
+        self.album.gapless = true;
+        if self.album.title.is_none() {
+            self.album.title = Some(self.path.clone());
+        }
+    }
+}
+
+impl GaplessInfo {
+    /// Parses an iTunes `iTunSMPB` value: space separated hex fields holding
+    /// the encoder delay, the padding and the original sample count.
+    pub fn from_itunsmpb(value: &str) -> Option<Self> {
+        let fields: Vec<u64> = value
+            .split_whitespace()
+            .map(|field| u64::from_str_radix(field, 16))
+            .collect::<std::result::Result<_, _>>()
+            .ok()?;
+
+        let delay = *fields.get(1)?;
+        let frames = fields.get(3).copied().filter(|&frames| frames > 0);
+        Some(Self { delay, frames })
+    }
+}
+
+impl AlbumInfo {
+    fn from_tags(tags: &[Tag]) -> Self {
+        let mut info = AlbumInfo::default();
+        let mut pgap = None;
+        let mut itunsmpb = false;
+        for tag in tags {
+            match tag.std_key {
+                Some(StandardTagKey::Album) => info.title = Some(tag.value.to_string()),
+                Some(StandardTagKey::TrackNumber) => {
+                    // "3/12" のような表記にも対応する
+                    info.track = tag
+                        .value
+                        .to_string()
+                        .split('/')
+                        .next()
+                        .and_then(|n| n.trim().parse().ok());
+                }
+                _ => {
+                    let key = tag.key.to_ascii_lowercase();
+                    if key.ends_with("pgap") || key.ends_with("itunpgap") {
+                        pgap = Some(match &tag.value {
+                            Value::Boolean(flag) => *flag,
+                            Value::Flag => true,
+                            value => value.to_string().trim() == "1",
+                        });
+                    } else if key.ends_with("itunsmpb") {
+                        itunsmpb |= GaplessInfo::from_itunsmpb(&tag.value.to_string()).is_some();
+                    }
+                }
+            }
+        }
+        // iTunes は普通の曲にも iTunSMPB を書くので、pgap があればそちらに従う
+        info.gapless = pgap.unwrap_or(itunsmpb);
+        info
+    }
+
+    /// Returns true if this track and `previous` are both marked gapless on
+    /// the same album, so the two should play back-to-back untouched.
+    pub fn continues(&self, previous: &AlbumInfo) -> bool {
+        self.title.is_some() && self.title == previous.title && self.gapless && previous.gapless
+    }
+}
+
+fn find_itunsmpb(tags: &[Tag]) -> Option<String> {
+    tags.iter()
+        .find(|tag| tag.key.ends_with("iTunSMPB"))
+        .map(|tag| tag.value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn album(title: &str, track: u32, gapless: bool) -> AlbumInfo {
+        AlbumInfo {
+            title: Some(title.into()),
+            track: Some(track),
+            gapless,
+        }
+    }
+
+    fn tag(key: &str, value: &str) -> Tag {
+        Tag::new(None, key, Value::from(value))
+    }
+
+    #[test]
+    fn crossfades_consecutive_tracks_without_gapless_flag() {
+        assert!(!album("A", 2, false).continues(&album("A", 1, false)));
+        assert!(!album("A", 2, true).continues(&album("A", 1, false)));
+    }
+
+    #[test]
+    fn continues_gapless_album() {
+        assert!(album("A", 2, true).continues(&album("A", 1, true)));
+        // 番号が飛んでいても gapless の印があれば続ける
+        assert!(album("A", 5, true).continues(&album("A", 1, true)));
+        assert!(!album("B", 2, true).continues(&album("A", 1, true)));
+    }
+
+    #[test]
+    fn reads_gapless_flag_from_tags() {
+        let smpb = " 00000000 00000840 000001C0 0000000000A0F000";
+        assert!(AlbumInfo::from_tags(&[tag("iTunSMPB", smpb)]).gapless);
+        assert!(AlbumInfo::from_tags(&[tag("ITUNPGAP", "1")]).gapless);
+        assert!(!AlbumInfo::from_tags(&[tag("iTunSMPB", smpb), tag("ITUNPGAP", "0")]).gapless);
+        assert!(!AlbumInfo::from_tags(&[tag("TRACKNUMBER", "2")]).gapless);
+    }
 }
@@ -0,0 +1,108 @@
+//! ASX (Advanced Stream Redirector) playlists of Windows Media.
+//!
+//! ```xml
+//! <asx version="3.0">
+//!   <entry>
+//!     <title>Example Radio</title>
+//!     <author>Example</author>
+//!     <ref href="http://example.com/stream" />
+//!   </entry>
+//! </asx>
+//! ```
+use std::time::Duration;
+
+use super::{PlaylistEntry, xml};
+
+/// Elements that are written without an end tag as often as with one
+const VOID: [&str; 7] = [
+    "ref", "entryref", "duration", "param", "banner", "logo", "moreinfo",
+];
+
+pub fn parse(text: &str) -> Vec<PlaylistEntry> {
+    let root = xml::parse(text, &VOID);
+    let Some(asx) = root.find("asx") else {
+        return Vec::new();
+    };
+
+    let mut entries = Vec::new();
+    for child in &asx.children {
+        match child.name.as_str() {
+            "entry" => {
+                // 2 つ目以降の ref は予備の URL
+                let Some(href) = child
+                    .children("ref")
+                    .find_map(|r| r.attr("href").filter(|h| !h.trim().is_empty()))
+                else {
+                    continue;
+                };
+                entries.push(PlaylistEntry {
+                    title: child.child_text("title"),
+                    artist: child.child_text("author"),
+                    duration: child
+                        .child("duration")
+                        .and_then(|d| d.attr("value"))
+                        .and_then(parse_clock),
+                    ..PlaylistEntry::new(href.trim())
+                });
+            }
+            // 別の ASX を指すエントリー
+            "entryref" => {
+                if let Some(href) = child.attr("href").filter(|h| !h.trim().is_empty()) {
+                    entries.push(PlaylistEntry::new(href.trim()));
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// Parses `[[hh:]mm:]ss[.fract]`.
+fn parse_clock(value: &str) -> Option<Duration> {
+    let mut secs = 0.0;
+    for part in value.trim().split(':') {
+        let part: f64 = part.trim().parse().ok()?;
+        secs = secs * 60.0 + part;
+    }
+    (secs.is_finite() && secs >= 0.0).then(|| Duration::from_secs_f64(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_mixed_case_entries() {
+        let entries = parse(
+            "<ASX Version=\"3.0\">\n\
+               <Title>Station</Title>\n\
+               <Entry>\n\
+                 <Title>Morning Show</Title>\n\
+                 <Author>Host</Author>\n\
+                 <Duration VALUE=\"01:02:03.5\">\n\
+                 <REF HREF=\"mms://example.com/live\">\n\
+                 <Ref href=\"http://example.com/backup\"/>\n\
+               </Entry>\n\
+               <entry><ref href=\"  \" /></entry>\n\
+               <ENTRYREF href=\"http://example.com/more.asx\" />\n\
+             </ASX>\n",
+        );
+        let locations: Vec<_> = entries.iter().map(|e| e.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            ["mms://example.com/live", "http://example.com/more.asx"]
+        );
+        assert_eq!(entries[0].title.as_deref(), Some("Morning Show"));
+        assert_eq!(entries[0].artist.as_deref(), Some("Host"));
+        assert_eq!(entries[0].duration, Some(Duration::from_secs_f64(3723.5)));
+        assert_eq!(entries[1].title, None);
+    }
+
+    #[test]
+    fn parses_clock_values() {
+        assert_eq!(parse_clock("45"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_clock("02:30"), Some(Duration::from_secs(150)));
+        assert_eq!(parse_clock("-1"), None);
+        assert_eq!(parse_clock("soon"), None);
+    }
+}
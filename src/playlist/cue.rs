@@ -0,0 +1,247 @@
+//! CUE sheets, which split one audio file, usually a whole album, into
+//! tracks.
+//!
+//! ```text
+//! REM GENRE Rock
+//! PERFORMER "The Band"
+//! TITLE "Live at the Park"
+//! FILE "album.flac" WAVE
+//!   TRACK 01 AUDIO
+//!     TITLE "Opening"
+//!     INDEX 01 00:00:00
+//!   TRACK 02 AUDIO
+//!     TITLE "Encore"
+//!     PERFORMER "Guest"
+//!     INDEX 00 04:10:20
+//!     INDEX 01 04:12:00
+//! ```
+//!
+//! A track starts at its `INDEX 01` and ends where the next track of the same
+//! file starts, so the pregap of a track is heard at the end of the one
+//! before it and no part of the file is left out.
+use std::time::Duration;
+
+use super::PlaylistEntry;
+
+/// `mm:ss:ff` times count frames of a CD, 75 per second
+const FRAMES_PER_SEC: u64 = 75;
+
+#[derive(Default)]
+struct CueTrack {
+    file: String,
+    title: Option<String>,
+    performer: Option<String>,
+    start: Option<Duration>,
+}
+
+pub fn parse(cue: &str) -> Vec<PlaylistEntry> {
+    let mut album = None;
+    let mut album_performer = None;
+    let mut genre = None;
+    let mut file: Option<String> = None;
+    let mut tracks: Vec<CueTrack> = Vec::new();
+    // データトラックの TITLE や INDEX はアルバムにも前の曲にも付けない
+    let mut in_track = false;
+    let mut in_audio_track = false;
+
+    for line in cue.lines().map(str::trim) {
+        let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = args.trim();
+        let track = tracks.last_mut().filter(|_| in_audio_track);
+
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => {
+                file = Some(file_name(args));
+                in_track = false;
+                in_audio_track = false;
+            }
+            "TRACK" => {
+                in_track = true;
+                in_audio_track = false;
+                let audio = args
+                    .split_whitespace()
+                    .nth(1)
+                    .is_some_and(|kind| kind.eq_ignore_ascii_case("AUDIO"));
+                if let Some(file) = file.as_ref().filter(|_| audio) {
+                    in_audio_track = true;
+                    tracks.push(CueTrack {
+                        file: file.clone(),
+                        ..Default::default()
+                    });
+                }
+            }
+            "TITLE" => match track {
+                Some(track) => track.title = unquote(args),
+                None if !in_track => album = unquote(args),
+                None => {}
+            },
+            "PERFORMER" => match track {
+                Some(track) => track.performer = unquote(args),
+                None if !in_track => album_performer = unquote(args),
+                None => {}
+            },
+            "REM" => {
+                if let Some((key, value)) = args.split_once(char::is_whitespace)
+                    && key.eq_ignore_ascii_case("GENRE")
+                    && !in_track
+                {
+                    genre = unquote(value.trim());
+                }
+            }
+            "INDEX" => {
+                let mut parts = args.split_whitespace();
+                if let Some(track) = track
+                    && parts.next().and_then(|n| n.parse::<u32>().ok()) == Some(1)
+                {
+                    track.start = parts.next().and_then(parse_time);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // INDEX 01 のない曲は位置が分からないので飛ばす
+    tracks.retain(|track| track.start.is_some());
+    let mut entries = Vec::new();
+    for (i, track) in tracks.iter().enumerate() {
+        let start = track.start.unwrap_or_default();
+        let end = tracks
+            .get(i + 1)
+            .filter(|next| next.file == track.file)
+            .and_then(|next| next.start)
+            .filter(|&end| end > start);
+        entries.push(PlaylistEntry {
+            title: track.title.clone(),
+            artist: track.performer.clone().or_else(|| album_performer.clone()),
+            album: album.clone(),
+            genre: genre.clone(),
+            duration: end.map(|end| end - start),
+            start: Some(start),
+            end,
+            ..PlaylistEntry::new(&track.file)
+        });
+    }
+    entries
+}
+
+/// The file name of a `FILE "name" TYPE` line. Unquoted names may contain
+/// spaces, so only the last word is taken for the type.
+fn file_name(args: &str) -> String {
+    if let Some(quoted) = args.strip_prefix('"') {
+        return quoted.split('"').next().unwrap_or_default().to_string();
+    }
+    match args.rsplit_once(char::is_whitespace) {
+        Some((name, _)) => name.trim().to_string(),
+        None => args.to_string(),
+    }
+}
+
+fn unquote(value: &str) -> Option<String> {
+    let value = value
+        .strip_prefix('"')
+        .map_or(value, |v| v.strip_suffix('"').unwrap_or(v))
+        .trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Parses `mm:ss:ff`.
+fn parse_time(time: &str) -> Option<Duration> {
+    let mut parts = time.split(':').map(|part| part.trim().parse::<u64>().ok());
+    let (min, sec, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || sec >= 60 || frames >= FRAMES_PER_SEC {
+        return None;
+    }
+    let frames = (min * 60 + sec) * FRAMES_PER_SEC + frames;
+    Some(Duration::from_nanos(
+        frames * 1_000_000_000 / FRAMES_PER_SEC,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_cd_frames() {
+        assert_eq!(parse_time("00:00:00"), Some(Duration::ZERO));
+        assert_eq!(parse_time("04:12:00"), Some(Duration::from_secs(252)));
+        // 75 フレームで 1 秒
+        assert_eq!(parse_time("01:02:15"), Some(Duration::from_millis(62_200)));
+        assert_eq!(
+            parse_time("00:00:01"),
+            Some(Duration::from_nanos(13_333_333))
+        );
+        // 分は 60 を超えてもよい
+        assert_eq!(
+            parse_time("75:00:74").map(|d| d.as_millis()),
+            Some(4_500_986)
+        );
+        assert_eq!(parse_time("00:60:00"), None);
+        assert_eq!(parse_time("00:00:75"), None);
+        assert_eq!(parse_time("00:00"), None);
+        assert_eq!(parse_time("00:00:00:00"), None);
+    }
+
+    #[test]
+    fn ends_tracks_at_next_track_of_same_file() {
+        let entries = parse(
+            "PERFORMER \"The Band\"\n\
+             TITLE \"Live\"\n\
+             FILE \"disc one.flac\" WAVE\n\
+               TRACK 01 AUDIO\n\
+                 TITLE \"Opening\"\n\
+                 INDEX 01 00:00:00\n\
+               TRACK 02 AUDIO\n\
+                 TITLE \"Encore\"\n\
+                 PERFORMER \"Guest\"\n\
+                 INDEX 00 04:10:20\n\
+                 INDEX 01 04:12:00\n\
+             FILE \"disc two.flac\" WAVE\n\
+               TRACK 03 AUDIO\n\
+                 INDEX 01 00:00:00\n",
+        );
+        let files: Vec<_> = entries.iter().map(|e| e.location.as_str()).collect();
+        assert_eq!(files, ["disc one.flac", "disc one.flac", "disc two.flac"]);
+
+        // 前の曲は次の曲の INDEX 01 で終わり、プリギャップを含む
+        assert_eq!(entries[0].start, Some(Duration::ZERO));
+        assert_eq!(entries[0].end, Some(Duration::from_secs(252)));
+        assert_eq!(entries[0].duration, Some(Duration::from_secs(252)));
+        assert_eq!(entries[0].artist.as_deref(), Some("The Band"));
+        // ファイルの最後の曲は終わりが分からない
+        assert_eq!(entries[1].start, Some(Duration::from_secs(252)));
+        assert_eq!((entries[1].end, entries[1].duration), (None, None));
+        assert_eq!(entries[1].artist.as_deref(), Some("Guest"));
+        assert_eq!(entries[2].end, None);
+        assert!(entries.iter().all(|e| e.album.as_deref() == Some("Live")));
+    }
+
+    #[test]
+    fn skips_tracks_without_index_01() {
+        let entries = parse(
+            "FILE album.wav WAVE\n\
+               TRACK 01 AUDIO\n\
+                 INDEX 01 00:00:00\n\
+               TRACK 02 AUDIO\n\
+                 TITLE \"Hidden\"\n\
+                 INDEX 00 02:00:00\n\
+               TRACK 03 MODE1/2352\n\
+                 INDEX 01 03:00:00\n\
+               TRACK 04 AUDIO\n\
+                 TITLE \"Last\"\n\
+                 INDEX 01 05:00:00\n",
+        );
+        let titles: Vec<_> = entries.iter().map(|e| e.title.as_deref()).collect();
+        assert_eq!(titles, [None, Some("Last")]);
+        // データトラックと INDEX 01 のない曲は区切りにならない
+        assert_eq!(entries[0].end, Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn reads_file_names() {
+        assert_eq!(file_name("\"My Album.flac\" WAVE"), "My Album.flac");
+        assert_eq!(file_name("\"My Album.flac\""), "My Album.flac");
+        assert_eq!(file_name("My Album.flac WAVE"), "My Album.flac");
+        assert_eq!(file_name("album.mp3"), "album.mp3");
+    }
+}
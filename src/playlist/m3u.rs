@@ -0,0 +1,364 @@
+//! M3U playlists, plain or extended.
+//!
+//! ```text
+//! #EXTM3U
+//! #PLAYLIST:Road Trip
+//! #EXTALB:Album
+//! #EXTART:Artist
+//! #EXTINF:215 tvg-logo="cover.jpg" group-title="Rock",Song
+//! #EXTBYT:8388608
+//! song.flac
+//! ```
+//!
+//! `#EXTALB`, `#EXTART`, `#EXTGENRE`, `#EXTGRP` and `#EXTIMG` apply to the
+//! entries after them until they are given again, and an empty value clears
+//! them. `#EXTINF` and `#EXTBYT` apply to the next entry only, as do the
+//! `start-time` and `stop-time` options of VLC's `#EXTVLCOPT`, which play a
+//! part of a file such as a track of a CUE sheet.
+use std::time::Duration;
+
+use super::PlaylistEntry;
+
+/// Details that stay in effect for all following entries
+#[derive(Clone, Default, PartialEq)]
+struct Sticky {
+    album: Option<String>,
+    artist: Option<String>,
+    genre: Option<String>,
+    group: Option<String>,
+    image: Option<String>,
+}
+
+impl Sticky {
+    fn of(entry: &PlaylistEntry) -> Self {
+        Self {
+            album: entry.album.clone(),
+            artist: entry.artist.clone(),
+            genre: entry.genre.clone(),
+            group: entry.group.clone(),
+            image: entry.image.clone(),
+        }
+    }
+}
+
+pub fn parse(m3u: &str) -> Vec<PlaylistEntry> {
+    let mut entries = Vec::new();
+    let mut playlist = None;
+    let mut sticky = Sticky::default();
+    // 次のエントリーだけに付く情報
+    let mut next = PlaylistEntry::default();
+
+    // #EXTM3U ヘッダがなくても、# で始まる行は指示かコメントとして読む
+    for line in m3u.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let Some(directive) = line.strip_prefix('#') else {
+            let next = std::mem::take(&mut next);
+            let artist = next.artist.or_else(|| sticky.artist.clone());
+            entries.push(PlaylistEntry {
+                title: next
+                    .title
+                    .map(|title| strip_artist(title, artist.as_deref())),
+                duration: next.duration,
+                artist,
+                album: next.album.or_else(|| sticky.album.clone()),
+                image: next.image.or_else(|| sticky.image.clone()),
+                genre: next.genre.or_else(|| sticky.genre.clone()),
+                group: next.group.or_else(|| sticky.group.clone()),
+                size: next.size,
+                playlist: playlist.clone(),
+                start: next.start,
+                end: next.end,
+                ..PlaylistEntry::new(line)
+            });
+            continue;
+        };
+
+        let (name, value) = directive.split_once(':').unwrap_or((directive, ""));
+        let value = non_empty(value);
+        match name.trim().to_ascii_uppercase().as_str() {
+            "EXTINF" => extinf(value.as_deref().unwrap_or_default(), &mut next),
+            "EXTBYT" => next.size = value.and_then(|size| size.parse().ok()),
+            "EXTVLCOPT" => vlc_option(value.as_deref().unwrap_or_default(), &mut next),
+            "PLAYLIST" => playlist = value,
+            "EXTALB" => sticky.album = value,
+            "EXTART" => sticky.artist = value,
+            "EXTGENRE" => sticky.genre = value,
+            "EXTGRP" => sticky.group = value,
+            "EXTIMG" => sticky.image = value,
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// Reads `<duration> [key="value" ...],<title>` into `entry`.
+fn extinf(info: &str, entry: &mut PlaylistEntry) {
+    let end = info
+        .find(|c: char| c.is_whitespace() || c == ',')
+        .unwrap_or(info.len());
+    // -1 は長さ不明
+    entry.duration = seconds(&info[..end]);
+    entry.image = None;
+    entry.group = None;
+
+    let (attrs, title) = attributes(&info[end..]);
+    let mut name = None;
+    for (key, value) in attrs {
+        let value = non_empty(&value);
+        match key.to_ascii_lowercase().as_str() {
+            "tvg-logo" | "logo" => entry.image = value,
+            "group-title" => entry.group = value,
+            "tvg-name" => name = value,
+            _ => {}
+        }
+    }
+    entry.title = non_empty(title).or(name);
+}
+
+/// Reads the `start-time=<secs>` and `stop-time=<secs>` options of VLC.
+pub(super) fn vlc_option(option: &str, entry: &mut PlaylistEntry) {
+    match option.split_once('=') {
+        Some(("start-time", secs)) => entry.start = seconds(secs),
+        Some(("stop-time", secs)) => entry.end = seconds(secs),
+        _ => {}
+    }
+}
+
+fn seconds(secs: &str) -> Option<Duration> {
+    secs.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+/// Splits the `key="value"` list of an `#EXTINF` line from the title after
+/// the first comma outside quotes.
+fn attributes(text: &str) -> (Vec<(String, String)>, &str) {
+    let mut attrs = Vec::new();
+    let mut rest = text;
+
+    loop {
+        rest = rest.trim_start();
+        if let Some(title) = rest.strip_prefix(',') {
+            return (attrs, title);
+        }
+        let Some(eq) = rest.find('=') else {
+            break;
+        };
+        let key = &rest[..eq];
+        if key.is_empty() || key.contains(|c: char| c == ',' || c.is_whitespace()) {
+            break;
+        }
+        let value = &rest[eq + 1..];
+        let (value, next) = match value.strip_prefix('"') {
+            Some(inner) => {
+                let end = inner.find('"').unwrap_or(inner.len());
+                (&inner[..end], inner.get(end + 1..).unwrap_or(""))
+            }
+            None => {
+                let end = value
+                    .find(|c: char| c.is_whitespace() || c == ',')
+                    .unwrap_or(value.len());
+                (&value[..end], &value[end..])
+            }
+        };
+        attrs.push((key.to_string(), value.to_string()));
+        rest = next;
+    }
+
+    // 属性として読めないものはタイトルの一部とみなす
+    let title = rest.split_once(',').map_or("", |(_, title)| title);
+    (attrs, title)
+}
+
+/// Removes the `Artist - ` that many players put before the title when the
+/// artist is given on its own as well.
+fn strip_artist(title: String, artist: Option<&str>) -> String {
+    artist
+        .and_then(|artist| title.strip_prefix(artist))
+        .and_then(|rest| rest.strip_prefix(" - "))
+        .filter(|rest| !rest.trim().is_empty())
+        .map_or_else(|| title.clone(), String::from)
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    Some(value.trim())
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+/// Writes extended M3U. Album details are written as directives when they
+/// change, `#EXTINF` lines for entries whose title or length is known.
+pub fn write(entries: &[PlaylistEntry]) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+
+    // すべてのエントリーが同じプレイリストから来たときだけ名前を書く
+    if let Some(playlist) = entries.first().and_then(|e| e.playlist.as_ref())
+        && entries
+            .iter()
+            .all(|e| e.playlist.as_ref() == Some(playlist))
+    {
+        m3u.push_str(&format!("#PLAYLIST:{}\n", one_line(playlist)));
+    }
+
+    let mut sticky = Sticky::default();
+    for entry in entries {
+        let current = Sticky::of(entry);
+        let directives = [
+            ("EXTALB", &sticky.album, &current.album),
+            ("EXTART", &sticky.artist, &current.artist),
+            ("EXTGENRE", &sticky.genre, &current.genre),
+            ("EXTGRP", &sticky.group, &current.group),
+            ("EXTIMG", &sticky.image, &current.image),
+        ];
+        for (name, before, now) in directives {
+            if before != now {
+                let value = now.as_deref().map(one_line).unwrap_or_default();
+                m3u.push_str(&format!("#{}:{}\n", name, value));
+            }
+        }
+        sticky = current;
+
+        let title = match (&entry.artist, &entry.title) {
+            (Some(artist), Some(title)) => Some(format!("{} - {}", artist, title)),
+            (_, title) => title.clone(),
+        };
+        if title.is_some() || entry.duration.is_some() {
+            // -1 は長さ不明
+            let secs = entry
+                .duration
+                .map_or(-1, |d| d.as_secs_f64().round() as i64);
+            m3u.push_str(&format!(
+                "#EXTINF:{},{}\n",
+                secs,
+                one_line(title.as_deref().unwrap_or_default())
+            ));
+        }
+        if let Some(size) = entry.size {
+            m3u.push_str(&format!("#EXTBYT:{}\n", size));
+        }
+        for option in vlc_options(entry) {
+            m3u.push_str(&format!("#EXTVLCOPT:{}\n", option));
+        }
+        m3u.push_str(&one_line(&entry.location));
+        m3u.push('\n');
+    }
+    m3u
+}
+
+/// The VLC options that play only the part of the file `entry` covers.
+pub(super) fn vlc_options(entry: &PlaylistEntry) -> Vec<String> {
+    let mut options = Vec::new();
+    if let Some(start) = entry.start.filter(|start| !start.is_zero()) {
+        options.push(format!("start-time={:.3}", start.as_secs_f64()));
+    }
+    if let Some(end) = entry.end {
+        options.push(format!("stop-time={:.3}", end.as_secs_f64()));
+    }
+    options
+}
+
+pub(super) fn one_line(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locations(entries: &[PlaylistEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.location.as_str()).collect()
+    }
+
+    #[test]
+    fn reads_plain_list() {
+        let entries = parse("a.mp3\n\n# comment\nhttp://example.com/b.ogg\n");
+        assert_eq!(locations(&entries), ["a.mp3", "http://example.com/b.ogg"]);
+        assert_eq!(entries[0].title, None);
+    }
+
+    #[test]
+    fn reads_crlf_lines() {
+        let entries =
+            parse("#EXTM3U\r\n#EXTINF:215,Song\r\nsong.flac\r\n#EXTBYT:1024\r\nnext.mp3\r\n");
+        assert_eq!(locations(&entries), ["song.flac", "next.mp3"]);
+        assert_eq!(entries[0].title.as_deref(), Some("Song"));
+        assert_eq!(entries[0].duration, Some(Duration::from_secs(215)));
+        assert_eq!(entries[1].size, Some(1024));
+    }
+
+    #[test]
+    fn reads_extinf_attributes() {
+        let entries = parse(
+            "#EXTM3U\n\
+             #EXTINF:-1 tvg-name=\"News, 24h\" tvg-logo=\"logo.png\" group-title=\"News, World\",Channel, One\n\
+             http://example.com/news\n\
+             #EXTINF:-1 tvg-name=\"Fallback, Name\",\n\
+             http://example.com/other\n",
+        );
+        assert_eq!(entries[0].title.as_deref(), Some("Channel, One"));
+        assert_eq!(entries[0].duration, None);
+        assert_eq!(entries[0].image.as_deref(), Some("logo.png"));
+        assert_eq!(entries[0].group.as_deref(), Some("News, World"));
+        // タイトルが空なら tvg-name を使う
+        assert_eq!(entries[1].title.as_deref(), Some("Fallback, Name"));
+        assert_eq!(entries[1].group, None);
+    }
+
+    #[test]
+    fn keeps_sticky_directives_until_changed() {
+        let entries = parse(
+            "#EXTM3U\n\
+             #PLAYLIST:Mix\n\
+             #EXTALB:First\n\
+             #EXTART:Band\n\
+             #EXTGRP:Rock\n\
+             #EXTINF:100,Band - One\n\
+             1.mp3\n\
+             2.mp3\n\
+             #EXTALB:Second\n\
+             #EXTGRP:\n\
+             3.mp3\n",
+        );
+        assert_eq!(locations(&entries), ["1.mp3", "2.mp3", "3.mp3"]);
+        let albums: Vec<_> = entries.iter().map(|e| e.album.as_deref()).collect();
+        assert_eq!(albums, [Some("First"), Some("First"), Some("Second")]);
+        let groups: Vec<_> = entries.iter().map(|e| e.group.as_deref()).collect();
+        assert_eq!(groups, [Some("Rock"), Some("Rock"), None]);
+        assert!(entries.iter().all(|e| e.artist.as_deref() == Some("Band")));
+        assert!(entries.iter().all(|e| e.playlist.as_deref() == Some("Mix")));
+        // EXTINF は次の 1 件だけ
+        assert_eq!(entries[0].title.as_deref(), Some("One"));
+        assert_eq!(entries[1].title, None);
+        assert_eq!(entries[1].duration, None);
+    }
+
+    #[test]
+    fn reads_vlc_clip_options() {
+        let entries =
+            parse("#EXTVLCOPT:start-time=10.5\n#EXTVLCOPT:stop-time=20\nalbum.flac\nalbum.flac\n");
+        assert_eq!(entries[0].start, Some(Duration::from_secs_f64(10.5)));
+        assert_eq!(entries[0].end, Some(Duration::from_secs(20)));
+        assert_eq!((entries[1].start, entries[1].end), (None, None));
+    }
+
+    #[test]
+    fn round_trips_written_playlist() {
+        let entries = vec![
+            PlaylistEntry {
+                title: Some("One".into()),
+                artist: Some("Band".into()),
+                album: Some("First".into()),
+                duration: Some(Duration::from_secs(100)),
+                ..PlaylistEntry::new("1.mp3")
+            },
+            PlaylistEntry {
+                album: Some("First".into()),
+                start: Some(Duration::from_secs(3)),
+                end: Some(Duration::from_secs(9)),
+                ..PlaylistEntry::new("2.mp3")
+            },
+        ];
+        assert_eq!(parse(&write(&entries)), entries);
+    }
+}
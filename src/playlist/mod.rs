@@ -0,0 +1,569 @@
+//! Playlists: M3U, PLS, XSPF, ASX and CUE sheets.
+//!
+//! All formats are read into the same [`PlaylistEntry`] list. The format is
+//! recognised from the content first, then from the MIME type and the
+//! extension. M3U, PLS and XSPF can also be written with [`save`].
+mod asx;
+mod cue;
+pub mod m3u;
+mod pls;
+mod xml;
+mod xspf;
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+
+use crate::error::{MinauError, Result};
+
+/// Bytes of a file looked at to recognise a playlist
+const SNIFF_LEN: usize = 1024;
+
+/// One item of a playlist.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlaylistEntry {
+    /// Path or URL of the media, resolved against the playlist by [`load`]
+    pub location: String,
+    pub title: Option<String>,
+    pub duration: Option<Duration>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// Path or URL of cover art
+    pub image: Option<String>,
+    pub genre: Option<String>,
+    /// Group or category, such as the channel group of an IPTV list
+    pub group: Option<String>,
+    /// Size of the file in bytes
+    pub size: Option<u64>,
+    /// Title of the playlist the entry was read from
+    pub playlist: Option<String>,
+    /// Where in the file the entry starts, for tracks of a CUE sheet
+    pub start: Option<Duration>,
+    /// Where in the file the entry ends, or the end of the file if `None`
+    pub end: Option<Duration>,
+}
+
+impl PlaylistEntry {
+    pub fn new(location: &str) -> Self {
+        Self {
+            location: location.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf,
+    Asx,
+    Cue,
+}
+
+impl PlaylistFormat {
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "xspf" => Some(Self::Xspf),
+            "asx" | "wax" | "wvx" => Some(Self::Asx),
+            "cue" => Some(Self::Cue),
+            _ => None,
+        }
+    }
+
+    /// Recognises the `Content-Type` of a playlist served over HTTP.
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        let mime = mime.split(';').next().unwrap_or_default().trim();
+        match mime.to_ascii_lowercase().as_str() {
+            "audio/x-mpegurl"
+            | "audio/mpegurl"
+            | "application/x-mpegurl"
+            | "application/vnd.apple.mpegurl"
+            | "audio/m3u"
+            | "audio/x-m3u" => Some(Self::M3u),
+            "audio/x-scpls" | "audio/scpls" | "application/pls+xml" => Some(Self::Pls),
+            "application/xspf+xml" => Some(Self::Xspf),
+            "video/x-ms-asx" | "audio/x-ms-wax" | "video/x-ms-wvx" | "application/x-ms-asx" => {
+                Some(Self::Asx)
+            }
+            "application/x-cue" => Some(Self::Cue),
+            _ => None,
+        }
+    }
+
+    /// Recognises a playlist by its first lines.
+    pub fn sniff(text: &str) -> Option<Self> {
+        let head: String = text.trim_start().chars().take(SNIFF_LEN).collect();
+        let head = head.to_ascii_lowercase();
+
+        if head.starts_with("#extm3u") {
+            Some(Self::M3u)
+        } else if head.starts_with("[playlist]") {
+            Some(Self::Pls)
+        } else if head.contains("<playlist") && head.contains("xspf.org") {
+            Some(Self::Xspf)
+        } else if head.contains("<asx") {
+            Some(Self::Asx)
+        } else if matches!(
+            head.split_whitespace().next(),
+            Some("rem" | "file" | "title" | "performer" | "catalog" | "songwriter")
+        ) && head.contains("track ")
+        {
+            Some(Self::Cue)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for PlaylistFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::from_extension(s).ok_or_else(|| {
+            format!(
+                "unknown playlist format '{}' (expected m3u, pls, xspf, asx or cue)",
+                s
+            )
+        })
+    }
+}
+
+impl fmt::Display for PlaylistFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlaylistFormat::M3u => write!(f, "m3u"),
+            PlaylistFormat::Pls => write!(f, "pls"),
+            PlaylistFormat::Xspf => write!(f, "xspf"),
+            PlaylistFormat::Asx => write!(f, "asx"),
+            PlaylistFormat::Cue => write!(f, "cue"),
+        }
+    }
+}
+
+/// How local files are referred to in a written playlist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathStyle {
+    #[default]
+    Absolute,
+    /// Relative to the playlist's directory, where possible
+    Relative,
+}
+
+impl FromStr for PathStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "absolute" => Ok(PathStyle::Absolute),
+            "relative" => Ok(PathStyle::Relative),
+            _ => Err(format!(
+                "unknown path style '{}' (expected absolute or relative)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PathStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathStyle::Absolute => write!(f, "absolute"),
+            PathStyle::Relative => write!(f, "relative"),
+        }
+    }
+}
+
+/// Recognises the playlist `text` read from `location`, a path or URL,
+/// and served as `mime` if it came over HTTP.
+pub fn detect(text: &str, location: &str, mime: Option<&str>) -> Option<PlaylistFormat> {
+    PlaylistFormat::sniff(text)
+        .or_else(|| mime.and_then(PlaylistFormat::from_mime))
+        .or_else(|| extension(location).and_then(|ext| PlaylistFormat::from_extension(&ext)))
+}
+
+/// Whether the local file at `path` is a playlist, by its extension or,
+/// failing that, its first bytes.
+pub fn is_playlist_file(path: &Path) -> bool {
+    if let Some(ext) = path.extension()
+        && PlaylistFormat::from_extension(&ext.to_string_lossy()).is_some()
+    {
+        return true;
+    }
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    if file.take(SNIFF_LEN as u64).read_to_end(&mut head).is_err() {
+        return false;
+    }
+    PlaylistFormat::sniff(&decode(&head)).is_some()
+}
+
+pub fn parse(text: &str, format: PlaylistFormat) -> Vec<PlaylistEntry> {
+    match format {
+        PlaylistFormat::M3u => m3u::parse(text),
+        PlaylistFormat::Pls => pls::parse(text),
+        PlaylistFormat::Xspf => xspf::parse(text),
+        PlaylistFormat::Asx => asx::parse(text),
+        PlaylistFormat::Cue => cue::parse(text),
+    }
+}
+
+/// Reads a CUE sheet embedded in the tags of `location`, such as the
+/// `CUESHEET` Vorbis comment of a FLAC file, into tracks of that file. The
+/// `FILE` lines of the sheet are ignored.
+pub fn from_cuesheet(cuesheet: &str, location: &str) -> Vec<PlaylistEntry> {
+    cue::parse(cuesheet)
+        .into_iter()
+        .map(|entry| PlaylistEntry {
+            location: location.to_string(),
+            ..entry
+        })
+        .collect()
+}
+
+/// Writes `entries` in `format`. Locations are written as they are.
+pub fn write(entries: &[PlaylistEntry], format: PlaylistFormat) -> Result<String> {
+    match format {
+        PlaylistFormat::M3u => Ok(m3u::write(entries)),
+        PlaylistFormat::Pls => Ok(pls::write(entries)),
+        PlaylistFormat::Xspf => Ok(xspf::write(entries)),
+        PlaylistFormat::Asx => Err(MinauError::Playlist(
+            "ASX playlists can only be read".into(),
+        )),
+        PlaylistFormat::Cue => Err(MinauError::Playlist("CUE sheets can only be read".into())),
+    }
+}
+
+/// Writes `entries` to the playlist file `path`, in `format` or the format
+/// of its extension.
+///
+/// Local files are written as absolute paths or relative to the playlist's
+/// directory, as `file://` URIs in XSPF. URLs are written unchanged.
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    entries: &[PlaylistEntry],
+    format: Option<PlaylistFormat>,
+    paths: PathStyle,
+) -> Result<()> {
+    let path = path.as_ref();
+    let format = format
+        .or_else(|| PlaylistFormat::from_extension(&path.extension()?.to_string_lossy()))
+        .ok_or_else(|| {
+            MinauError::Playlist(format!(
+                "Cannot tell the playlist format of {}",
+                path.display()
+            ))
+        })?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => absolute(dir),
+        _ => absolute(Path::new(".")),
+    };
+
+    let locate = |location: &str| locate(location, &dir, format, paths);
+    let entries: Vec<PlaylistEntry> = entries
+        .iter()
+        .map(|entry| PlaylistEntry {
+            location: locate(&entry.location),
+            image: entry.image.as_deref().map(locate),
+            ..entry.clone()
+        })
+        .collect();
+
+    std::fs::write(path, write(&entries, format)?)
+        .map_err(|e| MinauError::Playlist(format!("Failed to write playlist: {}", e)))
+}
+
+/// Reads a playlist file and resolves relative entries against its directory.
+///
+/// URL entries are returned unchanged, `file://` URLs as paths.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<PlaylistEntry>> {
+    let path = path.as_ref();
+    let content = std::fs::read(path)
+        .map_err(|e| MinauError::Playlist(format!("Failed to read playlist: {}", e)))?;
+    let text = decode(&content);
+
+    let format = detect(&text, &path.to_string_lossy(), None).ok_or_else(|| {
+        MinauError::Playlist(format!("{} is not a known playlist", path.display()))
+    })?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let entries = parse(&text, format)
+        .into_iter()
+        .map(|mut entry| {
+            entry.location = resolve_local(dir, &entry.location, format);
+            if format == PlaylistFormat::Cue {
+                entry.location = find_renamed(&entry.location);
+            }
+            if let Some(image) = &entry.image {
+                entry.image = Some(resolve_local(dir, image, format));
+            }
+            entry
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Resolves the entries of a playlist fetched from `base` against its URL.
+///
+/// Only HTTP and HTTPS entries are kept, so a playlist from the network
+/// cannot point the player at local files.
+pub fn resolve_remote(entries: Vec<PlaylistEntry>, base: &str) -> Vec<PlaylistEntry> {
+    let Ok(base) = Url::parse(base) else {
+        return Vec::new();
+    };
+    let join = |location: &str| {
+        base.join(location.trim())
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .map(String::from)
+    };
+
+    entries
+        .into_iter()
+        .filter_map(|mut entry| {
+            entry.location = join(&entry.location)?;
+            entry.image = entry.image.as_deref().and_then(join);
+            Some(entry)
+        })
+        .collect()
+}
+
+/// Makes `location` usable from outside the playlist in `dir`.
+fn resolve_local(dir: &Path, location: &str, format: PlaylistFormat) -> String {
+    if let Some(url) = parse_url(location) {
+        return match url.to_file_path() {
+            Ok(path) if url.scheme() == "file" => path.to_string_lossy().to_string(),
+            _ => location.to_string(),
+        };
+    }
+    if Path::new(location).is_absolute() {
+        return location.to_string();
+    }
+
+    // XSPF の location は URI なので %20 などを戻してから繋ぐ
+    if format == PlaylistFormat::Xspf
+        && let Ok(base) = Url::from_directory_path(absolute(dir))
+        && let Ok(url) = base.join(location)
+        && let Ok(path) = url.to_file_path()
+    {
+        return path.to_string_lossy().to_string();
+    }
+    dir.join(location).to_string_lossy().to_string()
+}
+
+/// Rips are often converted after the CUE sheet was written, so a `FILE`
+/// that does not exist is looked for under the same name with another
+/// extension, as `album.wav` becoming `album.flac`.
+fn find_renamed(location: &str) -> String {
+    let path = Path::new(location);
+    if path.exists() {
+        return location.to_string();
+    }
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return location.to_string();
+    };
+    let Ok(files) = std::fs::read_dir(dir) else {
+        return location.to_string();
+    };
+    files
+        .flatten()
+        .map(|file| file.path())
+        .find(|file| {
+            file.file_stem() == Some(stem)
+                && file
+                    .extension()
+                    .is_some_and(|ext| !ext.eq_ignore_ascii_case("cue"))
+        })
+        .map_or_else(
+            || location.to_string(),
+            |file| file.to_string_lossy().to_string(),
+        )
+}
+
+fn absolute(dir: &Path) -> PathBuf {
+    std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf())
+}
+
+/// Turns `location` into what a playlist in `dir` should refer to it by.
+fn locate(location: &str, dir: &Path, format: PlaylistFormat, paths: PathStyle) -> String {
+    if parse_url(location).is_some() {
+        return location.to_string();
+    }
+    let path = absolute(Path::new(location));
+    let relative = match paths {
+        PathStyle::Relative => relative_to(&path, dir),
+        PathStyle::Absolute => None,
+    };
+
+    if format == PlaylistFormat::Xspf {
+        let Ok(url) = Url::from_file_path(&path) else {
+            return location.to_string();
+        };
+        // 相対参照も URI なのでエンコードされたものを使う
+        return match Url::from_directory_path(dir) {
+            Ok(base) if relative.is_some() => base.make_relative(&url),
+            _ => None,
+        }
+        .unwrap_or_else(|| url.to_string());
+    }
+    relative.unwrap_or(path).to_string_lossy().to_string()
+}
+
+/// `path` relative to `dir`, both absolute, unless they are on different
+/// drives.
+fn relative_to(path: &Path, dir: &Path) -> Option<PathBuf> {
+    let path: Vec<Component> = path.components().collect();
+    let dir: Vec<Component> = dir.components().collect();
+    if path.first() != dir.first() {
+        return None;
+    }
+
+    let common = path.iter().zip(&dir).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    relative.extend(&path[common..]);
+    Some(relative)
+}
+
+/// Parses `location` if it is a URL. Windows paths like `C:\a.mp3` are
+/// not taken for URLs with a one-letter scheme.
+fn parse_url(location: &str) -> Option<Url> {
+    Url::parse(location)
+        .ok()
+        .filter(|url| url.scheme().len() > 1)
+}
+
+/// Extension of the last path segment of a path or URL.
+fn extension(location: &str) -> Option<String> {
+    let path = match parse_url(location) {
+        Some(url) => url.path().to_string(),
+        None => location.to_string(),
+    };
+    Path::new(&path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+}
+
+/// Decodes playlist text: UTF-8 or UTF-16 with a byte order mark, UTF-8,
+/// and Latin-1 for anything else.
+pub(crate) fn decode(bytes: &[u8]) -> String {
+    if let Some(rest) = bytes.strip_prefix(b"\xef\xbb\xbf") {
+        return String::from_utf8_lossy(rest).into_owned();
+    }
+    let utf16 = |rest: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        let units = rest.chunks_exact(2).map(|b| from_bytes([b[0], b[1]]));
+        char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    };
+    if let Some(rest) = bytes.strip_prefix(b"\xff\xfe") {
+        return utf16(rest, u16::from_le_bytes);
+    }
+    if let Some(rest) = bytes.strip_prefix(b"\xfe\xff") {
+        return utf16(rest, u16::from_be_bytes);
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        // Windows で作られた .m3u は Latin-1 のことが多い
+        Err(_) => bytes.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "#EXTM3U\r\n#EXTINF:1,Café\r\ncafé.mp3\r\n";
+
+    fn utf16(bom: [u8; 2], to_bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        let mut bytes = bom.to_vec();
+        bytes.extend(TEXT.encode_utf16().flat_map(to_bytes));
+        bytes
+    }
+
+    #[test]
+    fn decodes_utf8() {
+        assert_eq!(decode(TEXT.as_bytes()), TEXT);
+        let mut bom = b"\xef\xbb\xbf".to_vec();
+        bom.extend_from_slice(TEXT.as_bytes());
+        assert_eq!(decode(&bom), TEXT);
+    }
+
+    #[test]
+    fn decodes_utf16_with_bom() {
+        assert_eq!(decode(&utf16([0xff, 0xfe], u16::to_le_bytes)), TEXT);
+        assert_eq!(decode(&utf16([0xfe, 0xff], u16::to_be_bytes)), TEXT);
+    }
+
+    #[test]
+    fn falls_back_to_latin1() {
+        assert_eq!(decode(b"caf\xe9.mp3\r\n"), "café.mp3\r\n");
+    }
+
+    #[test]
+    fn resolves_local_entries_against_playlist_dir() {
+        let dir = Path::new("/music/lists");
+        let resolve = |location, format| resolve_local(dir, location, format);
+        assert_eq!(
+            resolve("../album/01.flac", PlaylistFormat::M3u),
+            "/music/lists/../album/01.flac"
+        );
+        assert_eq!(resolve("/srv/a.mp3", PlaylistFormat::Pls), "/srv/a.mp3");
+        assert_eq!(
+            resolve("file:///srv/my%20song.mp3", PlaylistFormat::M3u),
+            "/srv/my song.mp3"
+        );
+        assert_eq!(
+            resolve("http://example.com/a.mp3", PlaylistFormat::Asx),
+            "http://example.com/a.mp3"
+        );
+        // XSPF の location だけが URI として読まれる
+        assert_eq!(
+            resolve("my%20song.flac", PlaylistFormat::Xspf),
+            "/music/lists/my song.flac"
+        );
+        assert_eq!(
+            resolve("my%20song.flac", PlaylistFormat::M3u),
+            "/music/lists/my%20song.flac"
+        );
+    }
+
+    #[test]
+    fn resolves_remote_entries_against_playlist_url() {
+        let entries = pls::parse(
+            "[playlist]\n\
+             File1=song.mp3\n\
+             File2=/root.mp3\n\
+             File3=http://other.example/live\n\
+             File4=file:///etc/passwd\n",
+        );
+        let entries = resolve_remote(entries, "https://example.com/lists/radio.pls");
+        let locations: Vec<_> = entries.iter().map(|e| e.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "https://example.com/lists/song.mp3",
+                "https://example.com/root.mp3",
+                "http://other.example/live",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_decoded_crlf_playlist() {
+        let entries = m3u::parse(&decode(&utf16([0xff, 0xfe], u16::to_le_bytes)));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].location, "café.mp3");
+        assert_eq!(entries[0].title.as_deref(), Some("Café"));
+    }
+}
@@ -0,0 +1,118 @@
+//! PLS playlists, as served by Shoutcast and Icecast stations.
+//!
+//! ```text
+//! [playlist]
+//! File1=http://example.com:8000/stream
+//! Title1=Example Radio
+//! Length1=-1
+//! NumberOfEntries=1
+//! Version=2
+//! ```
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use super::PlaylistEntry;
+use super::m3u::one_line;
+
+pub fn parse(pls: &str) -> Vec<PlaylistEntry> {
+    // 番号ごとに集めてから番号順に並べる
+    let mut entries: BTreeMap<u32, PlaylistEntry> = BTreeMap::new();
+
+    for line in pls.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim();
+        let Some((field, index)) = split_index(&key) else {
+            continue;
+        };
+        let entry = entries
+            .entry(index)
+            .or_insert_with(|| PlaylistEntry::new(""));
+
+        match field {
+            "file" => entry.location = value.to_string(),
+            "title" if !value.is_empty() => entry.title = Some(value.to_string()),
+            "length" => {
+                entry.duration = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                    .map(Duration::from_secs_f64);
+            }
+            _ => {}
+        }
+    }
+
+    entries
+        .into_values()
+        .filter(|entry| !entry.location.is_empty())
+        .collect()
+}
+
+/// Splits `file12` into `("file", 12)`.
+fn split_index(key: &str) -> Option<(&str, u32)> {
+    let digits = key.find(|c: char| c.is_ascii_digit())?;
+    let index = key[digits..].parse().ok()?;
+    Some((&key[..digits], index))
+}
+
+pub fn write(entries: &[PlaylistEntry]) -> String {
+    let mut pls = String::from("[playlist]\n");
+    for (i, entry) in entries.iter().enumerate() {
+        let n = i + 1;
+        pls.push_str(&format!("File{}={}\n", n, one_line(&entry.location)));
+        if let Some(title) = &entry.title {
+            pls.push_str(&format!("Title{}={}\n", n, one_line(title)));
+        }
+        let secs = entry
+            .duration
+            .map_or(-1, |d| d.as_secs_f64().round() as i64);
+        pls.push_str(&format!("Length{}={}\n", n, secs));
+    }
+    pls.push_str(&format!("NumberOfEntries={}\nVersion=2\n", entries.len()));
+    pls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_numbered_entries_with_gaps() {
+        let entries = parse(
+            "[playlist]\n\
+             File3=http://example.com/c\n\
+             Title3=Third\n\
+             Length3=215.5\n\
+             file1 = http://example.com/a\n\
+             Title1=\n\
+             Length1=-1\n\
+             Title2=No file\n\
+             NumberOfEntries=3\n\
+             Version=2\n",
+        );
+        let locations: Vec<_> = entries.iter().map(|e| e.location.as_str()).collect();
+        assert_eq!(locations, ["http://example.com/a", "http://example.com/c"]);
+        assert_eq!(entries[0].title, None);
+        assert_eq!(entries[0].duration, None);
+        assert_eq!(entries[1].title.as_deref(), Some("Third"));
+        assert_eq!(entries[1].duration, Some(Duration::from_secs_f64(215.5)));
+    }
+
+    #[test]
+    fn round_trips_written_playlist() {
+        let entries = vec![
+            PlaylistEntry {
+                title: Some("Radio".into()),
+                ..PlaylistEntry::new("http://example.com/stream")
+            },
+            PlaylistEntry {
+                duration: Some(Duration::from_secs(100)),
+                ..PlaylistEntry::new("song.mp3")
+            },
+        ];
+        assert_eq!(parse(&write(&entries)), entries);
+    }
+}
@@ -0,0 +1,243 @@
+//! A forgiving XML reader for XSPF and ASX.
+//!
+//! ASX files in the wild mix the case of tags, leave `&` unescaped and do
+//! not always close their elements, so this does not validate anything. Names
+//! are lowercased and stripped of namespace prefixes.
+
+#[derive(Debug, Default)]
+pub struct Element {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    /// Text directly inside this element
+    pub text: String,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    /// Trimmed text of the first child called `name`, if not empty.
+    pub fn child_text(&self, name: &str) -> Option<String> {
+        self.child(name)
+            .map(|c| c.text.trim().to_string())
+            .filter(|t| !t.is_empty())
+    }
+
+    /// Finds the first element called `name` in this tree, itself included.
+    pub fn find(&self, name: &str) -> Option<&Element> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter().find_map(|c| c.find(name))
+    }
+}
+
+/// Parses `text` into a tree below an unnamed root element. Elements named
+/// in `void` never have content, even when their tags are not closed.
+pub fn parse(text: &str, void: &[&str]) -> Element {
+    let mut stack = vec![Element::default()];
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let Some(lt) = rest.find('<') else {
+            append_text(&mut stack, rest);
+            break;
+        };
+        append_text(&mut stack, &rest[..lt]);
+        rest = &rest[lt..];
+
+        // コメント、CDATA、宣言、処理命令
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after.find("]]>").unwrap_or(after.len());
+            if let Some(top) = stack.last_mut() {
+                top.text.push_str(&after[..end]);
+            }
+            rest = after.get(end + 3..).unwrap_or("");
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+            continue;
+        }
+
+        let end = tag_end(rest);
+        let tag = &rest[1..end];
+        rest = rest.get(end + 1..).unwrap_or("");
+
+        if let Some(name) = tag.strip_prefix('/') {
+            close(&mut stack, &local_name(name.trim()));
+            continue;
+        }
+        let (tag, empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let element = Element {
+            name: local_name(&tag[..name_end]),
+            attrs: attributes(&tag[name_end..]),
+            ..Default::default()
+        };
+        if empty || void.contains(&element.name.as_str()) {
+            if let Some(top) = stack.last_mut() {
+                top.children.push(element);
+            }
+        } else {
+            stack.push(element);
+        }
+    }
+
+    // 閉じられていない要素を畳む
+    while stack.len() > 1 {
+        let element = stack.pop().unwrap_or_default();
+        if let Some(parent) = stack.last_mut() {
+            parent.children.push(element);
+        }
+    }
+    stack.pop().unwrap_or_default()
+}
+
+/// Position of the `>` closing the tag at the start of `text`, skipping
+/// quoted attribute values.
+fn tag_end(text: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in text.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return i,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+fn close(stack: &mut Vec<Element>, name: &str) {
+    // 対応する開始タグがなければ無視する
+    if !stack.iter().skip(1).any(|e| e.name == name) {
+        return;
+    }
+    while stack.len() > 1 {
+        let element = stack.pop().unwrap_or_default();
+        let done = element.name == name;
+        if let Some(parent) = stack.last_mut() {
+            parent.children.push(element);
+        }
+        if done {
+            break;
+        }
+    }
+}
+
+fn append_text(stack: &mut [Element], text: &str) {
+    if let Some(top) = stack.last_mut() {
+        top.text.push_str(&unescape(text));
+    }
+}
+
+fn local_name(name: &str) -> String {
+    let name = name.rsplit(':').next().unwrap_or(name);
+    name.to_ascii_lowercase()
+}
+
+fn attributes(text: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    let mut rest = text.trim();
+
+    while let Some(eq) = rest.find('=') {
+        // 値のない属性は名前ごと読み飛ばす
+        let name = local_name(rest[..eq].split_whitespace().last().unwrap_or_default());
+        let value = rest[eq + 1..].trim_start();
+        let (value, next) = match value.chars().next() {
+            Some(q @ ('"' | '\'')) => {
+                let inner = &value[1..];
+                let end = inner.find(q).unwrap_or(inner.len());
+                (&inner[..end], inner.get(end + 1..).unwrap_or(""))
+            }
+            _ => {
+                let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                (&value[..end], &value[end..])
+            }
+        };
+        attrs.push((name, unescape(value)));
+        rest = next.trim_start();
+    }
+    attrs
+}
+
+/// Escapes `text` for use as element content or an attribute value.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Replaces entity references. Anything that is not one is kept as is.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+        match entity {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => {
+            let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => entity.strip_prefix('#')?.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
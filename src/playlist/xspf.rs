@@ -0,0 +1,174 @@
+//! XSPF ("spiff") playlists.
+//!
+//! ```xml
+//! <playlist version="1" xmlns="http://xspf.org/ns/0/">
+//!   <trackList>
+//!     <track>
+//!       <location>song.flac</location>
+//!       <title>Song</title>
+//!       <creator>Artist</creator>
+//!       <duration>215000</duration>
+//!     </track>
+//!   </trackList>
+//! </playlist>
+//! ```
+//!
+//! Tracks of a CUE sheet are written with the `start-time` and `stop-time`
+//! options of VLC's extension, which are read back as well.
+use std::time::Duration;
+
+use super::{PlaylistEntry, m3u, xml};
+
+const VLC_EXTENSION: &str = "http://www.videolan.org/vlc/playlist/0";
+
+pub fn parse(text: &str) -> Vec<PlaylistEntry> {
+    let root = xml::parse(text, &[]);
+    let Some(track_list) = root.find("tracklist") else {
+        return Vec::new();
+    };
+    let playlist = root
+        .find("playlist")
+        .and_then(|playlist| playlist.child_text("title"));
+
+    track_list
+        .children("track")
+        .filter_map(|track| {
+            // location が複数あるときは最初のものを使う
+            let location = track.child_text("location")?;
+            let mut entry = PlaylistEntry {
+                title: track.child_text("title"),
+                artist: track.child_text("creator"),
+                album: track.child_text("album"),
+                image: track.child_text("image"),
+                // ミリ秒
+                duration: track
+                    .child_text("duration")
+                    .and_then(|ms| ms.parse().ok())
+                    .map(Duration::from_millis),
+                playlist: playlist.clone(),
+                ..PlaylistEntry::new(&location)
+            };
+            let options = track
+                .children("extension")
+                .filter(|extension| extension.attr("application") == Some(VLC_EXTENSION))
+                .flat_map(|extension| extension.children("option"));
+            for option in options {
+                m3u::vlc_option(option.text.trim(), &mut entry);
+            }
+            Some(entry)
+        })
+        .collect()
+}
+
+pub fn write(entries: &[PlaylistEntry]) -> String {
+    let mut xspf = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\"",
+    );
+    if entries.iter().any(|e| !m3u::vlc_options(e).is_empty()) {
+        xspf.push_str(&format!(" xmlns:vlc=\"{}\"", VLC_EXTENSION));
+    }
+    xspf.push_str(">\n");
+    if let Some(playlist) = entries.first().and_then(|e| e.playlist.as_ref())
+        && entries
+            .iter()
+            .all(|e| e.playlist.as_ref() == Some(playlist))
+    {
+        xspf.push_str(&format!("  <title>{}</title>\n", xml::escape(playlist)));
+    }
+    xspf.push_str("  <trackList>\n");
+    for entry in entries {
+        xspf.push_str("    <track>\n");
+        let fields = [
+            ("location", Some(entry.location.clone())),
+            ("title", entry.title.clone()),
+            ("creator", entry.artist.clone()),
+            ("album", entry.album.clone()),
+            (
+                "duration",
+                entry.duration.map(|d| d.as_millis().to_string()),
+            ),
+            ("image", entry.image.clone()),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                xspf.push_str(&format!(
+                    "      <{0}>{1}</{0}>\n",
+                    name,
+                    xml::escape(&value)
+                ));
+            }
+        }
+        let options = m3u::vlc_options(entry);
+        if !options.is_empty() {
+            xspf.push_str(&format!(
+                "      <extension application=\"{}\">\n",
+                VLC_EXTENSION
+            ));
+            for option in options {
+                xspf.push_str(&format!(
+                    "        <vlc:option>{}</vlc:option>\n",
+                    xml::escape(&option)
+                ));
+            }
+            xspf.push_str("      </extension>\n");
+        }
+        xspf.push_str("    </track>\n");
+    }
+    xspf.push_str("  </trackList>\n</playlist>\n");
+    xspf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_tracks() {
+        let entries = parse(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n\
+               <title>Mix</title>\n\
+               <trackList>\n\
+                 <track>\n\
+                   <location>first%20song.flac</location>\n\
+                   <location>http://example.com/mirror.flac</location>\n\
+                   <title>Rock &amp; Roll</title>\n\
+                   <creator>Band</creator>\n\
+                   <duration>215500</duration>\n\
+                 </track>\n\
+                 <track><title>No location</title></track>\n\
+                 <track><location>http://example.com/b.ogg</location></track>\n\
+               </trackList>\n\
+             </playlist>\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].location, "first%20song.flac");
+        assert_eq!(entries[0].title.as_deref(), Some("Rock & Roll"));
+        assert_eq!(entries[0].artist.as_deref(), Some("Band"));
+        assert_eq!(entries[0].duration, Some(Duration::from_millis(215_500)));
+        assert_eq!(entries[0].playlist.as_deref(), Some("Mix"));
+        assert_eq!(entries[1].location, "http://example.com/b.ogg");
+        assert_eq!(
+            (entries[1].title.as_ref(), entries[1].duration),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn round_trips_written_playlist() {
+        let entries = vec![
+            PlaylistEntry {
+                title: Some("One".into()),
+                duration: Some(Duration::from_millis(1500)),
+                ..PlaylistEntry::new("1.mp3")
+            },
+            PlaylistEntry {
+                start: Some(Duration::from_secs(3)),
+                end: Some(Duration::from_secs(9)),
+                ..PlaylistEntry::new("album.flac")
+            },
+        ];
+        assert_eq!(parse(&write(&entries)), entries);
+    }
+}
@@ -0,0 +1,226 @@
+use lofty::config::WriteOptions;
+use lofty::file::{AudioFile, TaggedFileExt};
+use lofty::tag::{ItemKey, Tag};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{MinauError, Result};
+
+/// ReplayGain values of a track, as stored in its tags.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayGain {
+    /// Track gain in dB
+    pub track_gain: Option<f32>,
+    /// Track peak as a linear sample value
+    pub track_peak: Option<f32>,
+    /// Album gain in dB
+    pub album_gain: Option<f32>,
+    /// Album peak as a linear sample value
+    pub album_peak: Option<f32>,
+}
+
+/// Which ReplayGain value is applied during playback.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+    /// Album gain while neighbouring tracks come from the same album,
+    /// track gain otherwise
+    Auto,
+}
+
+/// Settings used by the engine to turn [`ReplayGain`] tags into a gain.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayGainOptions {
+    pub mode: ReplayGainMode,
+    /// Extra gain in dB added on top of the tagged value
+    pub preamp: f32,
+}
+
+impl ReplayGain {
+    /// Reads the ReplayGain items from ID3v2 TXXX frames, Vorbis comments,
+    /// APE items or MP4 freeform atoms. Earlier tags take precedence.
+    pub fn from_tags<'a>(tags: impl IntoIterator<Item = &'a Tag>) -> Self {
+        let mut gain = ReplayGain::default();
+        for tag in tags {
+            gain.track_gain = gain
+                .track_gain
+                .or_else(|| find(tag, ItemKey::ReplayGainTrackGain).and_then(parse_db));
+            gain.track_peak = gain
+                .track_peak
+                .or_else(|| find(tag, ItemKey::ReplayGainTrackPeak).and_then(parse_peak));
+            gain.album_gain = gain
+                .album_gain
+                .or_else(|| find(tag, ItemKey::ReplayGainAlbumGain).and_then(parse_db));
+            gain.album_peak = gain
+                .album_peak
+                .or_else(|| find(tag, ItemKey::ReplayGainAlbumPeak).and_then(parse_peak));
+        }
+        gain
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.track_gain.is_none() && self.album_gain.is_none()
+    }
+
+    /// Linear gain to apply with `options`. `album` selects the album value
+    /// (falling back to the track value) in [`ReplayGainMode::Auto`].
+    ///
+    /// The gain is lowered when the tagged peak would otherwise clip.
+    pub fn factor(&self, options: &ReplayGainOptions, album: bool) -> f32 {
+        let use_album = match options.mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => false,
+            ReplayGainMode::Album => true,
+            ReplayGainMode::Auto => album,
+        };
+
+        let (gain, peak) = if use_album && self.album_gain.is_some() {
+            (self.album_gain, self.album_peak)
+        } else if self.track_gain.is_some() {
+            (self.track_gain, self.track_peak)
+        } else {
+            (self.album_gain, self.album_peak)
+        };
+
+        let Some(gain) = gain else {
+            return 1.0;
+        };
+
+        let factor = db_to_linear(gain + options.preamp);
+        match peak {
+            // クリップしないようにゲインを抑える
+            Some(peak) if peak > 0.0 && factor * peak > 1.0 => 1.0 / peak,
+            _ => factor,
+        }
+    }
+}
+
+/// Writes every value that is set into the primary tag of the file at
+/// `path`, creating the tag when the file has none.
+pub fn write_tags<P: AsRef<Path>>(path: P, gain: &ReplayGain) -> Result<()> {
+    let path = path.as_ref();
+    let mut file = lofty::read_from_path(path)?;
+
+    if file.primary_tag().is_none() {
+        file.insert_tag(Tag::new(file.primary_tag_type()));
+    }
+    let Some(tag) = file.primary_tag_mut() else {
+        return Err(MinauError::TagWrite("no writable tag".into()));
+    };
+
+    let items = [
+        (ItemKey::ReplayGainTrackGain, gain.track_gain.map(format_db)),
+        (
+            ItemKey::ReplayGainTrackPeak,
+            gain.track_peak.map(format_peak),
+        ),
+        (ItemKey::ReplayGainAlbumGain, gain.album_gain.map(format_db)),
+        (
+            ItemKey::ReplayGainAlbumPeak,
+            gain.album_peak.map(format_peak),
+        ),
+    ];
+    for (key, value) in items {
+        let Some(value) = value else {
+            continue;
+        };
+        if !tag.insert_text(key.clone(), value) {
+            return Err(MinauError::TagWrite(format!(
+                "{:?} is not supported by {:?} tags",
+                key,
+                tag.tag_type()
+            )));
+        }
+    }
+
+    file.save_to_path(path, WriteOptions::default())?;
+    Ok(())
+}
+
+fn format_db(gain: f32) -> String {
+    format!("{:+.2} dB", gain)
+}
+
+fn format_peak(peak: f32) -> String {
+    format!("{:.6}", peak)
+}
+
+pub fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn find(tag: &Tag, key: ItemKey) -> Option<&str> {
+    if let Some(value) = tag.get_string(&key) {
+        return Some(value);
+    }
+
+    // 大文字の MP4 freeform など lofty が対応付けないキー
+    let name = match key {
+        ItemKey::ReplayGainTrackGain => "replaygain_track_gain",
+        ItemKey::ReplayGainTrackPeak => "replaygain_track_peak",
+        ItemKey::ReplayGainAlbumGain => "replaygain_album_gain",
+        ItemKey::ReplayGainAlbumPeak => "replaygain_album_peak",
+        _ => return None,
+    };
+    tag.items().find_map(|item| match item.key() {
+        ItemKey::Unknown(unknown) if unknown.to_ascii_lowercase().ends_with(name) => {
+            item.value().text()
+        }
+        _ => None,
+    })
+}
+
+/// Parses a gain such as `-6.48 dB`.
+fn parse_db(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .or_else(|| value.strip_suffix("DB"))
+        .unwrap_or(value);
+    value
+        .trim()
+        .parse()
+        .ok()
+        .filter(|gain: &f32| gain.is_finite())
+}
+
+fn parse_peak(value: &str) -> Option<f32> {
+    value
+        .trim()
+        .parse()
+        .ok()
+        .filter(|peak: &f32| peak.is_finite() && *peak >= 0.0)
+}
+
+impl FromStr for ReplayGainMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ReplayGainMode::Off),
+            "track" => Ok(ReplayGainMode::Track),
+            "album" => Ok(ReplayGainMode::Album),
+            "auto" => Ok(ReplayGainMode::Auto),
+            _ => Err(format!(
+                "unknown ReplayGain mode '{}' (expected off, track, album or auto)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReplayGainMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayGainMode::Off => write!(f, "off"),
+            ReplayGainMode::Track => write!(f, "track"),
+            ReplayGainMode::Album => write!(f, "album"),
+            ReplayGainMode::Auto => write!(f, "auto"),
+        }
+    }
+}
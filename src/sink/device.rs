@@ -0,0 +1,525 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    Device, FromSample, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig,
+    SupportedBufferSize, SupportedStreamConfig,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::{AudioSink, RenderFn, ResampleMode};
+use crate::err;
+use crate::error::{MinauError, Result};
+
+/// Frames the render buffer holds when the device does not say how large
+/// its periods get. Longer periods are rendered in several pieces.
+const DEFAULT_PERIOD_FRAMES: u32 = 8192;
+
+/// Which cpal host and output device to use. `None` picks the default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceSpec {
+    /// Host (audio API) name, e.g. `ALSA` or `WASAPI`
+    pub host: Option<String>,
+    /// Device name, a unique part of it, or its index from `minau devices`
+    pub device: Option<String>,
+}
+
+/// An output device and the stream configurations it supports.
+pub struct DeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub is_default: bool,
+    pub configs: Vec<ConfigRange>,
+}
+
+/// One supported configuration range of a device.
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_rate: u32,
+    pub max_rate: u32,
+    pub format: String,
+}
+
+/// Output devices of one host.
+pub struct HostInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub devices: Vec<DeviceInfo>,
+}
+
+/// Lists the output devices of every available host, or only of `host`.
+pub fn list_devices(host: Option<&str>) -> Result<Vec<HostInfo>> {
+    let default_host = cpal::default_host().id();
+    let ids = match host {
+        Some(name) => vec![find_host_id(name)?],
+        None => cpal::available_hosts(),
+    };
+
+    let mut hosts = Vec::new();
+    for id in ids {
+        let host = cpal::host_from_id(id).map_err(|e| MinauError::Device(e.to_string()))?;
+        let default_name = host.default_output_device().and_then(|d| d.name().ok());
+        let devices = host
+            .output_devices()
+            .map_err(|e| MinauError::Device(e.to_string()))?
+            .enumerate()
+            .map(|(index, device)| {
+                let name = device.name().unwrap_or_else(|_| "(unknown)".into());
+                let configs = device
+                    .supported_output_configs()
+                    .map(|configs| {
+                        configs
+                            .map(|c| ConfigRange {
+                                channels: c.channels(),
+                                min_rate: c.min_sample_rate().0,
+                                max_rate: c.max_sample_rate().0,
+                                format: c.sample_format().to_string(),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                DeviceInfo {
+                    index,
+                    is_default: default_name.as_deref() == Some(name.as_str()),
+                    name,
+                    configs,
+                }
+            })
+            .collect();
+
+        hosts.push(HostInfo {
+            name: id.name().to_string(),
+            is_default: id == default_host,
+            devices,
+        });
+    }
+    Ok(hosts)
+}
+
+fn find_host_id(name: &str) -> Result<cpal::HostId> {
+    cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = cpal::available_hosts().iter().map(|id| id.name()).collect();
+            MinauError::Device(format!(
+                "Unknown host '{}' (available: {})",
+                name,
+                names.join(", ")
+            ))
+        })
+}
+
+/// Finds a device by index, exact name, or a part of the name that only one
+/// device has.
+fn find_device(host: &cpal::Host, query: &str) -> Result<Device> {
+    let devices: Vec<(String, Device)> = host
+        .output_devices()
+        .map_err(|e| MinauError::Device(e.to_string()))?
+        .map(|d| (d.name().unwrap_or_default(), d))
+        .collect();
+    let not_found = || MinauError::Device(format!("No output device matches '{}'", query));
+
+    if let Ok(index) = query.parse::<usize>() {
+        return devices
+            .into_iter()
+            .nth(index)
+            .map(|(_, d)| d)
+            .ok_or_else(not_found);
+    }
+    if let Some(pos) = devices.iter().position(|(name, _)| name == query) {
+        return Ok(devices.into_iter().nth(pos).map(|(_, d)| d).unwrap());
+    }
+
+    let query_lower = query.to_lowercase();
+    let mut matches: Vec<(String, Device)> = devices
+        .into_iter()
+        .filter(|(name, _)| name.to_lowercase().contains(&query_lower))
+        .collect();
+    match matches.len() {
+        0 => Err(not_found()),
+        1 => Ok(matches.remove(0).1),
+        _ => {
+            let names: Vec<String> = matches.into_iter().map(|(name, _)| name).collect();
+            Err(MinauError::Device(format!(
+                "'{}' matches several devices: {}",
+                query,
+                names.join(", ")
+            )))
+        }
+    }
+}
+
+/// Plays samples on an output device through cpal.
+///
+/// cpal streams cannot move between threads on every platform, so the
+/// device is opened and its stream kept on a thread of its own that lives
+/// as long as the sink.
+pub struct CpalSink {
+    config: StreamConfig,
+    timing: Arc<Timing>,
+    /// Hands the render callback to the stream thread; dropping it ends
+    /// the thread
+    render_tx: Option<mpsc::Sender<RenderFn>>,
+    started_rx: mpsc::Receiver<Result<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CpalSink {
+    /// Opens the device. `preferred` is the `(sample_rate, channels)` of the
+    /// first track, which the device is opened at when it supports it and
+    /// `resample` allows.
+    pub fn open(spec: &DeviceSpec, preferred: (u32, u16), resample: ResampleMode) -> Result<Self> {
+        let (opened_tx, opened_rx) = mpsc::channel();
+        let (render_tx, render_rx) = mpsc::channel::<RenderFn>();
+        let (started_tx, started_rx) = mpsc::channel();
+        let timing = Arc::new(Timing::new());
+
+        let spec = spec.clone();
+        let stream_timing = Arc::clone(&timing);
+        let thread = std::thread::spawn(move || {
+            let (device, config, format, period) = match open_device(&spec, preferred, resample) {
+                Ok((device, config)) => {
+                    let format = config.sample_format();
+                    let period = match config.buffer_size() {
+                        SupportedBufferSize::Range { max, .. } => {
+                            (*max).min(config.sample_rate().0)
+                        }
+                        SupportedBufferSize::Unknown => DEFAULT_PERIOD_FRAMES,
+                    };
+                    let config = StreamConfig {
+                        channels: config.channels(),
+                        sample_rate: config.sample_rate(),
+                        buffer_size: cpal::BufferSize::Default,
+                    };
+                    let _ = opened_tx.send(Ok(config.clone()));
+                    (device, config, format, period)
+                }
+                Err(e) => {
+                    let _ = opened_tx.send(Err(e));
+                    return;
+                }
+            };
+
+            // ストリームはこのスレッドで作って持ち続ける
+            let mut _stream = None;
+            while let Ok(render) = render_rx.recv() {
+                let output = Output {
+                    config: &config,
+                    period,
+                    timing: &stream_timing,
+                };
+                match start_stream(&device, &output, format, render) {
+                    Ok(stream) => {
+                        _stream = Some(stream);
+                        let _ = started_tx.send(Ok(()));
+                    }
+                    Err(e) => {
+                        let _ = started_tx.send(Err(e));
+                    }
+                }
+            }
+        });
+
+        let config = opened_rx
+            .recv()
+            .map_err(|_| MinauError::Device("The output thread stopped".into()))??;
+        Ok(Self {
+            config,
+            timing,
+            render_tx: Some(render_tx),
+            started_rx,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Finds the device of `spec` and the config to open it with.
+fn open_device(
+    spec: &DeviceSpec,
+    preferred: (u32, u16),
+    resample: ResampleMode,
+) -> Result<(Device, SupportedStreamConfig)> {
+    let host = match spec.host.as_deref() {
+        Some(name) => cpal::host_from_id(find_host_id(name)?)
+            .map_err(|e| MinauError::Device(e.to_string()))?,
+        None => cpal::default_host(),
+    };
+    let device = match spec.device.as_deref() {
+        Some(query) => find_device(&host, query)?,
+        None => host
+            .default_output_device()
+            .ok_or_else(|| MinauError::Device("No output device available".into()))?,
+    };
+    let device_config = device
+        .default_output_config()
+        .map_err(|e| MinauError::Device(e.to_string()))?;
+
+    let (sample_rate, _) = preferred;
+    let device_config = match resample {
+        ResampleMode::Always => device_config,
+        _ if device_config.sample_rate().0 == sample_rate => device_config,
+        mode => match matching_config(&device, &device_config, sample_rate) {
+            Some(config) => config,
+            None if mode == ResampleMode::Never => {
+                return Err(MinauError::Device(format!(
+                    "The device does not support {} Hz (--resample never)",
+                    sample_rate
+                )));
+            }
+            None => device_config,
+        },
+    };
+    Ok((device, device_config))
+}
+
+/// When the last callback ran and how long its audio took to play out,
+/// packed into one atomic so neither side of the stream ever waits.
+struct Timing {
+    start: Instant,
+    /// Milliseconds from `start` to the callback in the high half (wrapping),
+    /// the delay in microseconds in the low half
+    packed: AtomicU64,
+}
+
+impl Timing {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            packed: AtomicU64::new(0),
+        }
+    }
+
+    fn millis(&self) -> u32 {
+        self.start.elapsed().as_millis() as u32
+    }
+
+    fn store(&self, delay: Duration) {
+        let delay = delay.as_micros().min(u32::MAX as u128) as u64;
+        let packed = (self.millis() as u64) << 32 | delay;
+        self.packed.store(packed, Ordering::Relaxed);
+    }
+
+    /// The delay stored last, less the time since it was stored.
+    fn delay(&self) -> Duration {
+        let packed = self.packed.load(Ordering::Relaxed);
+        let delay = Duration::from_micros(packed & u32::MAX as u64);
+        let elapsed = self.millis().wrapping_sub((packed >> 32) as u32);
+        delay.saturating_sub(Duration::from_millis(elapsed as u64))
+    }
+}
+
+/// The stream to build, as opened by [`CpalSink::open`].
+struct Output<'a> {
+    config: &'a StreamConfig,
+    /// Longest period the device asks for, in frames
+    period: u32,
+    timing: &'a Arc<Timing>,
+}
+
+/// Builds and plays a stream of `format` that pulls from `render`.
+fn start_stream(
+    device: &Device,
+    output: &Output,
+    format: SampleFormat,
+    render: RenderFn,
+) -> Result<Stream> {
+    let stream = match format {
+        SampleFormat::F32 => build::<f32>(device, output, render)?,
+        SampleFormat::F64 => build::<f64>(device, output, render)?,
+        SampleFormat::I8 => build::<i8>(device, output, render)?,
+        SampleFormat::I16 => build::<i16>(device, output, render)?,
+        SampleFormat::I32 => build::<i32>(device, output, render)?,
+        SampleFormat::I64 => build::<i64>(device, output, render)?,
+        SampleFormat::U8 => build::<u8>(device, output, render)?,
+        SampleFormat::U16 => build::<u16>(device, output, render)?,
+        SampleFormat::U32 => build::<u32>(device, output, render)?,
+        SampleFormat::U64 => build::<u64>(device, output, render)?,
+        format => {
+            return Err(MinauError::Device(format!(
+                "Unsupported sample format {}",
+                format
+            )));
+        }
+    };
+
+    stream
+        .play()
+        .map_err(|e| MinauError::Device(format!("Failed to play stream: {}", e)))?;
+    Ok(stream)
+}
+
+fn build<T>(device: &Device, output: &Output, mut render: RenderFn) -> Result<Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let config = output.config;
+    let mut dither = Dither::new(T::FORMAT);
+    // コールバック内で確保しないよう、最大の周期分を先に用意しておく
+    let mut buffer = vec![0.0f32; output.period.max(1) as usize * config.channels as usize];
+    let timing = Arc::clone(output.timing);
+    let samples_per_sec = config.sample_rate.0 as f64 * config.channels as f64;
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+                for piece in data.chunks_mut(buffer.len()) {
+                    let buffer = &mut buffer[..piece.len()];
+                    render(buffer);
+                    for (out, &sample) in piece.iter_mut().zip(buffer.iter()) {
+                        *out = T::from_sample(dither.apply(sample));
+                    }
+                }
+
+                // 先頭が鳴るまでの遅延にこのバッファ自体の長さを足す
+                let stamp = info.timestamp();
+                let latency = stamp
+                    .playback
+                    .duration_since(&stamp.callback)
+                    .unwrap_or_default();
+                let buffered = Duration::from_secs_f64(data.len() as f64 / samples_per_sec);
+                timing.store(latency + buffered);
+            },
+            move |err| {
+                err!("Stream error: {}", err);
+            },
+            None,
+        )
+        .map_err(|e| MinauError::Device(format!("Failed to build output stream: {}", e)))
+}
+
+/// Finds a supported config running at `sample_rate`, preferring the
+/// channel count and sample format of the default config.
+fn matching_config(
+    device: &Device,
+    default: &SupportedStreamConfig,
+    sample_rate: u32,
+) -> Option<SupportedStreamConfig> {
+    let rate = SampleRate(sample_rate);
+    let mut ranges: Vec<_> = device
+        .supported_output_configs()
+        .ok()?
+        .filter(|range| range.min_sample_rate() <= rate && rate <= range.max_sample_rate())
+        .collect();
+    ranges.sort_by_key(|range| {
+        (
+            range.channels() != default.channels(),
+            range.sample_format() != default.sample_format(),
+            format_rank(range.sample_format()),
+        )
+    });
+    ranges
+        .into_iter()
+        .next()
+        .map(|range| range.with_sample_rate(rate))
+}
+
+/// Lower is better: float first, then wider integers.
+fn format_rank(format: SampleFormat) -> u8 {
+    match format {
+        SampleFormat::F32 => 0,
+        SampleFormat::F64 => 1,
+        SampleFormat::I32 | SampleFormat::U32 => 2,
+        SampleFormat::I64 | SampleFormat::U64 => 3,
+        SampleFormat::I16 | SampleFormat::U16 => 4,
+        _ => 5,
+    }
+}
+
+/// TPDF dither added before samples are quantized to an integer format.
+struct Dither {
+    /// Size of one step of the output format, 0 for formats that need no dither
+    lsb: f32,
+    state: u32,
+}
+
+impl Dither {
+    fn new(format: SampleFormat) -> Self {
+        let bits = match format {
+            SampleFormat::I8 | SampleFormat::U8 => 8,
+            SampleFormat::I16 | SampleFormat::U16 => 16,
+            _ => 0,
+        };
+        Self {
+            lsb: if bits > 0 {
+                1.0 / (1u32 << (bits - 1)) as f32
+            } else {
+                0.0
+            },
+            state: 0x9E37_79B9,
+        }
+    }
+
+    fn apply(&mut self, sample: f32) -> f32 {
+        if self.lsb == 0.0 {
+            return sample;
+        }
+        // 一様乱数 2 つの差で三角分布のノイズを作る
+        let noise = (self.next() - self.next()) * self.lsb;
+        (sample + noise).clamp(-1.0, 1.0)
+    }
+
+    /// Uniform value in `[0, 1)` from a xorshift generator.
+    fn next(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+impl AudioSink for CpalSink {
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate.0
+    }
+
+    fn channels(&self) -> u16 {
+        self.config.channels
+    }
+
+    fn delay(&self) -> Duration {
+        self.timing.delay()
+    }
+
+    fn start(&mut self, render: RenderFn) -> Result<()> {
+        let stopped = || MinauError::Device("The output thread stopped".into());
+        self.render_tx
+            .as_ref()
+            .ok_or_else(stopped)?
+            .send(render)
+            .map_err(|_| stopped())?;
+        self.started_rx.recv().map_err(|_| stopped())?
+    }
+}
+
+impl Drop for CpalSink {
+    fn drop(&mut self) {
+        // 送信側を閉じるとスレッドがストリームを捨てて終わる
+        self.render_tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_counts_down_from_last_callback() {
+        let timing = Timing::new();
+        assert_eq!(timing.delay(), Duration::ZERO);
+
+        timing.store(Duration::from_millis(300));
+        let delay = timing.delay();
+        assert!(delay <= Duration::from_millis(300));
+        assert!(delay > Duration::from_millis(200), "{:?}", delay);
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(timing.delay() < delay);
+        timing.store(Duration::from_secs(7200));
+        assert!(timing.delay() > Duration::from_secs(3599));
+    }
+}
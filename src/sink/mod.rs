@@ -0,0 +1,165 @@
+//! Audio outputs the playback engine can render into.
+//!
+//! The engine hands every sink the same render callback; sinks only decide
+//! where the samples go and how fast they are pulled.
+mod device;
+mod null;
+mod wav;
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub use device::{ConfigRange, CpalSink, DeviceInfo, DeviceSpec, HostInfo, list_devices};
+pub use null::NullSink;
+pub use wav::WavFileSink;
+
+use crate::error::Result;
+
+/// Fills an interleaved buffer with the next samples and returns how many
+/// of them came from the source. The rest of the buffer is set to silence.
+pub type RenderFn = Box<dyn FnMut(&mut [f32]) -> usize + Send + 'static>;
+
+/// An output the engine renders into. Sinks are `Send` so the engine that
+/// owns one can move between threads.
+pub trait AudioSink: Send {
+    /// Output sample rate in Hz.
+    fn sample_rate(&self) -> u32;
+
+    /// Output channel count.
+    fn channels(&self) -> u16;
+
+    /// Starts pulling samples from `render` until the sink is dropped.
+    fn start(&mut self, render: RenderFn) -> Result<()>;
+
+    /// Time until the last sample taken from `render` becomes audible.
+    fn delay(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Which sink to open, as given to `--output`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SinkSpec {
+    /// An output device, the system's default unless one is selected
+    Cpal(DeviceSpec),
+    /// Discards samples, pulling them in real time or as fast as possible
+    Null { realtime: bool },
+    /// Writes 32-bit float samples to a WAV file
+    Wav(PathBuf),
+}
+
+impl SinkSpec {
+    /// Opens the sink. `preferred` is the `(sample_rate, channels)` of the
+    /// first track, used by sinks that are not bound to a device and by
+    /// devices that support that rate.
+    pub fn open(
+        &self,
+        preferred: (u32, u16),
+        resample: ResampleMode,
+    ) -> Result<Box<dyn AudioSink>> {
+        let (sample_rate, channels) = preferred;
+        Ok(match self {
+            SinkSpec::Cpal(device) => Box::new(CpalSink::open(device, preferred, resample)?),
+            SinkSpec::Null { realtime } => {
+                Box::new(NullSink::new(sample_rate, channels, *realtime))
+            }
+            SinkSpec::Wav(path) => Box::new(WavFileSink::create(path, sample_rate, channels)?),
+        })
+    }
+
+    /// Whether the output can be opened again at another rate between
+    /// tracks. A WAV file keeps the rate of its first track.
+    pub fn can_reopen(&self) -> bool {
+        !matches!(self, SinkSpec::Wav(_))
+    }
+}
+
+/// Whether the device is opened at its default rate or at the rate of the
+/// track it plays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleMode {
+    /// Use the track's rate when the device supports it
+    #[default]
+    Auto,
+    /// Always use the device's default rate
+    Always,
+    /// Reopen the device at the rate of every track instead of resampling,
+    /// failing the tracks it does not support
+    Never,
+}
+
+impl FromStr for ResampleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ResampleMode::Auto),
+            "always" => Ok(ResampleMode::Always),
+            "never" => Ok(ResampleMode::Never),
+            _ => Err(format!(
+                "unknown resample mode '{}' (expected auto, always or never)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ResampleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResampleMode::Auto => write!(f, "auto"),
+            ResampleMode::Always => write!(f, "always"),
+            ResampleMode::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl Default for SinkSpec {
+    fn default() -> Self {
+        SinkSpec::Cpal(DeviceSpec::default())
+    }
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cpal" => Ok(SinkSpec::default()),
+            "null" => Ok(SinkSpec::Null { realtime: true }),
+            "null:fast" => Ok(SinkSpec::Null { realtime: false }),
+            _ => {
+                if let Some(path) = s.strip_prefix("wav:").filter(|p| !p.is_empty()) {
+                    Ok(SinkSpec::Wav(PathBuf::from(path)))
+                } else if let Some(device) = s.strip_prefix("cpal:").filter(|d| !d.is_empty()) {
+                    Ok(SinkSpec::Cpal(DeviceSpec {
+                        host: None,
+                        device: Some(device.to_string()),
+                    }))
+                } else {
+                    Err(format!(
+                        "unknown output '{}' (expected cpal, cpal:<device>, null, null:fast or wav:<path>)",
+                        s
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for SinkSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkSpec::Cpal(DeviceSpec {
+                device: Some(device),
+                ..
+            }) => write!(f, "cpal:{}", device),
+            SinkSpec::Cpal(_) => write!(f, "cpal"),
+            SinkSpec::Null { realtime: true } => write!(f, "null"),
+            SinkSpec::Null { realtime: false } => write!(f, "null:fast"),
+            SinkSpec::Wav(path) => write!(f, "wav:{}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::{AudioSink, RenderFn};
+use crate::error::Result;
+
+const BLOCK_MS: u64 = 10;
+
+/// Pulls samples and throws them away. Useful where no audio device exists.
+pub struct NullSink {
+    sample_rate: u32,
+    channels: u16,
+    realtime: bool,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NullSink {
+    /// With `realtime` unset, samples are consumed as fast as the decoder
+    /// produces them.
+    pub fn new(sample_rate: u32, channels: u16, realtime: bool) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            realtime,
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn start(&mut self, mut render: RenderFn) -> Result<()> {
+        let frames = (self.sample_rate as u64 * BLOCK_MS / 1000).max(1) as usize;
+        let mut buffer = vec![0.0f32; frames * self.channels as usize];
+        let realtime = self.realtime;
+        let stop = Arc::clone(&self.stop);
+
+        self.thread = Some(std::thread::spawn(move || {
+            let block = Duration::from_millis(BLOCK_MS);
+            let mut deadline = Instant::now();
+
+            while !stop.load(Ordering::Relaxed) {
+                let rendered = render(&mut buffer);
+
+                if realtime {
+                    deadline += block;
+                    if let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+                        std::thread::sleep(wait);
+                    }
+                } else if rendered == 0 {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }));
+
+        Ok(())
+    }
+}
+
+impl Drop for NullSink {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
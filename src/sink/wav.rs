@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::{AudioSink, RenderFn};
+use crate::err;
+use crate::error::Result;
+
+const BLOCK_FRAMES: usize = 4096;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const HEADER_LEN: u32 = 58;
+
+/// Writes everything played to a 32-bit float WAV file, as fast as the
+/// decoder produces it.
+///
+/// The header is kept up to date after every block, so the file stays
+/// valid if the process exits without dropping the sink. WAV sizes are
+/// 32-bit; audio past 4 GiB is discarded.
+pub struct WavFileSink {
+    sample_rate: u32,
+    channels: u16,
+    writer: Option<BufWriter<File>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WavFileSink {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_header(&mut writer, sample_rate, channels, 0)?;
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            writer: Some(writer),
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        })
+    }
+}
+
+impl AudioSink for WavFileSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn start(&mut self, mut render: RenderFn) -> Result<()> {
+        let Some(mut writer) = self.writer.take() else {
+            return Ok(());
+        };
+        let (sample_rate, channels) = (self.sample_rate, self.channels);
+        let mut buffer = vec![0.0f32; BLOCK_FRAMES * channels as usize];
+        let stop = Arc::clone(&self.stop);
+
+        self.thread = Some(std::thread::spawn(move || {
+            let block_align = channels as u32 * 4;
+            let max_len = (u32::MAX - (HEADER_LEN - 8)) / block_align * block_align;
+            let mut data_len = 0u32;
+            let mut bytes = Vec::with_capacity(buffer.len() * 4);
+
+            while !stop.load(Ordering::Relaxed) {
+                let rendered = render(&mut buffer);
+                if rendered == 0 {
+                    std::thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+                // 上限に達した後も再生を止めないよう読み捨てる
+                if data_len == max_len {
+                    continue;
+                }
+
+                let len = (rendered as u32 * 4).min(max_len - data_len);
+                bytes.clear();
+                for sample in &buffer[..len as usize / 4] {
+                    bytes.extend_from_slice(&sample.to_le_bytes());
+                }
+                data_len += len;
+
+                // ヘッダを毎回書き直して、途中で終了してもファイルが壊れないようにする
+                let written = writer
+                    .write_all(&bytes)
+                    .and_then(|_| writer.seek(SeekFrom::Start(0)))
+                    .and_then(|_| write_header(&mut writer, sample_rate, channels, data_len))
+                    .and_then(|_| writer.seek(SeekFrom::End(0)))
+                    .and_then(|_| writer.flush());
+                if let Err(e) = written {
+                    err!("Failed to write WAV data: {}", e);
+                    return;
+                }
+                if data_len == max_len {
+                    err!("WAV file reached the 4 GiB size limit; the rest is not written");
+                }
+            }
+        }));
+
+        Ok(())
+    }
+}
+
+impl Drop for WavFileSink {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn write_header<W: Write>(
+    w: &mut W,
+    sample_rate: u32,
+    channels: u16,
+    data_len: u32,
+) -> std::io::Result<()> {
+    let block_align = channels * 4;
+    let frames = data_len / block_align.max(1) as u32;
+
+    w.write_all(b"RIFF")?;
+    w.write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+
+    w.write_all(b"fmt ")?;
+    w.write_all(&18u32.to_le_bytes())?;
+    w.write_all(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes())?;
+    w.write_all(&channels.to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    w.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    w.write_all(&block_align.to_le_bytes())?;
+    w.write_all(&32u16.to_le_bytes())?;
+    w.write_all(&0u16.to_le_bytes())?;
+
+    w.write_all(b"fact")?;
+    w.write_all(&4u32.to_le_bytes())?;
+    w.write_all(&frames.to_le_bytes())?;
+
+    w.write_all(b"data")?;
+    w.write_all(&data_len.to_le_bytes())
+}
@@ -0,0 +1,73 @@
+use std::fs::File;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use symphonia::core::io::{
+    MediaSource, MediaSourceStream, MediaSourceStreamOptions, ReadOnlySource,
+};
+use symphonia::core::probe::Hint;
+
+use crate::error::Result;
+
+const STREAM_BUFFER_LEN: usize = 256 * 1024;
+
+/// Where the encoded bytes of a track come from.
+///
+/// Every variant is decoded by the same pipeline, so seeking, volume and
+/// position reporting behave identically regardless of the source.
+pub enum Source {
+    /// A local file
+    File(PathBuf),
+    /// Standard input
+    Stdin,
+    /// An in-memory buffer
+    Memory(Vec<u8>),
+    /// Any other byte stream, such as an HTTP response body
+    Stream {
+        reader: Box<dyn MediaSource>,
+        hint: Hint,
+        name: String,
+    },
+}
+
+impl Source {
+    /// Human readable name used for display and error messages.
+    pub fn name(&self) -> String {
+        match self {
+            Source::File(path) => path.to_string_lossy().to_string(),
+            Source::Stdin => "-".to_string(),
+            Source::Memory(_) => "<memory>".to_string(),
+            Source::Stream { name, .. } => name.clone(),
+        }
+    }
+
+    pub(crate) fn into_stream(self) -> Result<(MediaSourceStream, Hint)> {
+        let mut hint = Hint::new();
+        let default_len = MediaSourceStreamOptions::default().buffer_len;
+
+        let (source, buffer_len): (Box<dyn MediaSource>, usize) = match self {
+            Source::File(path) => {
+                if let Some(ext) = path.extension() {
+                    hint.with_extension(&ext.to_string_lossy());
+                }
+                (Box::new(File::open(&path)?), default_len)
+            }
+            Source::Stdin => (
+                Box::new(ReadOnlySource::new(std::io::stdin())),
+                STREAM_BUFFER_LEN,
+            ),
+            Source::Memory(data) => (Box::new(Cursor::new(data)), default_len),
+            Source::Stream {
+                reader,
+                hint: stream_hint,
+                ..
+            } => {
+                hint = stream_hint;
+                (reader, STREAM_BUFFER_LEN)
+            }
+        };
+
+        let mss = MediaSourceStream::new(source, MediaSourceStreamOptions { buffer_len });
+        Ok((mss, hint))
+    }
+}
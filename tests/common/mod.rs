@@ -0,0 +1,94 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use minau::engine::EngineOptions;
+use minau::sink::SinkSpec;
+use minau::{PlaybackEngine, Player};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const RATE: u32 = 44100;
+pub const CHANNELS: u16 = 2;
+
+/// A path in the temporary directory that is unique to this test process.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("minau-{}-{}", std::process::id(), name))
+}
+
+/// Value of the left channel at `frame` in files from [`write_ramp`]. The
+/// right channel is its negation.
+pub fn ramp(frame: u32) -> f32 {
+    ((frame % 1000) as i16 * 16) as f32 / 32768.0
+}
+
+/// Writes a stereo 16-bit PCM WAV file of `frames` frames of [`ramp`].
+pub fn write_ramp(path: &Path, frames: u32) {
+    let data_len = frames * CHANNELS as u32 * 2;
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&CHANNELS.to_le_bytes());
+    wav.extend_from_slice(&RATE.to_le_bytes());
+    wav.extend_from_slice(&(RATE * CHANNELS as u32 * 2).to_le_bytes());
+    wav.extend_from_slice(&(CHANNELS * 2).to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for i in 0..frames {
+        let sample = (i % 1000) as i16 * 16;
+        wav.extend_from_slice(&sample.to_le_bytes());
+        wav.extend_from_slice(&(-sample).to_le_bytes());
+    }
+    std::fs::write(path, wav).unwrap();
+}
+
+/// Starts an engine that writes to the WAV file at `output`.
+pub fn wav_engine(output: &Path) -> PlaybackEngine {
+    let options = EngineOptions {
+        output: SinkSpec::Wav(output.to_path_buf()),
+        ..Default::default()
+    };
+    PlaybackEngine::new(&options, (RATE, CHANNELS)).unwrap()
+}
+
+/// Waits up to ten seconds for `done`.
+pub fn wait_for(mut done: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if done() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+/// The samples of a 32-bit float WAV file written by the engine.
+pub fn read_output(path: &Path) -> Vec<f32> {
+    let written = std::fs::read(path).unwrap();
+    written[58..]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+/// Plays `player` to the end and returns the interleaved samples the
+/// engine produced.
+pub fn render(name: &str, player: Player) -> Vec<f32> {
+    let output = temp_path(name);
+    let engine = wav_engine(&output);
+    let id = engine.enqueue(player);
+    assert!(
+        wait_for(|| engine.is_finished(id)),
+        "{} did not finish",
+        name
+    );
+    // 書き込みスレッドを終わらせてから読む
+    drop(engine);
+    let samples = read_output(&output);
+    std::fs::remove_file(&output).unwrap();
+    samples
+}
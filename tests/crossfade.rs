@@ -0,0 +1,54 @@
+//! Crossfading between queued tracks.
+
+mod common;
+
+use common::{CHANNELS, RATE, read_output, temp_path, wait_for, write_ramp};
+use minau::engine::{Crossfade, EngineOptions, FadeCurve};
+use minau::sink::SinkSpec;
+use minau::{PlaybackEngine, Player};
+use std::time::Duration;
+
+const FRAMES: u32 = 30_000;
+const OVERLAP_MS: u64 = 250;
+
+/// Plays `tracks` ramp fixtures of [`FRAMES`] frames with a crossfade and
+/// returns the number of frames written.
+fn crossfaded_frames(name: &str, tracks: usize) -> usize {
+    let fixture = temp_path(&format!("{}.wav", name));
+    let output = temp_path(&format!("{}-out.wav", name));
+    write_ramp(&fixture, FRAMES);
+
+    let options = EngineOptions {
+        output: SinkSpec::Wav(output.clone()),
+        crossfade: Some(Crossfade {
+            duration: Duration::from_millis(OVERLAP_MS),
+            curve: FadeCurve::Linear,
+        }),
+        ..Default::default()
+    };
+    let engine = PlaybackEngine::new(&options, (RATE, CHANNELS)).unwrap();
+    let ids: Vec<_> = (0..tracks)
+        .map(|_| engine.enqueue(Player::new(&fixture).unwrap()))
+        .collect();
+    assert!(wait_for(|| engine.is_finished(*ids.last().unwrap())));
+    drop(engine);
+
+    let frames = read_output(&output).len() / CHANNELS as usize;
+    std::fs::remove_file(&fixture).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    frames
+}
+
+#[test]
+fn plays_out_held_tail_of_last_track() {
+    assert_eq!(crossfaded_frames("fade-last", 1), FRAMES as usize);
+}
+
+#[test]
+fn overlaps_consecutive_tracks() {
+    let overlap = (RATE as u64 * OVERLAP_MS / 1000) as usize;
+    assert_eq!(
+        crossfaded_frames("fade-pair", 3),
+        3 * FRAMES as usize - 2 * overlap
+    );
+}
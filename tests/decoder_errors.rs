@@ -0,0 +1,212 @@
+//! How the engine gets through read and decode errors in the middle of a
+//! track.
+
+mod common;
+
+use common::{ramp, render, temp_path, write_ramp};
+use minau::Player;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use symphonia::core::audio::AudioBufferRef;
+use symphonia::core::codecs::{
+    CodecDescriptor, CodecParameters, Decoder, DecoderOptions, FinalizeResult,
+};
+use symphonia::core::errors::{Error, Result};
+use symphonia::core::formats::{
+    Cue, FormatOptions, FormatReader, Packet, SeekMode, SeekTo, SeekedTo, Track,
+};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::Metadata;
+
+const FRAMES: u32 = 44100;
+/// Frames in each packet symphonia reads from a WAV file
+const PACKET_FRAMES: u64 = 1152;
+
+/// Wraps a format reader, returning `ResetRequired` once before the packet
+/// numbered `reset_before`, and repeating the last packet forever after
+/// the end when `endless` is set.
+struct FaultyFormat {
+    inner: Box<dyn FormatReader>,
+    packets: usize,
+    reset_before: Option<usize>,
+    endless: bool,
+    last: Option<Packet>,
+}
+
+impl FormatReader for FaultyFormat {
+    fn try_new(_: MediaSourceStream, _: &FormatOptions) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn cues(&self) -> &[Cue] {
+        self.inner.cues()
+    }
+
+    fn metadata(&mut self) -> Metadata<'_> {
+        self.inner.metadata()
+    }
+
+    fn seek(&mut self, mode: SeekMode, to: SeekTo) -> Result<SeekedTo> {
+        self.inner.seek(mode, to)
+    }
+
+    fn tracks(&self) -> &[Track] {
+        self.inner.tracks()
+    }
+
+    fn next_packet(&mut self) -> Result<Packet> {
+        if self.reset_before == Some(self.packets) {
+            self.reset_before = None;
+            return Err(Error::ResetRequired);
+        }
+        match self.inner.next_packet() {
+            Ok(packet) => {
+                self.packets += 1;
+                self.last = Some(packet.clone());
+                Ok(packet)
+            }
+            Err(_) if self.endless && self.last.is_some() => Ok(self.last.clone().unwrap()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn into_inner(self: Box<Self>) -> MediaSourceStream {
+        self.inner.into_inner()
+    }
+}
+
+/// Wraps a decoder, failing every packet that starts at or after `fail_from`
+/// or is listed in `fail_at`. The frames it refused are counted in
+/// `dropped`.
+struct FaultyDecoder {
+    inner: Box<dyn Decoder>,
+    fail_at: Vec<u64>,
+    fail_from: u64,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Decoder for FaultyDecoder {
+    fn try_new(_: &CodecParameters, _: &DecoderOptions) -> Result<Self> {
+        unimplemented!()
+    }
+
+    fn supported_codecs() -> &'static [CodecDescriptor] {
+        &[]
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    fn codec_params(&self) -> &CodecParameters {
+        self.inner.codec_params()
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Result<AudioBufferRef<'_>> {
+        if packet.ts() >= self.fail_from || self.fail_at.contains(&packet.ts()) {
+            let frames = self.inner.decode(packet)?.frames();
+            self.dropped.fetch_add(frames as u64, Ordering::Relaxed);
+            return Err(Error::DecodeError("injected"));
+        }
+        self.inner.decode(packet)
+    }
+
+    fn finalize(&mut self) -> FinalizeResult {
+        self.inner.finalize()
+    }
+
+    fn last_decoded(&self) -> AudioBufferRef<'_> {
+        self.inner.last_decoded()
+    }
+}
+
+/// Opens a ramp fixture named `name` with the faults given.
+fn open_faulty(
+    name: &str,
+    format: impl FnOnce(Box<dyn FormatReader>) -> FaultyFormat,
+    decoder: impl FnOnce(Box<dyn Decoder>) -> FaultyDecoder,
+) -> Player {
+    let fixture = temp_path(name);
+    write_ramp(&fixture, FRAMES);
+    let mut player = Player::new(&fixture).unwrap();
+    std::fs::remove_file(&fixture).unwrap();
+
+    let inner = Arc::try_unwrap(player.format).ok().unwrap();
+    player.format = Arc::new(Mutex::new(Box::new(format(inner.into_inner().unwrap()))));
+    let inner = Arc::try_unwrap(player.decoder).ok().unwrap();
+    player.decoder = Arc::new(Mutex::new(Box::new(decoder(inner.into_inner().unwrap()))));
+    player
+}
+
+fn faulty_format(inner: Box<dyn FormatReader>) -> FaultyFormat {
+    FaultyFormat {
+        inner,
+        packets: 0,
+        reset_before: None,
+        endless: false,
+        last: None,
+    }
+}
+
+fn faulty_decoder(inner: Box<dyn Decoder>, dropped: &Arc<AtomicU64>) -> FaultyDecoder {
+    FaultyDecoder {
+        inner,
+        fail_at: Vec::new(),
+        fail_from: u64::MAX,
+        dropped: Arc::clone(dropped),
+    }
+}
+
+#[test]
+fn continues_after_reset_required() {
+    let dropped = Arc::new(AtomicU64::new(0));
+    let player = open_faulty(
+        "reset.wav",
+        |inner| FaultyFormat {
+            reset_before: Some(3),
+            ..faulty_format(inner)
+        },
+        |inner| faulty_decoder(inner, &dropped),
+    );
+
+    let samples = render("reset-out.wav", player);
+    assert_eq!(samples.len(), FRAMES as usize * 2);
+    assert_eq!(samples[samples.len() - 2], ramp(FRAMES - 1));
+}
+
+#[test]
+fn skips_undecodable_packets() {
+    let dropped = Arc::new(AtomicU64::new(0));
+    let player = open_faulty("corrupt.wav", faulty_format, |inner| FaultyDecoder {
+        fail_at: vec![0, 5 * PACKET_FRAMES, 10 * PACKET_FRAMES],
+        ..faulty_decoder(inner, &dropped)
+    });
+
+    let samples = render("corrupt-out.wav", player);
+    assert_eq!(dropped.load(Ordering::Relaxed), 3 * PACKET_FRAMES);
+    assert_eq!(
+        samples.len() as u64,
+        (FRAMES as u64 - 3 * PACKET_FRAMES) * 2
+    );
+}
+
+#[test]
+fn gives_up_after_repeated_decode_errors() {
+    let dropped = Arc::new(AtomicU64::new(0));
+    // 最後のパケットが壊れたまま延々と続く
+    let player = open_faulty(
+        "endless.wav",
+        |inner| FaultyFormat {
+            endless: true,
+            ..faulty_format(inner)
+        },
+        |inner| FaultyDecoder {
+            fail_from: FRAMES as u64 / 2,
+            ..faulty_decoder(inner, &dropped)
+        },
+    );
+
+    let samples = render("endless-out.wav", player);
+    assert!(samples.len() / 2 < FRAMES as usize);
+    assert!(!samples.is_empty());
+}
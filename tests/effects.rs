@@ -0,0 +1,38 @@
+//! Volume and DSP settings changed through the engine handle.
+
+mod common;
+
+use common::{ramp, read_output, temp_path, wait_for, wav_engine, write_ramp};
+use minau::Player;
+use minau::dsp::DspSettings;
+
+#[test]
+fn applies_volume_and_dsp_changes() {
+    let fixture = temp_path("effects.wav");
+    let output = temp_path("effects-out.wav");
+    write_ramp(&fixture, 20_000);
+
+    let engine = wav_engine(&output);
+    engine.set_volume_mut(0.5);
+    engine.set_dsp(DspSettings {
+        preamp: 20.0 * 0.5f32.log10(),
+        ..Default::default()
+    });
+    assert_eq!(engine.get_volume(), 0.5);
+    assert_eq!(engine.dsp().preamp, 20.0 * 0.5f32.log10());
+
+    let id = engine.enqueue(Player::new(&fixture).unwrap());
+    assert!(wait_for(|| engine.is_finished(id)));
+    drop(engine);
+
+    let samples = read_output(&output);
+    std::fs::remove_file(&fixture).unwrap();
+    std::fs::remove_file(&output).unwrap();
+
+    assert_eq!(samples.len(), 40_000);
+    for frame in [1, 500, 999, 12_345] {
+        let expected = ramp(frame) * 0.25;
+        assert!((samples[2 * frame as usize] - expected).abs() < 1e-5);
+        assert!((samples[2 * frame as usize + 1] + expected).abs() < 1e-5);
+    }
+}
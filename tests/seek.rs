@@ -0,0 +1,100 @@
+//! Seeking tracks whose audio does not start at the start of the file.
+
+mod common;
+
+use common::{RATE, ramp, read_output, temp_path, wait_for, wav_engine, write_ramp};
+use minau::Player;
+use minau::player::player_structs::GaplessInfo;
+use std::path::Path;
+use std::time::Duration;
+
+const FRAMES: u32 = 100_000;
+/// Encoder delay given to the fixture, not a multiple of the ramp period
+const DELAY: u64 = 1500;
+const PADDING: u64 = 700;
+
+fn gapless_fixture(path: &Path) -> Player {
+    write_ramp(path, FRAMES);
+    let mut player = Player::new(path).unwrap();
+    player.gapless = Some(GaplessInfo {
+        delay: DELAY,
+        frames: Some(FRAMES as u64 - DELAY - PADDING),
+    });
+    player
+}
+
+/// Queues `player` paused, seeks it to `to` once some of it is decoded,
+/// then plays it to the end. Only what was decoded after the seek reaches
+/// the output.
+fn seek_then_play(name: &str, player: Player, to: Duration) -> Vec<f32> {
+    let output = temp_path(name);
+    let engine = wav_engine(&output);
+    engine.pause();
+    let id = engine.enqueue(player);
+
+    assert!(wait_for(|| engine.is_seekable()));
+    // シーク前にデコーダーが先読みするのを待つ
+    std::thread::sleep(Duration::from_millis(200));
+    engine.seek(to).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    engine.resume();
+
+    assert!(
+        wait_for(|| engine.is_finished(id)),
+        "{} did not finish",
+        name
+    );
+    drop(engine);
+    let samples = read_output(&output);
+    std::fs::remove_file(&output).unwrap();
+    samples
+}
+
+/// Checks that `samples` hold the ramp from source frame `first` on.
+fn assert_ramp_from(samples: &[f32], first: u64, frames: u64) {
+    assert_eq!(samples.len() as u64, frames * 2);
+    assert_eq!(samples[0], ramp(first as u32));
+    assert_eq!(samples[1], -ramp(first as u32));
+    let last = samples.len() - 2;
+    assert_eq!(samples[last], ramp((first + frames - 1) as u32));
+}
+
+#[test]
+fn keeps_delay_trimmed_after_seeking_to_start() {
+    let fixture = temp_path("seek-start.wav");
+    let player = gapless_fixture(&fixture);
+    let samples = seek_then_play("seek-start-out.wav", player, Duration::ZERO);
+    std::fs::remove_file(&fixture).unwrap();
+
+    assert_ramp_from(&samples, DELAY, FRAMES as u64 - DELAY - PADDING);
+}
+
+#[test]
+fn seeks_past_delay() {
+    let fixture = temp_path("seek-mid.wav");
+    let player = gapless_fixture(&fixture);
+    let samples = seek_then_play("seek-mid-out.wav", player, Duration::from_secs(1));
+    std::fs::remove_file(&fixture).unwrap();
+
+    let skipped = RATE as u64;
+    assert_ramp_from(
+        &samples,
+        DELAY + skipped,
+        FRAMES as u64 - DELAY - PADDING - skipped,
+    );
+}
+
+#[test]
+fn seeks_back_within_clip() {
+    let fixture = temp_path("seek-clip.wav");
+    write_ramp(&fixture, FRAMES);
+    let mut player = Player::new(&fixture).unwrap();
+    player.set_clip(
+        Duration::from_millis(500),
+        Some(Duration::from_millis(1500)),
+    );
+    let samples = seek_then_play("seek-clip-out.wav", player, Duration::ZERO);
+    std::fs::remove_file(&fixture).unwrap();
+
+    assert_ramp_from(&samples, RATE as u64 / 2, RATE as u64);
+}
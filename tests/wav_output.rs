@@ -0,0 +1,61 @@
+//! Plays a file through the engine into a WAV file and checks what was
+//! written.
+
+mod common;
+
+use common::{CHANNELS, RATE, ramp, temp_path, wait_for, wav_engine, write_ramp};
+use minau::Player;
+
+/// Deliberately not a multiple of the sink's block size
+const FRAMES: u32 = 54321;
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+#[test]
+fn writes_header_while_playing() {
+    let fixture = temp_path("fixture.wav");
+    let output = temp_path("output.wav");
+    write_ramp(&fixture, FRAMES);
+
+    let engine = wav_engine(&output);
+    let id = engine.enqueue(Player::new(&fixture).unwrap());
+
+    let expected_len = FRAMES * CHANNELS as u32 * 4;
+    let mut written = Vec::new();
+    wait_for(|| {
+        written = std::fs::read(&output).unwrap();
+        engine.is_finished(id) && written.len() >= 58 && u32_at(&written, 54) == expected_len
+    });
+
+    // エンジンを閉じる前からヘッダが正しいこと
+    assert_eq!(&written[0..4], b"RIFF");
+    assert_eq!(u32_at(&written, 4), 50 + expected_len);
+    assert_eq!(&written[8..16], b"WAVEfmt ");
+    assert_eq!(u16_at(&written, 20), 3, "IEEE float");
+    assert_eq!(u16_at(&written, 22), CHANNELS);
+    assert_eq!(u32_at(&written, 24), RATE);
+    assert_eq!(u16_at(&written, 34), 32);
+    assert_eq!(&written[38..42], b"fact");
+    assert_eq!(u32_at(&written, 46), FRAMES);
+    assert_eq!(&written[50..54], b"data");
+    assert_eq!(u32_at(&written, 54), expected_len);
+    assert_eq!(written.len(), 58 + expected_len as usize);
+
+    let samples: Vec<f32> = written[58..]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+    assert_eq!(samples[2 * 999], ramp(999));
+    assert_eq!(samples[2 * 999 + 1], -ramp(999));
+
+    drop(engine);
+    assert_eq!(std::fs::read(&output).unwrap(), written);
+    std::fs::remove_file(&fixture).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}